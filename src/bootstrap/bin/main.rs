@@ -6,6 +6,8 @@
 //! directory in each respective module.
 
 use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
 
 use bootstrap::{Build, Config, Subcommand, VERSION};
 
@@ -13,13 +15,21 @@ fn main() {
     let args = env::args().skip(1).collect::<Vec<_>>();
     let config = Config::parse(&args);
 
-    // check_version warnings are not printed during setup
-    let changelog_suggestion =
-        if matches!(config.cmd, Subcommand::Setup {..}) { None } else { check_version(&config) };
+    // check_version warnings are not printed during setup, nor around output
+    // meant for scripts
+    let quiet = matches!(
+        config.cmd,
+        Subcommand::Setup { .. }
+            | Subcommand::SetupEditor { .. }
+            | Subcommand::Check { print_stamp_path: true, .. }
+            | Subcommand::Check { print_config: true, .. }
+            | Subcommand::Clippy { print_lint_flags: true, .. }
+    );
+    let changelog_suggestion = if quiet { None } else { check_version(&config) };
 
     // NOTE: Since `./configure` generates a `config.toml`, distro maintainers will see the
     // changelog warning, not the `x.py setup` message.
-    let suggest_setup = !config.config.exists() && !matches!(config.cmd, Subcommand::Setup { .. });
+    let suggest_setup = !config.config.exists() && !quiet;
     if suggest_setup {
         println!("warning: you have not made a `config.toml`");
         println!("help: consider running `x.py setup` or copying `config.toml.example`");
@@ -27,7 +37,22 @@ fn main() {
         println!("{}", suggestion);
     }
 
-    Build::new(config).build();
+    // `x.py check` promises distinct exit codes (see its `--help`): 2 for a
+    // broken setup and 3 if bootstrap itself falls over.
+    let is_check = matches!(config.cmd, Subcommand::Check { .. });
+    if is_check && !config.initial_rustc.exists() {
+        eprintln!(
+            "error: the stage0 rustc is missing, expected it at {}",
+            config.initial_rustc.display()
+        );
+        process::exit(2);
+    }
+    if let Err(err) = panic::catch_unwind(AssertUnwindSafe(|| Build::new(config).build())) {
+        if is_check {
+            process::exit(3);
+        }
+        panic::resume_unwind(err);
+    }
 
     if suggest_setup {
         println!("warning: you have not made a `config.toml`");
@@ -16,13 +16,21 @@
 //! never get replaced.
 
 use std::env;
+use std::fs::File;
 use std::path::PathBuf;
 use std::process::Command;
 use std::str::FromStr;
 use std::time::Instant;
 
 fn main() {
-    let args = env::args_os().skip(1).collect::<Vec<_>>();
+    let mut args = env::args_os().skip(1).collect::<Vec<_>>();
+
+    // Only the crates of the tree report their warnings when checking.
+    if let (Some(src), Some(manifest_dir)) =
+        (env::var_os("RUSTC_CAP_EXTERNAL_LINTS"), env::var_os("CARGO_MANIFEST_DIR"))
+    {
+        args = bootstrap::util::cap_external_lints(&args, manifest_dir.as_ref(), src.as_ref());
+    }
 
     // Detect whether or not we're a build script depending on whether --target
     // is passed (a bit janky...)
@@ -149,6 +157,29 @@ fn main() {
         eprintln!("libdir: {:?}", libdir);
     }
 
+    // The statistics `x.py check --compiler-stats` asks for are printed to
+    // stdout, which is otherwise unused when checking, so keep them in a file
+    // named like the crate's artifacts.
+    if let (Some(dir), Some(crate_name)) = (env::var_os("RUSTC_STATS_DIR"), crate_name) {
+        if target.is_some() {
+            let metadata = args
+                .windows(2)
+                .filter(|args| args[0] == "-C")
+                .find_map(|args| args[1].to_str()?.strip_prefix("metadata="));
+            let is_test = args.iter().any(|a| a == "--test");
+            let name = format!(
+                "{}{}{}.txt",
+                crate_name,
+                metadata.map(|hash| format!("-{}", hash)).unwrap_or_default(),
+                if is_test { "-test" } else { "" }
+            );
+            let path = PathBuf::from(dir).join(name);
+            let file = File::create(&path)
+                .unwrap_or_else(|e| panic!("failed to create {}: {}", path.display(), e));
+            cmd.stdout(file);
+        }
+    }
+
     let start = Instant::now();
     let status = {
         let errmsg = format!("\nFailed to run:\n{:?}\n-------------", cmd);
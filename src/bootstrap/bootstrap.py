@@ -1067,7 +1067,14 @@ def bootstrap(help_triggered):
     env["RUSTC_BOOTSTRAP"] = '1'
     if toml_path:
         env["BOOTSTRAP_CONFIG"] = toml_path
-    run(args, env=env, verbose=build.verbose)
+    if build.verbose:
+        print("running: " + ' '.join(args))
+    sys.stdout.flush()
+    # Pass the exit code on unchanged, `x.py check --help` documents what
+    # the codes mean.
+    code = subprocess.Popen(args, env=env).wait()
+    if code != 0:
+        sys.exit(code)
 
 
 def main():
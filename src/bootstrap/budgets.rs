@@ -0,0 +1,173 @@
+//! Implementation of the check budgets in `src/bootstrap/check-budgets.toml`.
+//!
+//! `x.py check` has Cargo report how long checking each library crate took,
+//! and notes how large its metadata came out. Each run's numbers are written
+//! to `build/check-timings/` for looking at trends, which keeps the last
+//! `KEPT_RUNS` runs. The crates that went over the budgets set for them by
+//! more than the tolerance are warned about, or fail the check with
+//! `--deny-budget-overruns`. Crates without a budget are only recorded.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::time::SystemTime;
+
+use build_helper::t;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{Build, Subcommand};
+
+/// How long checking a library crate for a target took, and how large the
+/// metadata it produced is, if Cargo said where it is.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CrateTiming {
+    pub name: String,
+    pub target: String,
+    pub seconds: f64,
+    pub metadata_bytes: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Budgets {
+    /// How far, in percent, a crate may go over its budget unreported.
+    #[serde(default)]
+    tolerance_percent: f64,
+    #[serde(default)]
+    crates: BTreeMap<String, Budget>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Budget {
+    seconds: Option<f64>,
+    metadata_mib: Option<f64>,
+}
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+/// How many runs `build/check-timings/` keeps the timings of, so that checking
+/// on every save in an editor doesn't fill the build directory.
+const KEPT_RUNS: usize = 50;
+
+/// Whether the check steps ask Cargo for the timings of the crates.
+pub fn enabled(build: &Build) -> bool {
+    matches!(build.config.cmd, Subcommand::Check { .. }) && !build.config.dry_run
+}
+
+/// Records the timings collected while checking, and reports the crates that
+/// went over their budgets.
+pub fn finish(build: &Build) {
+    let timings = build.check_timings.borrow();
+    if !enabled(build) || timings.is_empty() {
+        return;
+    }
+    record(build, &timings);
+
+    let path = build.src.join("src/bootstrap/check-budgets.toml");
+    let budgets = load(&path).unwrap_or_else(|err| {
+        eprintln!("error: failed to read {}: {}", path.display(), err);
+        process::exit(2);
+    });
+    let overruns = overruns(&budgets, &timings);
+    for overrun in &overruns {
+        println!("warning: {}", overrun);
+    }
+    if !overruns.is_empty()
+        && matches!(build.config.cmd, Subcommand::Check { deny_budget_overruns: true, .. })
+    {
+        eprintln!(
+            "error: {} crate(s) went over their check budgets\n\
+             help: speed them up, or raise their budgets in {}",
+            overruns.len(),
+            path.display()
+        );
+        process::exit(1);
+    }
+}
+
+/// Writes `timings` to a file of its own in `build/check-timings/`, named
+/// after the time of the run, and removes those of older runs past
+/// `KEPT_RUNS`.
+fn record(build: &Build, timings: &[CrateTiming]) {
+    let dir = build.out.join("check-timings");
+    t!(fs::create_dir_all(&dir));
+    let since_epoch = t!(SystemTime::UNIX_EPOCH.elapsed()).as_secs();
+    let run = json!({
+        "timestamp": since_epoch,
+        "commit": build.rust_info.sha(),
+        "crates": timings,
+    });
+    let path = dir.join(format!("{}-{}.json", since_epoch, process::id()));
+    t!(fs::write(&path, t!(serde_json::to_string_pretty(&run))));
+    build.verbose(&format!("wrote the check timings to {}", path.display()));
+    prune(&dir, KEPT_RUNS);
+}
+
+/// Removes the timings of all but the `keep` latest runs in `dir`, going by
+/// the time and process id their names start with.
+fn prune(dir: &Path, keep: usize) {
+    let mut runs = t!(fs::read_dir(dir))
+        .filter_map(|entry| {
+            let path = t!(entry).path();
+            let stem = path.file_stem()?.to_str()?;
+            let mut parts = stem.splitn(2, '-');
+            let secs = parts.next()?.parse::<u64>().ok()?;
+            let pid = parts.next()?.parse::<u32>().ok()?;
+            Some(((secs, pid), path))
+        })
+        .collect::<Vec<_>>();
+    if runs.len() <= keep {
+        return;
+    }
+    runs.sort();
+    for (_, path) in &runs[..runs.len() - keep] {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Reads the budgets at `path`, of which there are none if it doesn't exist.
+fn load(path: &Path) -> Result<Budgets, String> {
+    match fs::read_to_string(path) {
+        Ok(contents) => toml::from_str(&contents).map_err(|e| e.to_string()),
+        Err(_) if !path.exists() => Ok(Budgets::default()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Describes each budget `timings` goes over by more than the tolerance.
+fn overruns(budgets: &Budgets, timings: &[CrateTiming]) -> Vec<String> {
+    let allowed = |budget: f64| budget * (1.0 + budgets.tolerance_percent / 100.0);
+    let mut overruns = Vec::new();
+    for timing in timings {
+        let budget = match budgets.crates.get(&timing.name) {
+            Some(budget) => budget,
+            None => continue,
+        };
+        if let Some(seconds) = budget.seconds {
+            if timing.seconds > allowed(seconds) {
+                overruns.push(format!(
+                    "checking {} for {} took {:.1}s, over its budget of {}s",
+                    timing.name, timing.target, timing.seconds, seconds
+                ));
+            }
+        }
+        if let (Some(mib), Some(bytes)) = (budget.metadata_mib, timing.metadata_bytes) {
+            if bytes as f64 > allowed(mib) * MIB {
+                overruns.push(format!(
+                    "the metadata of {} for {} is {:.1} MiB, over its budget of {} MiB",
+                    timing.name,
+                    timing.target,
+                    bytes as f64 / MIB,
+                    mib
+                ));
+            }
+        }
+    }
+    overruns
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,63 @@
+use super::*;
+
+fn timing(name: &str, seconds: f64, metadata_bytes: Option<u64>) -> CrateTiming {
+    CrateTiming {
+        name: name.to_string(),
+        target: "x86_64-unknown-linux-gnu".to_string(),
+        seconds,
+        metadata_bytes,
+    }
+}
+
+#[test]
+fn overruns_past_the_tolerance() {
+    let budgets: Budgets = toml::from_str(
+        r#"
+tolerance-percent = 10
+
+[crates]
+rustc_middle = { seconds = 60, metadata-mib = 50 }
+rustc_parse = { seconds = 10 }
+"#,
+    )
+    .unwrap();
+    let timings = [
+        timing("rustc_middle", 65.0, Some(60 * 1024 * 1024)),
+        timing("rustc_parse", 11.5, None),
+        timing("rustc_span", 300.0, None),
+    ];
+    assert_eq!(
+        overruns(&budgets, &timings),
+        [
+            "the metadata of rustc_middle for x86_64-unknown-linux-gnu is 60.0 MiB, over its \
+             budget of 50 MiB",
+            "checking rustc_parse for x86_64-unknown-linux-gnu took 11.5s, over its budget of 10s",
+        ]
+    );
+}
+
+#[test]
+fn no_budgets_without_a_file() {
+    let budgets = load(Path::new("/nonexistent/check-budgets.toml")).unwrap();
+    assert!(overruns(&budgets, &[timing("rustc_middle", 1000.0, None)]).is_empty());
+    assert!(toml::from_str::<Budgets>("[crates]\nrustc_middle = { minutes = 1 }\n").is_err());
+}
+
+#[test]
+fn only_the_latest_runs_are_kept() {
+    let dir = std::env::temp_dir().join(format!("bootstrap-check-timings-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    t!(fs::create_dir_all(&dir));
+    for name in &["900-7.json", "1000-2.json", "1000-10.json", "80-3.json", "notes.txt"] {
+        t!(fs::write(dir.join(name), "{}"));
+    }
+
+    prune(&dir, 2);
+    let mut left = t!(fs::read_dir(&dir))
+        .map(|entry| t!(entry).file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    left.sort();
+    assert_eq!(left, ["1000-10.json", "1000-2.json", "notes.txt"]);
+
+    t!(fs::remove_dir_all(&dir));
+}
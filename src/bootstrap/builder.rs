@@ -1,14 +1,15 @@
 use std::any::Any;
 use std::cell::{Cell, RefCell};
-use std::collections::BTreeSet;
+use std::cmp;
+use std::collections::{BTreeSet, HashSet};
 use std::env;
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::fmt::Debug;
 use std::fs;
 use std::hash::Hash;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{self, Command};
 use std::time::{Duration, Instant};
 
 use build_helper::{output, t};
@@ -16,7 +17,7 @@ use build_helper::{output, t};
 use crate::cache::{Cache, Interned, INTERNER};
 use crate::check;
 use crate::compile;
-use crate::config::TargetSelection;
+use crate::config::{Config, TargetSelection};
 use crate::dist;
 use crate::doc;
 use crate::flags::Subcommand;
@@ -36,7 +37,14 @@ pub struct Builder<'a> {
     pub kind: Kind,
     cache: Cache,
     stack: RefCell<Vec<Box<dyn Any>>>,
+    // Names of the steps in `stack`, such as `check::Rustc`
+    step_names: RefCell<Vec<&'static str>>,
     time_spent_on_dependencies: Cell<Duration>,
+    // Steps that were decided against along with why, for `-v` to list.
+    skipped: RefCell<Vec<(String, String)>>,
+    // Steps the paths given, or the defaults, selected, as opposed to the ones
+    // only run as their dependencies.
+    requested: RefCell<HashSet<&'static str>>,
     pub paths: Vec<PathBuf>,
 }
 
@@ -141,6 +149,14 @@ impl PathSet {
         }
     }
 
+    /// Whether any of the paths lies within the directory `dir`.
+    fn within(&self, dir: &Path) -> bool {
+        match self {
+            PathSet::Set(set) => set.iter().any(|p| p.starts_with(dir)),
+            PathSet::Suite(suite) => suite.starts_with(dir),
+        }
+    }
+
     fn path(&self, builder: &Builder<'_>) -> PathBuf {
         match self {
             PathSet::Set(set) => set.iter().next().unwrap_or(&builder.build.src).to_path_buf(),
@@ -163,6 +179,8 @@ impl StepDescription {
     fn maybe_run(&self, builder: &Builder<'_>, pathset: &PathSet) {
         if builder.config.exclude.iter().any(|e| pathset.has(e)) {
             eprintln!("Skipping {:?} because it is excluded", pathset);
+            let reason = format!("{} is excluded", pathset.path(builder).display());
+            builder.skip(self.short_name(), &reason);
             return;
         } else if !builder.config.exclude.is_empty() {
             eprintln!(
@@ -171,8 +189,16 @@ impl StepDescription {
             );
         }
 
+        builder.requested.borrow_mut().insert(self.name);
+
         // Determine the targets participating in this rule.
         let targets = if self.only_hosts { &builder.hosts } else { &builder.targets };
+        if self.only_hosts {
+            for target in builder.targets.iter().filter(|target| !builder.hosts.contains(target)) {
+                let step = format!("{} ({})", self.short_name(), target);
+                builder.skip(&step, "only runs for hosts, and this target isn't one");
+            }
+        }
 
         for target in targets {
             let run = RunConfig { builder, path: pathset.path(builder), target: *target };
@@ -180,7 +206,12 @@ impl StepDescription {
         }
     }
 
-    fn run(v: &[StepDescription], builder: &Builder<'_>, paths: &[PathBuf]) {
+    /// The step's name without the crate, such as `check::Rustc`.
+    fn short_name(&self) -> &'static str {
+        self.name.trim_start_matches("bootstrap::")
+    }
+
+    fn run(v: &[StepDescription], builder: &Builder<'_>, paths: &[PathBuf]) -> Result<(), String> {
         let should_runs =
             v.iter().map(|desc| (desc.should_run)(ShouldRun::new(builder))).collect::<Vec<_>>();
 
@@ -193,16 +224,51 @@ impl StepDescription {
             );
         }
 
-        if paths.is_empty() || builder.config.include_default_paths {
+        if let Some(only) = builder.config.cmd.only_default_step() {
             for (desc, should_run) in v.iter().zip(&should_runs) {
-                if desc.default && should_run.is_really_default {
+                if desc.short_name() == only {
                     for pathset in &should_run.paths {
                         desc.maybe_run(builder, pathset);
                     }
+                } else if desc.default {
+                    builder.skip(desc.short_name(), &format!("only {} was asked for", only));
+                }
+            }
+        } else if paths.is_empty() || builder.config.include_default_paths {
+            let default_paths = builder.default_paths();
+            if default_paths.is_empty() {
+                for (desc, should_run) in v.iter().zip(&should_runs) {
+                    if desc.default && should_run.is_really_default {
+                        for pathset in &should_run.paths {
+                            desc.maybe_run(builder, pathset);
+                        }
+                    } else if desc.default {
+                        builder.skip(desc.short_name(), "its default condition doesn't hold");
+                    } else {
+                        builder.skip(desc.short_name(), "not run by default");
+                    }
+                }
+            }
+            // Configured defaults replace the steps' own, and name either a
+            // step's path or a directory containing the paths of steps.
+            for dir in default_paths {
+                let mut matched = false;
+                for (desc, should_run) in v.iter().zip(&should_runs) {
+                    for pathset in &should_run.paths {
+                        if pathset.within(dir) || pathset.has(dir) {
+                            matched = true;
+                            desc.maybe_run(builder, pathset);
+                        }
+                    }
+                }
+                if !matched {
+                    eprintln!("warning: no rules matched the default path {}", dir.display());
                 }
             }
         }
 
+        let mut unclaimed = Vec::new();
+        let mut claimed = vec![false; v.len()];
         for path in paths {
             // strip CurDir prefix if present
             let path = match path.strip_prefix(".") {
@@ -211,23 +277,91 @@ impl StepDescription {
             };
 
             let mut attempted_run = false;
-            for (desc, should_run) in v.iter().zip(&should_runs) {
+            for (i, (desc, should_run)) in v.iter().zip(&should_runs).enumerate() {
                 if let Some(suite) = should_run.is_suite_path(path) {
                     attempted_run = true;
+                    claimed[i] = true;
                     desc.maybe_run(builder, suite);
                 } else if let Some(pathset) = should_run.pathset_for_path(path) {
                     attempted_run = true;
+                    claimed[i] = true;
+                    builder.verbose(&format!("{} is handled by {}", path.display(), desc.name));
                     desc.maybe_run(builder, pathset);
                 }
             }
 
             if !attempted_run {
-                panic!("error: no rules matched {}", path.display());
+                unclaimed.push(path);
+            }
+        }
+        if !paths.is_empty() && !builder.config.include_default_paths {
+            for (desc, claimed) in v.iter().zip(claimed) {
+                if desc.default && !claimed {
+                    builder.skip(desc.short_name(), "none of the given paths are its");
+                }
+            }
+        }
+
+        // Report every path that nothing claimed at once rather than stopping
+        // at the first one, so that a typo doesn't hide the next.
+        if !unclaimed.is_empty() {
+            let mut msg = String::from("error: no rules matched the following paths:");
+            for path in unclaimed {
+                msg.push_str(&format!("\n    {}", path.display()));
+                let suggestions = closest_paths(path, &should_runs);
+                if !suggestions.is_empty() {
+                    let suggestions = suggestions
+                        .iter()
+                        .map(|p| format!("`{}`", p.display()))
+                        .collect::<Vec<_>>()
+                        .join(" or ");
+                    msg.push_str(&format!(" (did you mean {}?)", suggestions));
+                }
             }
+            return Err(msg);
         }
+        Ok(())
     }
 }
 
+/// Finds the registered paths closest to `path` by edit distance, for use as a
+/// suggestion when `path` didn't match any rule.
+fn closest_paths(path: &Path, should_runs: &[ShouldRun<'_>]) -> Vec<PathBuf> {
+    let needle = path.to_string_lossy();
+    let components = path.components().count();
+    let mut candidates = Vec::new();
+    for should_run in should_runs {
+        for pathset in &should_run.paths {
+            let known: Vec<&PathBuf> = match pathset {
+                PathSet::Set(set) => set.iter().collect(),
+                PathSet::Suite(suite) => vec![suite],
+            };
+            for known in known {
+                // Paths are matched as a suffix, so also compare against the
+                // suffix that has as many components as `path` does.
+                let mut suffix = known.components().rev().take(components).collect::<Vec<_>>();
+                suffix.reverse();
+                let suffix = suffix.into_iter().collect::<PathBuf>();
+                let distance = cmp::min(
+                    util::edit_distance(&needle, &known.to_string_lossy()),
+                    util::edit_distance(&needle, &suffix.to_string_lossy()),
+                );
+                candidates.push((distance, known.clone()));
+            }
+        }
+    }
+
+    let threshold = cmp::max(needle.len() / 3, 1);
+    candidates.retain(|&(distance, _)| distance <= threshold);
+    candidates.sort();
+    candidates.dedup();
+    let best = match candidates.first() {
+        Some(&(distance, _)) => distance,
+        None => return Vec::new(),
+    };
+    candidates.into_iter().take_while(|&(distance, _)| distance == best).map(|(_, p)| p).collect()
+}
+
 #[derive(Clone)]
 pub struct ShouldRun<'a> {
     pub builder: &'a Builder<'a>,
@@ -333,6 +467,34 @@ pub enum Kind {
     Run,
 }
 
+impl Kind {
+    /// Arguments that this kind adds to the end of every Cargo invocation of
+    /// the check-like steps (check, clippy and fix). Anything after a `--` is
+    /// passed on to rustc or the driver instead of Cargo.
+    pub fn extra_cargo_args(self, config: &Config) -> Vec<String> {
+        match self {
+            // The in-tree clippy gets these through `CLIPPY_ARGS` instead.
+            Kind::Clippy if config.stage == 0 => {
+                let mut args = vec!["--".to_owned()];
+                args.extend(Kind::clippy_args(config));
+                args
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// The arguments `x.py clippy` passes the clippy driver.
+    pub fn clippy_args(config: &Config) -> Vec<String> {
+        // Capping at `warn` still leaves the suggestions of the lints `--fix`
+        // applies, and the lint options come after so they decide which of
+        // them fire, the ones on the command line last.
+        let mut args = vec!["--cap-lints".to_owned(), "warn".to_owned()];
+        args.extend(config.clippy_lints.flags.iter().cloned());
+        args.extend(config.cmd.lint_args().iter().cloned());
+        args
+    }
+}
+
 impl<'a> Builder<'a> {
     fn get_step_descriptions(kind: Kind) -> Vec<StepDescription> {
         macro_rules! describe {
@@ -371,7 +533,20 @@ impl<'a> Builder<'a> {
                 native::Lld
             ),
             Kind::Check | Kind::Clippy | Kind::Fix | Kind::Format => {
-                describe!(check::Std, check::Rustc, check::Rustdoc, check::Clippy, check::Bootstrap)
+                describe!(
+                    check::Std,
+                    check::Rustc,
+                    check::Rustdoc,
+                    check::Clippy,
+                    check::Rustfmt,
+                    check::Bootstrap,
+                    check::CargoTest,
+                    check::Linkchecker,
+                    check::Miri,
+                    check::UnstableBookGen,
+                    check::RustBook,
+                    check::Manifest
+                )
             }
             Kind::Test => describe!(
                 crate::toolstate::ToolStateCheck,
@@ -525,7 +700,10 @@ impl<'a> Builder<'a> {
             kind,
             cache: Cache::new(),
             stack: RefCell::new(Vec::new()),
+            step_names: RefCell::new(Vec::new()),
             time_spent_on_dependencies: Cell::new(Duration::new(0, 0)),
+            skipped: RefCell::new(Vec::new()),
+            requested: RefCell::new(HashSet::new()),
             paths,
         }
     }
@@ -533,25 +711,44 @@ impl<'a> Builder<'a> {
     pub fn new(build: &Build) -> Builder<'_> {
         let (kind, paths) = match build.config.cmd {
             Subcommand::Build { ref paths } => (Kind::Build, &paths[..]),
-            Subcommand::Check { ref paths, all_targets: _ } => (Kind::Check, &paths[..]),
-            Subcommand::Clippy { ref paths } => (Kind::Clippy, &paths[..]),
-            Subcommand::Fix { ref paths } => (Kind::Fix, &paths[..]),
+            Subcommand::Check { ref paths, .. } => (Kind::Check, &paths[..]),
+            Subcommand::Clippy { ref paths, .. } => (Kind::Clippy, &paths[..]),
+            Subcommand::Fix { ref paths, .. } => (Kind::Fix, &paths[..]),
             Subcommand::Doc { ref paths, .. } => (Kind::Doc, &paths[..]),
             Subcommand::Test { ref paths, .. } => (Kind::Test, &paths[..]),
             Subcommand::Bench { ref paths, .. } => (Kind::Bench, &paths[..]),
             Subcommand::Dist { ref paths } => (Kind::Dist, &paths[..]),
             Subcommand::Install { ref paths } => (Kind::Install, &paths[..]),
             Subcommand::Run { ref paths } => (Kind::Run, &paths[..]),
-            Subcommand::Format { .. } | Subcommand::Clean { .. } | Subcommand::Setup { .. } => {
-                panic!()
-            }
+            Subcommand::Format { .. }
+            | Subcommand::Clean { .. }
+            | Subcommand::Setup { .. }
+            | Subcommand::SetupEditor { .. }
+            | Subcommand::Presubmit { .. } => panic!(),
         };
 
         Self::new_internal(build, kind, paths.to_owned())
     }
 
+    /// Paths configured to be built when none are given, replacing the steps
+    /// that run by default.
+    fn default_paths(&self) -> &[PathBuf] {
+        match self.kind {
+            Kind::Check => &self.config.check_default_paths,
+            _ => &[],
+        }
+    }
+
+    /// Whether the crates `compiler` builds see `cfg(bootstrap)`, which marks
+    /// the code that has to compile with the previous release: only for the
+    /// stage0 compiler, unless `build.local-rebuild` says that one is current.
+    pub fn cfg_bootstrap(&self, compiler: Compiler) -> bool {
+        compiler.stage == 0 && !self.local_rebuild
+    }
+
     pub fn execute_cli(&self) {
         self.run_step_descriptions(&Builder::get_step_descriptions(self.kind), &self.paths);
+        self.print_skipped();
     }
 
     pub fn default_doc(&self, paths: Option<&[PathBuf]>) {
@@ -559,8 +756,23 @@ impl<'a> Builder<'a> {
         self.run_step_descriptions(&Builder::get_step_descriptions(Kind::Doc), paths);
     }
 
+    /// Runs the steps of `v` for `paths`, exiting with status 2 if some of
+    /// the paths don't belong to any step, as that's most likely a typo.
     fn run_step_descriptions(&self, v: &[StepDescription], paths: &[PathBuf]) {
-        StepDescription::run(v, self, paths);
+        if let Err(msg) = self.try_run_step_descriptions(v, paths) {
+            eprintln!("{}", msg);
+            process::exit(2);
+        }
+    }
+
+    /// Same as `run_step_descriptions`, but returns the error instead of
+    /// exiting.
+    fn try_run_step_descriptions(
+        &self,
+        v: &[StepDescription],
+        paths: &[PathBuf],
+    ) -> Result<(), String> {
+        StepDescription::run(v, self, paths)
     }
 
     /// Obtain a compiler at a given stage and for a given host. Explicitly does
@@ -760,7 +972,23 @@ impl<'a> Builder<'a> {
         cmd: &str,
     ) -> Cargo {
         let mut cargo = Command::new(&self.initial_cargo);
-        let out_dir = self.stage_out(compiler, mode);
+        // Bootstrap sets what it passes on to Cargo after this, so it's only
+        // the rest of the environment that's dropped.
+        if self.config.hermetic_env && self.is_check_like() {
+            let dropped = hermetic_env_removals(env::vars_os().map(|(name, _)| name));
+            for name in &dropped {
+                cargo.env_remove(name);
+            }
+            if !dropped.is_empty() {
+                let names = dropped.iter().map(|name| name.to_string_lossy()).collect::<Vec<_>>();
+                self.verbose(&format!("not passing on to cargo: {}", names.join(", ")));
+            }
+        }
+        let out_dir = if self.is_check_like() && matches!(cmd, "check" | "clippy" | "fix") {
+            self.check_stage_out(compiler, mode)
+        } else {
+            self.stage_out(compiler, mode)
+        };
 
         if cmd == "doc" || cmd == "rustdoc" {
             let my_out = match mode {
@@ -773,7 +1001,29 @@ impl<'a> Builder<'a> {
             self.clear_if_dirty(&my_out, &rustdoc);
         }
 
-        cargo.env("CARGO_TARGET_DIR", &out_dir).arg(cmd);
+        // `x.py clippy --stage N` uses the in-tree clippy, which Cargo only
+        // runs as a wrapper around rustc.
+        let in_tree_clippy = cmd == "clippy" && self.top_stage > 0;
+        cargo.env("CARGO_TARGET_DIR", &out_dir).arg(if in_tree_clippy { "check" } else { cmd });
+        if in_tree_clippy {
+            let driver = self.ensure(tool::Clippy {
+                compiler: self.compiler(self.top_stage - 1, self.config.build),
+                target: self.config.build,
+                extra_features: Vec::new(),
+            });
+            let driver = driver.unwrap_or_else(|| {
+                eprintln!("error: the in-tree clippy failed to build");
+                std::process::exit(1);
+            });
+            let mut clippy_args = String::new();
+            for arg in Kind::clippy_args(&self.config) {
+                clippy_args.push_str(&arg);
+                clippy_args.push_str("__CLIPPY_HACKERY__");
+            }
+            cargo.env("RUSTC_WORKSPACE_WRAPPER", &driver).env("CLIPPY_ARGS", clippy_args);
+            // The driver links to the libraries of the compiler it's paired with.
+            self.add_rustc_lib_path(compiler, &mut cargo);
+        }
 
         let profile_var = |name: &str| {
             let profile = if self.config.rust_optimize { "RELEASE" } else { "DEV" };
@@ -787,6 +1037,13 @@ impl<'a> Builder<'a> {
             cargo.env("REAL_LIBRARY_PATH", e);
         }
 
+        if cmd == "clippy" && !in_tree_clippy && self.config.cmd.clippy_fix() {
+            cargo.arg("--fix").arg("-Zunstable-options");
+        }
+        if self.is_check_like() && self.config.cmd.keep_going() {
+            cargo.arg("--keep-going").arg("-Zunstable-options");
+        }
+
         if cmd != "install" {
             cargo.arg("--target").arg(target.rustc_target_arg());
         } else {
@@ -815,7 +1072,7 @@ impl<'a> Builder<'a> {
         };
 
         let mut rustflags = Rustflags::new(target);
-        if stage != 0 {
+        if !self.cfg_bootstrap(compiler) {
             if let Ok(s) = env::var("CARGOFLAGS_NOT_BOOTSTRAP") {
                 cargo.args(s.split_whitespace());
             }
@@ -865,7 +1122,8 @@ impl<'a> Builder<'a> {
         // feature on the rustc side.
         cargo.arg("-Zbinary-dep-depinfo");
 
-        cargo.arg("-j").arg(self.jobs().to_string());
+        let jobs = self.config.cmd.check_jobs().unwrap_or_else(|| self.jobs());
+        cargo.arg("-j").arg(jobs.to_string());
         // Remove make-related flags to ensure Cargo can correctly set things up
         cargo.env_remove("MAKEFLAGS");
         cargo.env_remove("MFLAGS");
@@ -914,6 +1172,14 @@ impl<'a> Builder<'a> {
 
         rustflags.arg("-Zmacro-backtrace");
 
+        // Type-check with several threads, which only makes a difference if
+        // the checking compiler was built with the parallel compiler.
+        if self.kind == Kind::Check {
+            if let Some(threads) = self.config.rust_check_threads {
+                rustflags.arg(&format!("-Zthreads={}", threads));
+            }
+        }
+
         let want_rustdoc = self.doc_tests != DocTests::No;
 
         // We synthetically interpret a stage0 compiler used to build tools as a
@@ -1114,7 +1380,14 @@ impl<'a> Builder<'a> {
             }
         }
 
-        if self.config.incremental {
+        if self.is_check_like() && self.incremental() {
+            // Cargo's incremental directory is shared by everything using the
+            // target directory, so keep check, clippy and fix from reusing
+            // each other's state.
+            let dir = out_dir.join(&*target.triple).join(format!("incremental-{}", cmd));
+            cargo.env("CARGO_INCREMENTAL", "0");
+            rustflags.arg(&format!("-Cincremental={}", dir.display()));
+        } else if self.incremental() {
             cargo.env("CARGO_INCREMENTAL", "1");
         } else {
             // Don't rely on any default setting for incr. comp. in Cargo
@@ -1135,6 +1408,12 @@ impl<'a> Builder<'a> {
 
         cargo.env("RUSTC_VERBOSE", self.verbosity.to_string());
 
+        // Warnings in vendored crates can't be fixed here, so only the crates
+        // of the tree get to report theirs when checking.
+        if self.is_check_like() {
+            cargo.env("RUSTC_CAP_EXTERNAL_LINTS", &self.src);
+        }
+
         if source_type == SourceType::InTree {
             let mut lint_flags = Vec::new();
             // When extending this list, add the new lints to the RUSTFLAGS of the
@@ -1144,14 +1423,22 @@ impl<'a> Builder<'a> {
             lint_flags.push("-Wunused_lifetimes");
 
             if self.config.deny_warnings {
-                lint_flags.push("-Dwarnings");
+                // Cargo doesn't know about `RUSTC_LINT_FLAGS`, so a check that
+                // passed with warnings would still pass after turning them into
+                // errors. In `RUSTFLAGS` the escalation is part of the
+                // fingerprint; vendored crates have their lints capped anyway.
+                if self.is_check_like() {
+                    rustflags.arg("-Dwarnings");
+                } else {
+                    lint_flags.push("-Dwarnings");
+                }
                 rustdocflags.arg("-Dwarnings");
             }
 
             // FIXME(#58633) hide "unused attribute" errors in incremental
             // builds of the standard library, as the underlying checks are
             // not yet properly integrated with incremental recompilation.
-            if mode == Mode::Std && compiler.stage == 0 && self.config.incremental {
+            if mode == Mode::Std && compiler.stage == 0 && self.incremental() {
                 lint_flags.push("-Aunused-attributes");
             }
             // This does not use RUSTFLAGS due to caching issues with Cargo.
@@ -1321,6 +1608,9 @@ impl<'a> Builder<'a> {
         if self.config.vendor || self.is_sudo {
             cargo.arg("--frozen");
         }
+        if self.config.offline {
+            cargo.arg("--offline");
+        }
 
         // Try to use a sysroot-relative bindir, in case it was configured absolutely.
         cargo.env("RUSTC_INSTALL_BINDIR", self.config.bindir_relative());
@@ -1352,6 +1642,51 @@ impl<'a> Builder<'a> {
         Cargo { command: cargo, rustflags, rustdocflags }
     }
 
+    /// Whether this is `x.py check`, `clippy` or `fix`.
+    pub fn is_check_like(&self) -> bool {
+        matches!(self.kind, Kind::Check | Kind::Clippy | Kind::Fix)
+    }
+
+    /// Whether to compile incrementally, which `check.incremental` decides for
+    /// the check-like commands.
+    pub fn incremental(&self) -> bool {
+        if self.is_check_like() {
+            self.config.check_incremental.unwrap_or(self.config.incremental)
+        } else {
+            self.config.incremental
+        }
+    }
+
+    /// The name of the step currently running, such as `check::Rustc`.
+    pub fn current_step(&self) -> Option<&'static str> {
+        self.step_names.borrow().last().copied()
+    }
+
+    /// Whether the step `S` was selected by the paths given, or as a default
+    /// step, rather than only run as a dependency of the selected ones.
+    pub fn is_requested<S: Step>(&self) -> bool {
+        self.requested.borrow().contains(std::any::type_name::<S>())
+    }
+
+    /// Records that `step` was decided against, and why, for `-v` to list
+    /// once everything ran.
+    pub fn skip(&self, step: &str, reason: &str) {
+        let entry = (step.to_string(), reason.to_string());
+        let mut skipped = self.skipped.borrow_mut();
+        if !skipped.contains(&entry) {
+            skipped.push(entry);
+        }
+    }
+
+    fn print_skipped(&self) {
+        if !self.is_verbose() {
+            return;
+        }
+        for (step, reason) in self.skipped.borrow().iter() {
+            println!("skipped: {} ({})", step, reason);
+        }
+    }
+
     /// Ensure that a given step is built, returning its output. This will
     /// cache the step, so it is safe (and good!) to call this as often as
     /// needed to ensure that all dependencies are built.
@@ -1372,11 +1707,14 @@ impl<'a> Builder<'a> {
             }
             if let Some(out) = self.cache.get(&step) {
                 self.verbose(&format!("{}c {:?}", "  ".repeat(stack.len()), step));
+                self.skip(&format!("{:?}", step), "already ran");
 
                 return out;
             }
             self.verbose(&format!("{}> {:?}", "  ".repeat(stack.len()), step));
             stack.push(Box::new(step.clone()));
+            let name = std::any::type_name::<S>();
+            self.step_names.borrow_mut().push(name.trim_start_matches("bootstrap::"));
         }
 
         let (out, dur) = {
@@ -1390,13 +1728,24 @@ impl<'a> Builder<'a> {
         };
 
         if self.config.print_step_timings && !self.config.dry_run {
-            println!("[TIMING] {:?} -- {}.{:03}", step, dur.as_secs(), dur.subsec_millis());
+            // Mark incremental check runs, so that their timings can be told
+            // apart from full ones.
+            let incremental =
+                if self.is_check_like() && self.incremental() { " (incremental)" } else { "" };
+            println!(
+                "[TIMING] {:?} -- {}.{:03}{}",
+                step,
+                dur.as_secs(),
+                dur.subsec_millis(),
+                incremental
+            );
         }
 
         {
             let mut stack = self.stack.borrow_mut();
             let cur_step = stack.pop().expect("step stack empty");
             assert_eq!(cur_step.downcast_ref(), Some(&step));
+            self.step_names.borrow_mut().pop();
         }
         self.verbose(&format!("{}< {:?}", "  ".repeat(self.stack.borrow().len()), step));
         self.cache.put(step, out.clone());
@@ -1487,6 +1836,85 @@ impl Cargo {
     pub fn add_rustc_lib_path(&mut self, builder: &Builder<'_>, compiler: Compiler) {
         builder.add_rustc_lib_path(compiler, &mut self.command);
     }
+
+    /// The `RUSTFLAGS` Cargo runs with.
+    pub fn rustflags(&self) -> &str {
+        &self.rustflags.0
+    }
+
+    /// The command line along with the flags passed through the environment,
+    /// for showing how to run Cargo by hand.
+    pub fn command_line(&self) -> String {
+        let mut line = String::new();
+        if !self.rustflags.0.is_empty() {
+            line.push_str(&format!("RUSTFLAGS={:?} ", self.rustflags.0));
+        }
+        if !self.rustdocflags.0.is_empty() {
+            line.push_str(&format!("RUSTDOCFLAGS={:?} ", self.rustdocflags.0));
+        }
+        line.push_str(&format!("{:?}", self.command));
+        line
+    }
+}
+
+/// The variables of the environment bootstrap runs in that Cargo still gets
+/// with `build.hermetic-env`, as it or the tools it runs can't do without.
+const HERMETIC_ENV_PASSTHROUGH: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "TMPDIR",
+    "TERM",
+    "LANG",
+    "LC_ALL",
+    "LD_LIBRARY_PATH",
+    "DYLD_LIBRARY_PATH",
+    "LIBRARY_PATH",
+    "CARGO_HOME",
+    "RUSTUP_HOME",
+    "BOOTSTRAP_PARENT_ID",
+    "SSL_CERT_FILE",
+    "SSL_CERT_DIR",
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "NO_PROXY",
+    "SSH_AUTH_SOCK",
+    // Windows.
+    "SYSTEMROOT",
+    "SYSTEMDRIVE",
+    "WINDIR",
+    "TEMP",
+    "TMP",
+    "USERPROFILE",
+    "APPDATA",
+    "LOCALAPPDATA",
+    "PROGRAMDATA",
+    "PROGRAMFILES",
+    "PROGRAMFILES(X86)",
+    "COMSPEC",
+    "PATHEXT",
+    "NUMBER_OF_PROCESSORS",
+    "PROCESSOR_ARCHITECTURE",
+    "INCLUDE",
+    "LIB",
+    "LIBPATH",
+    "VCINSTALLDIR",
+    "VSINSTALLDIR",
+];
+
+/// The variables among `names` that `build.hermetic-env` keeps from Cargo,
+/// sorted. Names are compared without regard to case, as Windows does.
+fn hermetic_env_removals(names: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    let mut dropped = names
+        .filter(|name| {
+            let name = name.to_string_lossy();
+            !HERMETIC_ENV_PASSTHROUGH.iter().any(|kept| name.eq_ignore_ascii_case(kept))
+        })
+        .collect::<Vec<_>>();
+    dropped.sort();
+    dropped
 }
 
 impl From<Cargo> for Command {
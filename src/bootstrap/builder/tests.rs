@@ -616,3 +616,773 @@ mod dist {
         );
     }
 }
+
+mod check {
+    use super::{configure, first};
+    use crate::builder::*;
+    use crate::config::Target;
+    use crate::{CargoFailure, Config};
+
+    #[test]
+    fn check_unclaimed_tool_path() {
+        let build = Build::new(configure("check", &["A"], &["A"]));
+        let builder = Builder::new(&build);
+        let err = builder
+            .try_run_step_descriptions(
+                &Builder::get_step_descriptions(Kind::Check),
+                &["src/tools/rustdco".into()],
+            )
+            .unwrap_err();
+        assert!(err.contains("src/tools/rustdco (did you mean `src/tools/rustdoc`?)"), "{}", err);
+    }
+
+    #[test]
+    fn check_library_path() {
+        let build = Build::new(configure("check", &["A"], &["A"]));
+        let builder = Builder::new(&build);
+        builder.run_step_descriptions(
+            &Builder::get_step_descriptions(Kind::Check),
+            &["library/std".into()],
+        );
+
+        assert!(builder.cache.contains::<check::Std>());
+        assert!(!builder.cache.contains::<check::Rustdoc>());
+    }
+
+    #[test]
+    fn check_publishes_checked_crates() {
+        let build = Build::new(configure("check", &["A"], &["A"]));
+        let builder = Builder::new(&build);
+        builder.run_step_descriptions(
+            &Builder::get_step_descriptions(Kind::Check),
+            &["library/std".into()],
+        );
+
+        let results = build.check_results.borrow();
+        let std = &results[&("check::Std", TargetSelection::from_user("A"))];
+        assert!(std.iter().any(|krate| &krate[..] == "core"));
+    }
+
+    #[test]
+    fn check_rustfmt_path() {
+        let build = Build::new(configure("check", &["A"], &["A"]));
+        let mut builder = Builder::new(&build);
+        builder.run_step_descriptions(
+            &Builder::get_step_descriptions(Kind::Check),
+            &["src/tools/rustfmt".into()],
+        );
+
+        assert_eq!(
+            first(builder.cache.all::<check::Rustfmt>()),
+            &[check::Rustfmt { target: TargetSelection::from_user("A") }]
+        );
+        assert!(!builder.cache.contains::<tool::Rustfmt>());
+    }
+
+    #[test]
+    fn check_tool_paths_are_step_paths() {
+        let build = Build::new(configure("check", &["A"], &["A"]));
+        let builder = Builder::new(&build);
+        let descriptions = Builder::get_step_descriptions(Kind::Check);
+        for path in check::TOOL_PATHS {
+            assert!(
+                descriptions.iter().any(|desc| {
+                    (desc.should_run)(ShouldRun::new(&builder))
+                        .paths
+                        .iter()
+                        .any(|set| set.has(Path::new(path)))
+                }),
+                "no check step for {}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn check_records_skipped_steps() {
+        let mut config = configure("check", &["A"], &["A", "B"]);
+        config.exclude = vec![PathBuf::from("src/tools/clippy")];
+        let build = Build::new(config);
+        let builder = Builder::new(&build);
+        builder.run_step_descriptions(
+            &Builder::get_step_descriptions(Kind::Check),
+            &["library/std".into(), "src/librustdoc".into(), "src/tools/clippy".into()],
+        );
+
+        let skipped = builder.skipped.borrow();
+        let reason = |step: &str| {
+            skipped.iter().find(|(skipped, _)| skipped == step).map(|(_, reason)| &reason[..])
+        };
+        assert_eq!(reason("check::Clippy"), Some("src/tools/clippy is excluded"));
+        assert_eq!(reason("check::Miri"), Some("none of the given paths are its"));
+        assert_eq!(
+            reason("check::Rustdoc (B)"),
+            Some("only runs for hosts, and this target isn't one")
+        );
+        assert_eq!(reason("check::Std"), None);
+    }
+
+    #[test]
+    fn check_deny_warnings_is_fingerprinted() {
+        let mut config = configure("check", &["A"], &["A"]);
+        config.deny_warnings = true;
+        let build = Build::new(config);
+        let builder = Builder::new(&build);
+        let compiler = Compiler { stage: 0, host: TargetSelection::from_user("A") };
+        let cargo = builder.cargo(
+            compiler,
+            Mode::Std,
+            SourceType::InTree,
+            TargetSelection::from_user("A"),
+            "check",
+        );
+        assert!(cargo.rustflags.0.split(' ').any(|flag| flag == "-Dwarnings"));
+    }
+
+    #[test]
+    fn check_skip_crates() {
+        let mut config = configure("check", &["A"], &["A", "B"]);
+        let mut target = crate::config::Target::from_triple("B");
+        target.skip_crates = vec!["alloc".to_string()];
+        config.target_config.insert(TargetSelection::from_user("B"), target);
+        let build = Build::new(config);
+        let builder = Builder::new(&build);
+        builder.run_step_descriptions(&Builder::get_step_descriptions(Kind::Check), &[]);
+
+        let results = build.check_results.borrow();
+        let checks_alloc = |target| {
+            results[&("check::Std", TargetSelection::from_user(target))]
+                .iter()
+                .any(|krate| &krate[..] == "alloc")
+        };
+        assert!(checks_alloc("A"));
+        assert!(!checks_alloc("B"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn check_keep_going_writes_partial_stamp() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut config = configure("check", &["A"], &["A"]);
+        if let Subcommand::Check { ref mut keep_going, .. } = config.cmd {
+            *keep_going = true;
+        }
+        let deps = config.out.join("A/release/deps");
+        t!(fs::create_dir_all(&deps));
+        let rmeta = deps.join("liba-0123.rmeta");
+        t!(fs::write(&rmeta, ""));
+        // Cargo checks `a`, fails to compile `b` and keeps going.
+        let cargo = config.out.join("fake-cargo");
+        t!(fs::write(
+            &cargo,
+            format!(
+                "#!/bin/sh\n\
+                 echo '{{\"reason\":\"compiler-artifact\",\"package_id\":\"a 0.1.0\",\
+                 \"features\":[],\"filenames\":[\"{}\"],\"target\":{{\"crate_types\":[\"lib\"]}}}}'\n\
+                 echo '{{\"reason\":\"compiler-message\",\"package_id\":\"b 0.1.0\",\
+                 \"message\":{{\"message\":\"oops\",\"code\":null,\"level\":\"error\",\
+                 \"spans\":[],\"rendered\":null}}}}'\n\
+                 echo '{{\"reason\":\"build-finished\",\"success\":false}}'\n\
+                 exit 101\n",
+                rmeta.display()
+            )
+        ));
+        t!(fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)));
+        let stamp = config.out.join("A/release/.test-check.stamp");
+
+        let mut build = Build::new(config);
+        build.config.dry_run = false;
+        build.initial_cargo = cargo;
+        let builder = Builder::new(&build);
+        let compiler = Compiler { stage: 0, host: TargetSelection::from_user("A") };
+        let target = TargetSelection::from_user("A");
+        let cargo = builder.cargo(compiler, Mode::Std, SourceType::InTree, target, "check");
+        let result = crate::compile::try_run_cargo(&builder, cargo, vec![], &stamp, vec![], true);
+
+        assert_eq!(result.unwrap(), [rmeta]);
+        assert!(crate::compile::is_partial_stamp(&stamp));
+        assert_eq!(*build.delayed_failures.borrow(), ["failed to compile b"]);
+    }
+
+    #[test]
+    fn check_force_only_the_steps_asked_for() {
+        let mut config = configure("check", &["A"], &["A"]);
+        if let Subcommand::Check { ref mut force, .. } = config.cmd {
+            *force = true;
+        }
+        let build = Build::new(config);
+        let builder = Builder::new(&build);
+        builder.run_step_descriptions(
+            &Builder::get_step_descriptions(Kind::Check),
+            &["src/tools/clippy".into()],
+        );
+        assert!(builder.is_requested::<check::Clippy>());
+        assert!(!builder.is_requested::<check::Rustc>());
+        assert!(!builder.is_requested::<check::Std>());
+
+        let mut config = configure("check", &["A"], &["A"]);
+        let dir = config.out.join("force");
+        let _ = fs::remove_dir_all(&dir);
+        let fingerprints = dir.join("release/.fingerprint");
+        for krate in &["clippy-0123", "clippy_lints-4567", "rustc_middle-89ab"] {
+            t!(fs::create_dir_all(fingerprints.join(krate)));
+        }
+        let stamp = dir.join("release/.clippy-check.stamp");
+        t!(fs::write(&stamp, ""));
+
+        // A step that wasn't asked for is only forced with `--force-deps`.
+        if let Subcommand::Check { ref mut force, .. } = config.cmd {
+            *force = true;
+        }
+        let mut build = Build::new(config);
+        build.config.dry_run = false;
+        check::force::<check::Clippy>(&Builder::new(&build), &stamp, &["clippy"]);
+        assert!(stamp.exists());
+
+        if let Subcommand::Check { ref mut force_deps, .. } = build.config.cmd {
+            *force_deps = true;
+        }
+        check::force::<check::Clippy>(&Builder::new(&build), &stamp, &["clippy"]);
+        assert!(!stamp.exists());
+        assert!(!fingerprints.join("clippy-0123").exists());
+        assert!(fingerprints.join("clippy_lints-4567").exists());
+        assert!(fingerprints.join("rustc_middle-89ab").exists());
+    }
+
+    #[test]
+    fn check_tools_again_when_rustc_changes() {
+        let config = configure("check", &["A"], &["A"]);
+        let dir = config.out.join("rustc-changes");
+        let _ = fs::remove_dir_all(&dir);
+        let mut build = Build::new(config);
+        build.config.dry_run = false;
+        let builder = Builder::new(&build);
+
+        let rmeta = dir.join("rustc/deps/librustc_middle-0123.rmeta");
+        t!(fs::create_dir_all(rmeta.parent().unwrap()));
+        t!(fs::write(&rmeta, ""));
+        let rustc_stamp = dir.join("rustc/.librustc-check.stamp");
+        let deps = [(rmeta.clone(), crate::DependencyType::Target)];
+        t!(fs::write(&rustc_stamp, crate::compile::encode_stamp(&deps, &build.out)));
+        let fingerprint = dir.join("tool/.fingerprint/clippy-4567");
+        t!(fs::create_dir_all(&fingerprint));
+        let stamp = dir.join("tool/.clippy-check.stamp");
+        t!(fs::write(&stamp, ""));
+
+        let hash = check::check_against_rustc(&builder, &stamp, &rustc_stamp, &["clippy"]);
+        t!(fs::write(stamp.with_extension("stamp.rustc"), hash.unwrap()));
+        t!(fs::write(&stamp, ""));
+        t!(fs::create_dir_all(&fingerprint));
+        check::check_against_rustc(&builder, &stamp, &rustc_stamp, &["clippy"]);
+        assert!(stamp.exists());
+
+        // Checking the compiler crates again rewrites them.
+        let later = filetime::FileTime::from_unix_time(2_000_000_000, 0);
+        t!(filetime::set_file_mtime(&rmeta, later));
+        check::check_against_rustc(&builder, &stamp, &rustc_stamp, &["clippy"]);
+        assert!(!stamp.exists());
+        assert!(!fingerprint.exists());
+    }
+
+    #[test]
+    fn check_std_only_and_rustc_only() {
+        let mut config = configure("check", &["A"], &["A"]);
+        if let Subcommand::Check { ref mut std_only, .. } = config.cmd {
+            *std_only = true;
+        }
+        let build = Build::new(config);
+        let mut builder = Builder::new(&build);
+        builder.run_step_descriptions(&Builder::get_step_descriptions(Kind::Check), &[]);
+        assert!(!builder.cache.all::<check::Std>().is_empty());
+        assert!(builder.cache.all::<check::Rustc>().is_empty());
+        assert!(builder.cache.all::<check::Clippy>().is_empty());
+
+        let mut config = configure("check", &["A"], &["A"]);
+        if let Subcommand::Check { ref mut rustc_only, .. } = config.cmd {
+            *rustc_only = true;
+        }
+        let build = Build::new(config);
+        let mut builder = Builder::new(&build);
+        builder.run_step_descriptions(&Builder::get_step_descriptions(Kind::Check), &[]);
+        assert!(!builder.is_requested::<check::Std>());
+        assert!(!builder.cache.all::<check::Std>().is_empty());
+        assert!(!builder.cache.all::<check::Rustc>().is_empty());
+        assert!(builder.cache.all::<check::Rustdoc>().is_empty());
+        assert!(builder.skipped.borrow().contains(&(
+            "check::Rustdoc".to_string(),
+            "only check::Rustc was asked for".to_string()
+        )));
+    }
+
+    #[test]
+    fn check_hermetic_env() {
+        let names = ["RUSTC_WRAPPER", "PATH", "CARGO_INCREMENTAL", "SystemRoot", "HOME"];
+        let dropped = hermetic_env_removals(names.iter().map(OsString::from));
+        assert_eq!(dropped, ["CARGO_INCREMENTAL", "RUSTC_WRAPPER"]);
+
+        let args = ["check".to_owned(), "--deterministic-env".to_owned()];
+        assert!(Config::parse(&args).hermetic_env);
+        let config = Config::parse_with_toml(&args[..1], "[build]\nhermetic-env = true\n");
+        assert!(config.unwrap().hermetic_env);
+        assert!(!Config::parse(&args[..1]).hermetic_env);
+    }
+
+    #[test]
+    fn check_manifest_path() {
+        let mut config = configure("check", &["A"], &["A"]);
+        if let Subcommand::Check { ref mut manifest_path, .. } = config.cmd {
+            *manifest_path = Some(PathBuf::from("src/tools/experimental/Cargo.toml"));
+        }
+        let build = Build::new(config);
+        let mut builder = Builder::new(&build);
+        builder.run_step_descriptions(&Builder::get_step_descriptions(Kind::Check), &[]);
+        assert_eq!(
+            first(builder.cache.all::<check::Manifest>()),
+            &[check::Manifest { target: TargetSelection::from_user("A") }]
+        );
+        assert!(!builder.cache.all::<check::Rustc>().is_empty());
+        assert!(builder.cache.all::<check::Clippy>().is_empty());
+    }
+
+    #[test]
+    fn check_profiles() {
+        let toml = "[check]\nall-targets = true\n\
+                    [check.profiles.quick]\nall-targets = false\n\
+                    targets = [\"aarch64-unknown-linux-gnu\"]\n\
+                    paths = [\"library/std\"]\ndeny-warnings = false\n\
+                    [check.profiles.ci]\nrustc-args = [\"--cfg=ci\"]\ndeny-warnings = true\n";
+        let parse = |args: &[&str]| {
+            let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+            Config::parse_with_toml(&args, toml).unwrap()
+        };
+
+        let config = parse(&["check", "--profile", "quick"]);
+        assert_eq!(config.targets, [TargetSelection::from_user("aarch64-unknown-linux-gnu")]);
+        assert!(!config.deny_warnings);
+        match config.cmd {
+            Subcommand::Check { all_targets, ref paths, .. } => {
+                assert!(!all_targets);
+                assert_eq!(*paths, [PathBuf::from("library/std")]);
+            }
+            _ => unreachable!(),
+        }
+
+        // The command line takes precedence over the profile.
+        let config = parse(&[
+            "check",
+            "--profile",
+            "quick",
+            "--all-targets",
+            "--target",
+            "x86_64-pc-windows-msvc",
+        ]);
+        assert_eq!(config.targets, [TargetSelection::from_user("x86_64-pc-windows-msvc")]);
+        assert!(matches!(config.cmd, Subcommand::Check { all_targets: true, .. }));
+
+        let config = parse(&["check", "--profile", "ci", "--warnings", "warn"]);
+        assert!(!config.deny_warnings);
+        match config.cmd {
+            Subcommand::Check { all_targets, ref rustc_args, .. } => {
+                assert!(all_targets);
+                assert_eq!(*rustc_args, ["--cfg=ci"]);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn check_no_fail_fast() {
+        let fail_fast = |args: &[&str]| {
+            let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+            match Config::parse(&args).cmd {
+                Subcommand::Check { fail_fast, .. } => fail_fast,
+                _ => unreachable!(),
+            }
+        };
+        assert!(fail_fast(&["check", "--all-targets"]));
+        assert!(!fail_fast(&["check", "--all-targets", "--no-fail-fast"]));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn check_post_step_hooks_see_the_step() {
+        let mut config = configure("check", &["A"], &["A"]);
+        let out = config.out.join("hook-out");
+        let _ = fs::remove_file(&out);
+        let hooks = vec![
+            "false".to_string(),
+            format!(
+                "echo \"$BOOTSTRAP_HOOK_STEP $BOOTSTRAP_HOOK_TARGET $BOOTSTRAP_HOOK_STAMP \
+                 $BOOTSTRAP_HOOK_SYSROOT $BOOTSTRAP_HOOK_CRATES\" > {}",
+                out.display()
+            ),
+        ];
+        config.hooks_post_step.insert("check::Std".to_string(), hooks);
+        let mut build = Build::new(config);
+        build.config.dry_run = false;
+        let a = TargetSelection::from_user("A");
+        let crates = vec![INTERNER.intern_str("core"), INTERNER.intern_str("std")];
+        build.check_results.borrow_mut().insert(("check::Std", a), crates.into_iter().collect());
+
+        let stamp = PathBuf::from("/build/.libstd-check.stamp");
+        let sysroot = PathBuf::from("/build/stage0-sysroot");
+        crate::hooks::post_step(&build, "check::Rustc", a, &stamp, &sysroot);
+        assert!(!out.exists());
+        crate::hooks::post_step(&build, "check::Std", a, &stamp, &sysroot);
+        assert_eq!(
+            t!(fs::read_to_string(&out)),
+            "check::Std A /build/.libstd-check.stamp /build/stage0-sysroot core std\n"
+        );
+    }
+
+    #[test]
+    fn check_print_config_plans_steps() {
+        let mut config = configure("check", &["A"], &["A", "B"]);
+        if let Subcommand::Check { ref mut print_config, .. } = config.cmd {
+            *print_config = true;
+        }
+        let build = Build::new(config);
+        let builder = Builder::new(&build);
+        builder.run_step_descriptions(
+            &Builder::get_step_descriptions(Kind::Check),
+            &["library/std".into()],
+        );
+
+        let steps = build.planned_steps.borrow();
+        let planned = steps
+            .iter()
+            .map(|step| (&step.step[..], step.target.as_deref().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(planned, [("check::Std", "A"), ("check::Std", "B")]);
+        assert!(steps[0].stamp.ends_with("A/release/.libstd-check.stamp"));
+    }
+
+    #[test]
+    fn check_std_with_cfg_bootstrap() {
+        let host = TargetSelection::from_user("A");
+        let mut config = configure("check", &["A"], &["A"]);
+        if let Subcommand::Check { ref mut print_config, .. } = config.cmd {
+            *print_config = true;
+        }
+        let mut build = Build::new(config);
+        let builder = Builder::new(&build);
+        assert!(builder.cfg_bootstrap(Compiler { stage: 0, host }));
+        assert!(!builder.cfg_bootstrap(Compiler { stage: 1, host }));
+        builder.run_step_descriptions(
+            &Builder::get_step_descriptions(Kind::Check),
+            &["library/std".into()],
+        );
+        assert!(build.planned_steps.borrow()[0].rustflags.contains("--cfg=bootstrap"));
+
+        build.local_rebuild = true;
+        build.planned_steps.borrow_mut().clear();
+        let builder = Builder::new(&build);
+        assert!(!builder.cfg_bootstrap(Compiler { stage: 0, host }));
+        builder.run_step_descriptions(
+            &Builder::get_step_descriptions(Kind::Check),
+            &["library/std".into()],
+        );
+        assert!(!build.planned_steps.borrow()[0].rustflags.contains("--cfg=bootstrap"));
+    }
+
+    #[test]
+    fn check_std_as_a_library() {
+        let args = ["check".to_owned()];
+        let toml = "[build]\ntarget = [\"aarch64-unknown-linux-gnu\"]\n";
+        let config = Config::parse_with_toml(&args, toml).unwrap();
+        assert_eq!(config.targets, [TargetSelection::from_user("aarch64-unknown-linux-gnu")]);
+        assert!(Config::parse_with_toml(&args, "[build").is_err());
+
+        let build = Build::new(configure("check", &["A"], &["A"]));
+        let outcome = build.check_std("A").unwrap();
+        assert!(outcome.stamp.ends_with("A/release/.libstd-check.stamp"));
+        assert_eq!(outcome.diagnostics, 0);
+
+        let build = Build::new(configure("build", &["A"], &["A"]));
+        assert!(matches!(build.check_std("A"), Err(CargoFailure::Bootstrap(_))));
+    }
+
+    #[test]
+    fn check_std_with_target_rustflags() {
+        let args = ["check".to_owned()];
+        let toml = "[target.aarch64-unknown-linux-gnu]\nrustflags = [\"-Ctarget-cpu=native\"]\n";
+        let config = Config::parse_with_toml(&args, toml).unwrap();
+        let aarch64 = TargetSelection::from_user("aarch64-unknown-linux-gnu");
+        assert_eq!(config.target_config[&aarch64].rustflags, ["-Ctarget-cpu=native"]);
+
+        let mut config = configure("check", &["A"], &["A", "B"]);
+        let b = TargetSelection::from_user("B");
+        let target = config.target_config.entry(b).or_insert_with(|| Target::from_triple("B"));
+        target.rustflags = vec!["-Ctarget-cpu=native".to_owned()];
+        let build = Build::new(config);
+        let builder = Builder::new(&build);
+        let compiler = Compiler { stage: 0, host: TargetSelection::from_user("A") };
+        let mut cargo = builder.cargo(compiler, Mode::Std, SourceType::InTree, b, "check");
+        compile::std_cargo(&builder, b, 0, &mut cargo);
+        assert!(cargo.rustflags().ends_with(" -Ctarget-cpu=native"));
+
+        // They're left out of real builds, whose artifacts would change.
+        let mut config = configure("build", &["A"], &["A", "B"]);
+        let target = config.target_config.entry(b).or_insert_with(|| Target::from_triple("B"));
+        target.rustflags = vec!["-Ctarget-cpu=native".to_owned()];
+        let build_only = Build::new(config);
+        let builder = Builder::new(&build_only);
+        let mut cargo = builder.cargo(compiler, Mode::Std, SourceType::InTree, b, "build");
+        compile::std_cargo(&builder, b, 0, &mut cargo);
+        assert!(!cargo.rustflags().contains("-Ctarget-cpu=native"));
+
+        // Changing them makes for another stamp, the other targets keep theirs.
+        assert!(build.check_std("A").unwrap().stamp.ends_with("A/release/.libstd-check.stamp"));
+        let stamp = build.check_std("B").unwrap().stamp;
+        assert!(!stamp.ends_with("B/release/.libstd-check.stamp"));
+    }
+
+    #[test]
+    fn check_since_is_only_changed() {
+        let parse = |args: &[&str]| {
+            let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+            Config::parse(&args).cmd.only_changed().map(|base| base.to_string())
+        };
+        assert_eq!(parse(&["check", "--since", "origin/master"]).as_deref(), Some("origin/master"));
+        assert_eq!(parse(&["check", "--only-changed"]).as_deref(), Some("HEAD"));
+        assert_eq!(parse(&["clippy", "--only-changed", "--since", "x"]).as_deref(), Some("x"));
+        assert_eq!(parse(&["check"]), None);
+    }
+
+    #[test]
+    fn check_tools_with_sysroot() {
+        let mut config = configure("check", &["A"], &["A", "B"]);
+        let sysroot = config.out.join("known-good-sysroot");
+        let _ = fs::remove_dir_all(&sysroot);
+        let libdir = sysroot.join("lib/rustlib/B/lib");
+        t!(fs::create_dir_all(&libdir));
+        t!(fs::write(libdir.join("libstd-0123.rmeta"), ""));
+        let manifest = r#"{"kind": "check", "stage": 0, "target": "B",
+            "artifacts": [{"destination": "/elsewhere/libstd-0123.rmeta"}]}"#;
+        t!(fs::write(sysroot.join("lib/rustlib/B/sysroot.json"), manifest));
+
+        let (a, b) = (TargetSelection::from_user("A"), TargetSelection::from_user("B"));
+        assert_eq!(check::validate_sysroot(&sysroot, a, b), Ok(()));
+        let err = check::validate_sysroot(&sysroot, a, a).unwrap_err();
+        assert_eq!(err, "it was exported for B, not for A");
+
+        if let Subcommand::Check { ref mut with_sysroot, .. } = config.cmd {
+            *with_sysroot = Some(sysroot.clone());
+        }
+        let build = Build::new(config);
+        let mut builder = Builder::new(&build);
+        builder.ensure(check::Clippy { target: b });
+        assert!(builder.cache.all::<check::Rustc>().is_empty());
+        assert!(builder.cache.all::<check::Std>().is_empty());
+
+        t!(fs::remove_file(libdir.join("libstd-0123.rmeta")));
+        let err = check::validate_sysroot(&sysroot, a, b).unwrap_err();
+        assert_eq!(err, "libstd-0123.rmeta is missing, though its manifest lists it");
+    }
+
+    #[test]
+    fn check_like_commands_have_their_own_target_dirs() {
+        let compiler = Compiler { stage: 0, host: TargetSelection::from_user("A") };
+        let stage_dir = |cmd| {
+            let build = Build::new(configure(cmd, &["A"], &["A"]));
+            let out = build.check_stage_out(compiler, Mode::Std);
+            out.file_name().unwrap().to_str().unwrap().to_string()
+        };
+        assert_eq!(stage_dir("check"), "stage0-std");
+        assert_eq!(stage_dir("clippy"), "stage0-std-clippy");
+        assert_eq!(stage_dir("fix"), "stage0-std-fix");
+    }
+
+    #[test]
+    fn check_walks_crate_graph_once_per_root() {
+        let build = Build::new(configure("check", &["A", "B"], &["A", "B", "C"]));
+        let builder = Builder::new(&build);
+        builder.run_step_descriptions(&Builder::get_step_descriptions(Kind::Check), &[]);
+
+        let cache = build.in_tree_crates.borrow();
+        let mut roots = cache.keys().map(|root| &root[..]).collect::<Vec<_>>();
+        roots.sort();
+        assert_eq!(roots, ["rustc-main", "test"]);
+    }
+
+    #[test]
+    fn check_librustdoc_path() {
+        let build = Build::new(configure("check", &["A"], &["A"]));
+        let builder = Builder::new(&build);
+        builder.run_step_descriptions(
+            &Builder::get_step_descriptions(Kind::Check),
+            &["src/librustdoc".into()],
+        );
+
+        assert!(builder.cache.contains::<check::Rustdoc>());
+    }
+
+    #[test]
+    fn check_rustc_includes_main_binary() {
+        // A type error in `compiler/rustc/src/main.rs` is only caught if the
+        // `rustc-main` binary itself is part of the check.
+        let build = Build::new(configure("check", &["A"], &["A"]));
+        let builder = Builder::new(&build);
+        builder.run_step_descriptions(
+            &Builder::get_step_descriptions(Kind::Check),
+            &["compiler/rustc".into()],
+        );
+
+        assert!(builder.cache.contains::<check::Rustc>());
+        assert!(builder.checked_crates.borrow().iter().any(|krate| &krate[..] == "rustc-main"));
+    }
+
+    #[test]
+    fn check_proc_macros_for_host() {
+        let build = Build::new(configure("check", &["B"], &["B"]));
+        let mut builder = Builder::new(&build);
+        builder.run_step_descriptions(
+            &Builder::get_step_descriptions(Kind::Check),
+            &["compiler/rustc".into()],
+        );
+
+        // `rustc_macros` is checked for the host A, which needs std for it.
+        let a = TargetSelection::from_user("A");
+        let b = TargetSelection::from_user("B");
+        assert_eq!(
+            first(builder.cache.all::<check::Std>()),
+            &[
+                check::Std { target: a, check_test_targets: false },
+                check::Std { target: b, check_test_targets: false }
+            ]
+        );
+        assert!(builder.checked_crates.borrow().iter().any(|krate| &krate[..] == "rustc_macros"));
+    }
+
+    #[test]
+    fn check_rustdoc_cross() {
+        let build = Build::new(configure("check", &["B"], &["B"]));
+        let mut builder = Builder::new(&build);
+        builder.run_step_descriptions(
+            &Builder::get_step_descriptions(Kind::Check),
+            &["src/tools/rustdoc".into()],
+        );
+
+        // rustdoc for B links against the rustc crates checked for B, not
+        // for the host A the check runs on.
+        let b = TargetSelection::from_user("B");
+        assert_eq!(first(builder.cache.all::<check::Rustdoc>()), &[check::Rustdoc { target: b }]);
+        assert_eq!(first(builder.cache.all::<check::Rustc>()), &[check::Rustc { target: b }]);
+    }
+
+    fn configure_all_targets() -> Config {
+        let mut config = configure("check", &["A"], &["A"]);
+        if let Subcommand::Check { ref mut all_targets, .. } = config.cmd {
+            *all_targets = true;
+        }
+        config
+    }
+
+    #[test]
+    fn check_all_targets_of_compiler_skips_std_test_targets() {
+        let build = Build::new(configure_all_targets());
+        let mut builder = Builder::new(&build);
+        builder.run_step_descriptions(
+            &Builder::get_step_descriptions(Kind::Check),
+            &["compiler/rustc".into()],
+        );
+
+        let a = TargetSelection::from_user("A");
+        assert_eq!(
+            first(builder.cache.all::<check::Std>()),
+            &[check::Std { target: a, check_test_targets: false }]
+        );
+    }
+
+    #[test]
+    fn check_all_targets_of_std() {
+        let build = Build::new(configure_all_targets());
+        let mut builder = Builder::new(&build);
+        builder.run_step_descriptions(
+            &Builder::get_step_descriptions(Kind::Check),
+            &["library/std".into()],
+        );
+
+        let a = TargetSelection::from_user("A");
+        assert_eq!(
+            first(builder.cache.all::<check::Std>()),
+            &[
+                check::Std { target: a, check_test_targets: false },
+                check::Std { target: a, check_test_targets: true }
+            ]
+        );
+    }
+
+    #[test]
+    fn check_std_doctests_only_compile_examples() {
+        let dir = std::env::temp_dir().join(format!("bootstrap-doctests-{}", std::process::id()));
+        t!(fs::create_dir_all(&dir));
+        let source = dir.join("lib.rs");
+        let examples =
+            "/// ```should_panic\n/// panic!();\n/// ```\n///\n/// ```\n/// loop {}\n/// ```\n";
+        t!(fs::write(&source, format!("{}pub fn f() {{}}\n", examples)));
+
+        let rustc = PathBuf::from(std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into()));
+        let output = t!(std::process::Command::new(rustc.with_file_name("rustdoc"))
+            .args(check::DOCTEST_RUSTDOCFLAGS)
+            .arg("--test")
+            .arg(&source)
+            .arg("--crate-name=doctests")
+            .env("RUSTC_BOOTSTRAP", "1")
+            .output());
+        t!(fs::remove_dir_all(&dir));
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{}{}", stdout, String::from_utf8_lossy(&output.stderr));
+        assert!(stdout.contains("2 passed"), "{}", stdout);
+    }
+}
+
+mod clean {
+    use super::configure;
+    use crate::builder::*;
+    use crate::compile::encode_stamp;
+    use crate::DependencyType;
+
+    /// Writes the stamp `name` for std, listing an artifact of its own, and
+    /// returns both.
+    fn std_stamp(build: &Build, name: &str) -> (PathBuf, PathBuf) {
+        let compiler = Compiler { stage: 0, host: build.build };
+        let dir = build.check_cargo_out(compiler, Mode::Std, build.build);
+        let artifact = dir.join("deps").join(format!("libstd{}.rmeta", name));
+        let stamp = dir.join(name);
+        t!(fs::create_dir_all(artifact.parent().unwrap()));
+        t!(fs::write(&artifact, ""));
+        t!(fs::write(
+            &stamp,
+            encode_stamp(&[(artifact.clone(), DependencyType::Target)], &build.out)
+        ));
+        (stamp, artifact)
+    }
+
+    #[test]
+    fn stamps_of_other_check_variants_are_kept() {
+        let mut build = Build::new(configure("clean", &["A"], &["A"]));
+        build.config.dry_run = false;
+        let _ = fs::remove_dir_all(build.out.join("A"));
+
+        let kept = [
+            std_stamp(&build, ".libstd-check.stamp"),
+            std_stamp(&build, ".libstd-check-panic-abort.stamp"),
+            std_stamp(&build, ".libstd-check-test-panic-abort.stamp"),
+            std_stamp(&build, ".libstd-check-0123456789abcdef-panic-abort.stamp"),
+            std_stamp(&build, ".libstd-check-address.stamp"),
+            std_stamp(&build, ".libstd-check-0123456789abcdef-thread.stamp"),
+            std_stamp(&build, ".libstd-check-test-memory-panic-abort.stamp"),
+        ];
+        let (stale, stale_artifact) = std_stamp(&build, ".libstd-renamed-step.stamp");
+
+        crate::clean::clean(&build, false, true);
+        for (stamp, artifact) in &kept {
+            assert!(stamp.exists(), "{} was pruned", stamp.display());
+            assert!(artifact.exists(), "{} was pruned", artifact.display());
+        }
+        assert!(!stale.exists());
+        assert!(!stale_artifact.exists());
+    }
+}
@@ -1,38 +1,112 @@
 //! Implementation of compiling the compiler and standard library, in "check"-based modes.
 
-use crate::compile::{add_to_sysroot, run_cargo, rustc_cargo, std_cargo};
-use crate::config::TargetSelection;
+use crate::cache::{Interned, INTERNER};
+use crate::compile::{
+    self, add_to_sysroot, run_cargo, rustc_cargo, std_cargo, std_profile_cargo,
+    sysroot_fingerprint_path, try_run_cargo, CargoFailure, PlannedStep, SysrootFingerprint,
+};
+use crate::config::{StdProfile, TargetSelection};
 use crate::tool::{prepare_tool_cargo, SourceType};
 use crate::{
-    builder::{Builder, Kind, RunConfig, ShouldRun, Step},
+    builder::{Builder, Cargo, Kind, RunConfig, ShouldRun, Step},
     Subcommand,
 };
-use crate::{Compiler, Mode};
-use std::path::PathBuf;
+use crate::{hooks, native, util, Build, Compiler, Crate, Mode};
+use build_helper::{mtime, t};
+use filetime::FileTime;
+use serde::Serialize;
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, PartialOrd, Ord, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Std {
     pub target: TargetSelection,
+    /// Whether to also check the tests, benches and examples of the library
+    /// crates, for `--all-targets` runs that asked for std itself rather
+    /// than only for something depending on it.
+    pub check_test_targets: bool,
 }
 
-fn args(kind: Kind) -> Vec<String> {
-    match kind {
-        Kind::Clippy => vec!["--".to_owned(), "--cap-lints".to_owned(), "warn".to_owned()],
-        _ => Vec::new(),
+/// The result of checking std with `Build::check_std`.
+#[derive(Debug)]
+pub struct CheckOutcome {
+    pub duration: Duration,
+    /// The stamp listing the checked std artifacts.
+    pub stamp: PathBuf,
+    /// The number of warnings and errors rustc emitted.
+    pub diagnostics: usize,
+}
+
+/// Checks std for `target` with a `Builder` of its own, for `Build::check_std`.
+pub(crate) fn check_std(
+    build: &Build,
+    target: TargetSelection,
+) -> Result<CheckOutcome, CargoFailure> {
+    match build.config.cmd {
+        Subcommand::Check { .. } | Subcommand::Clippy { .. } | Subcommand::Fix { .. } => {}
+        _ => {
+            return Err(CargoFailure::Bootstrap(
+                "std can only be checked with a configuration parsed for `check`, `clippy` or \
+                 `fix`"
+                    .to_string(),
+            ));
+        }
     }
+    let builder = Builder::new(build);
+    let compiler = check_compiler(&builder);
+    let diagnostics = build.diagnostic_count.get();
+    let start = Instant::now();
+    builder.ensure(Std { target, check_test_targets: false })?;
+    Ok(CheckOutcome {
+        duration: start.elapsed(),
+        stamp: libstd_stamp(&builder, compiler, target),
+        diagnostics: build.diagnostic_count.get() - diagnostics,
+    })
 }
 
-fn cargo_subcommand(kind: Kind) -> &'static str {
+/// Ensures `step`, exiting with the exit code of its failure if it fails.
+fn ensure_std(builder: &Builder<'_>, step: Std) {
+    if let Err(failure) = builder.ensure(step) {
+        failure.exit(builder);
+    }
+}
+
+fn args(builder: &Builder<'_>) -> Vec<String> {
+    builder.kind.extra_cargo_args(&builder.config)
+}
+
+/// The Cargo subcommand the check steps run for `kind`, which is only known
+/// for the check-like commands.
+fn cargo_subcommand(kind: Kind) -> Result<&'static str, CargoFailure> {
     match kind {
-        Kind::Check => "check",
-        Kind::Clippy => "clippy",
-        Kind::Fix => "fix",
-        _ => unreachable!(),
+        Kind::Check => Ok("check"),
+        Kind::Clippy => Ok("clippy"),
+        Kind::Fix => Ok("fix"),
+        _ => Err(CargoFailure::Internal(format!(
+            "the check steps were run for `Kind::{:?}`, which `cargo_subcommand` in \
+             src/bootstrap/check.rs has no Cargo subcommand for",
+            kind
+        ))),
     }
 }
 
+/// The Cargo subcommand for the command `builder` runs, exiting with an
+/// internal error if the check steps don't handle it.
+fn subcommand(builder: &Builder<'_>) -> &'static str {
+    cargo_subcommand(builder.kind).unwrap_or_else(|failure| failure.exit(builder))
+}
+
 impl Step for Std {
-    type Output = ();
+    /// The failure is returned rather than reported, so that bootstrap can be
+    /// driven as a library; see `Build::check_std`.
+    type Output = Result<(), CargoFailure>;
     const DEFAULT: bool = true;
 
     fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
@@ -40,78 +114,466 @@ impl Step for Std {
     }
 
     fn make_run(run: RunConfig<'_>) {
-        run.builder.ensure(Std { target: run.target });
+        let check_test_targets =
+            matches!(run.builder.config.cmd, Subcommand::Check { all_targets: true, .. });
+        ensure_std(run.builder, Std { target: run.target, check_test_targets });
     }
 
-    fn run(self, builder: &Builder<'_>) {
+    fn run(self, builder: &Builder<'_>) -> Result<(), CargoFailure> {
         let target = self.target;
-        let compiler = builder.compiler(0, builder.config.build);
-
-        let mut cargo = builder.cargo(
-            compiler,
-            Mode::Std,
-            SourceType::InTree,
-            target,
-            cargo_subcommand(builder.kind),
-        );
-        std_cargo(builder, target, compiler.stage, &mut cargo);
-
-        builder.info(&format!("Checking std artifacts ({} -> {})", &compiler.host, target));
-        run_cargo(
-            builder,
-            cargo,
-            args(builder.kind),
-            &libstd_stamp(builder, compiler, target),
-            vec![],
-            true,
-        );
+        if self.check_test_targets {
+            // The library targets are checked by the plain step, which is
+            // also the one everything else depends on.
+            builder.ensure(Std { target, check_test_targets: false })?;
+            return check_std_test_targets(builder, target);
+        }
 
+        let compiler = check_compiler(builder);
+        check_stage0_target(builder, compiler, target)?;
+        let stamp = libstd_stamp(builder, compiler, target);
+        let forced = force::<Self>(builder, &stamp, &crate_names(builder, "test"));
         let libdir = builder.sysroot_libdir(compiler, target);
         let hostdir = builder.sysroot_libdir(compiler, compiler.host);
-        add_to_sysroot(&builder, &libdir, &hostdir, &libstd_stamp(builder, compiler, target));
 
-        // Then run cargo again, once we've put the rmeta files for the library
-        // crates into the sysroot. This is needed because e.g., core's tests
-        // depend on `libtest` -- Cargo presumes it will exist, but it doesn't
-        // since we initialize with an empty sysroot.
-        //
-        // Currently only the "libtest" tree of crates does this.
+        let selection = select_crates(builder, "test", target, &stamp);
+        if let CrateSelection::Unchanged = selection {
+            builder.info(&format!("Skipping std, no crates changed ({})", target));
+            builder.skip(&format!("{:?}", self), "no crates changed since the last check");
+            add_to_sysroot(&builder, &libdir, &hostdir, &stamp, compiler.stage, target);
+            export_std_sysroot(builder, compiler, target, &stamp);
+            return Ok(());
+        }
 
-        if let Subcommand::Check { all_targets: true, .. } = builder.config.cmd {
-            let mut cargo = builder.cargo(
-                compiler,
-                Mode::Std,
-                SourceType::InTree,
-                target,
-                cargo_subcommand(builder.kind),
-            );
+        let mut cargo =
+            builder.cargo(compiler, Mode::Std, SourceType::InTree, target, subcommand(builder));
+        std_cargo(builder, target, compiler.stage, &mut cargo);
+        add_rustc_args(builder, &mut cargo);
+        add_sanitizer(builder, &mut cargo);
+        selection.restrict(&mut cargo);
+
+        // Starting Cargo only to learn that nothing changed takes a while, so
+        // first see whether the inputs recorded by the last check still match.
+        let probe_key = fast_fresh_key(builder, &cargo);
+        let fast_fresh = builder.config.cmd.fast_fresh() && !builder.config.dry_run;
+        if fast_fresh && compile::probe_is_fresh(&stamp, probe_key, &builder.out) {
+            builder.info(&format!("Skipping std, its inputs are unchanged ({})", target));
+            builder.skip(&format!("{:?}", self), "its inputs are unchanged since the last check");
+        } else {
+            builder.info(&format!("Checking std artifacts ({} -> {})", &compiler.host, target));
+            explain_rebuild(builder, "std", &stamp, probe_key, forced_cause(forced));
+            report_cfg_bootstrap(builder, compiler, "std");
+            try_run_cargo(builder, cargo, args(builder), &stamp, vec![], true)?;
+            if !builder.config.dry_run {
+                compile::write_fresh_probe(&stamp, probe_key, &builder.out);
+            }
+        }
+        publish_checked(builder, "check::Std", target, selection.crates(builder, "test"));
+
+        add_to_sysroot(&builder, &libdir, &hostdir, &stamp, compiler.stage, target);
+        export_std_sysroot(builder, compiler, target, &stamp);
+
+        if let Subcommand::Check { doctests: true, .. } = builder.config.cmd {
+            let mut cargo = builder.cargo(compiler, Mode::Std, SourceType::InTree, target, "test");
+            // Cargo builds the libraries for real to test their examples, so
+            // keep those builds apart from the checked ones in the sysroot.
+            cargo.env("CARGO_TARGET_DIR", doctests_out(builder, compiler));
             std_cargo(builder, target, compiler.stage, &mut cargo);
-            cargo.arg("--all-targets");
+            add_rustc_args(builder, &mut cargo);
+            cargo.args(&["--doc", "--no-fail-fast"]);
+            for krate in selection.crates(builder, "test") {
+                cargo.arg("-p").arg(krate);
+            }
 
-            // Explicitly pass -p for all dependencies krates -- this will force cargo
-            // to also check the tests/benches/examples for these crates, rather
-            // than just the leaf crate.
-            for krate in builder.in_tree_crates("test") {
-                cargo.arg("-p").arg(krate.name);
+            // Examples are only compiled, as if they were all `no_run`, so
+            // `should_panic` ones don't fail for not having panicked.
+            check_rustdoc_no_run(builder, compiler)?;
+            for flag in DOCTEST_RUSTDOCFLAGS {
+                cargo.rustdocflag(flag);
             }
 
             builder.info(&format!(
-                "Checking std test/bench/example targets ({} -> {})",
+                "Checking std documentation examples ({} -> {})",
                 &compiler.host, target
             ));
-            run_cargo(
-                builder,
-                cargo,
-                args(builder.kind),
-                &libstd_test_stamp(builder, compiler, target),
-                vec![],
-                true,
-            );
+            let stamp = libstd_doctests_stamp(builder, compiler, target);
+            let result = try_run_cargo(builder, cargo, args(builder), &stamp, vec![], true);
+            if let Err(failure) = result {
+                eprintln!("error: std documentation examples failed to check");
+                return Err(failure);
+            }
+        }
+
+        if let Subcommand::Check { document_private_items: true, .. } = builder.config.cmd {
+            let mut cargo = builder.cargo(compiler, Mode::Std, SourceType::InTree, target, "doc");
+            std_cargo(builder, target, compiler.stage, &mut cargo);
+            let crates = selection.crates(builder, "test");
+            check_private_docs(builder, cargo, Mode::Std, compiler, target, &crates);
+        }
+
+        // The profile above is the one that ends up in the sysroot; any other
+        // profiles requested in config.toml are checked in their own target
+        // directory so they don't invalidate each other's artifacts.
+        for &profile in builder.config.rust_check_std_profiles.iter() {
+            if profile == builder.config.cmd.panic_strategy() {
+                continue;
+            }
+
+            let mut cargo =
+                builder.cargo(compiler, Mode::Std, SourceType::InTree, target, subcommand(builder));
+            cargo.env("CARGO_TARGET_DIR", profile_out(builder, compiler, profile));
+            std_profile_cargo(builder, target, compiler.stage, profile, &mut cargo);
+            add_rustc_args(builder, &mut cargo);
+            add_sanitizer(builder, &mut cargo);
+            selection.restrict(&mut cargo);
+
+            builder.info(&format!(
+                "Checking std artifacts with the {} profile ({} -> {})",
+                profile.name(),
+                &compiler.host,
+                target
+            ));
+            let stamp = libstd_profile_stamp(builder, compiler, target, profile);
+            let result = try_run_cargo(builder, cargo, args(builder), &stamp, vec![], true);
+            if let Err(failure) = result {
+                eprintln!("error: std failed to check with the {} profile", profile.name());
+                return Err(failure);
+            }
+        }
+
+        if builder.config.rust_check_facade_configs {
+            for facade in FACADE_CONFIGS {
+                let mut cargo = builder.cargo(
+                    compiler,
+                    Mode::Std,
+                    SourceType::InTree,
+                    target,
+                    subcommand(builder),
+                );
+                cargo.env("CARGO_TARGET_DIR", facade_out(builder, compiler, facade));
+                cargo
+                    .arg("--manifest-path")
+                    .arg(builder.src.join("library").join(facade.krate).join("Cargo.toml"))
+                    .arg("--no-default-features");
+                if !facade.features.is_empty() {
+                    cargo.arg("--features").arg(facade.features);
+                }
+                for cfg in facade.cfgs {
+                    cargo.rustflag(&format!("--cfg={}", cfg));
+                }
+                add_rustc_args(builder, &mut cargo);
+
+                builder.info(&format!(
+                    "Checking {} artifacts with the {} facade config ({} -> {})",
+                    facade.krate, facade.name, &compiler.host, target
+                ));
+                let stamp = facade_stamp(builder, compiler, target, facade);
+                let result = try_run_cargo(builder, cargo, args(builder), &stamp, vec![], true);
+                if let Err(failure) = result {
+                    eprintln!(
+                        "error: {} failed to check with the {} facade config",
+                        facade.krate, facade.name
+                    );
+                    return Err(failure);
+                }
+            }
+        }
+
+        post_step_hooks(builder, "check::Std", target, &stamp);
+        Ok(())
+    }
+}
+
+/// Says which side of `cfg(bootstrap)` the check of `what` with `compiler`
+/// covers, since the other side goes unchecked.
+fn report_cfg_bootstrap(builder: &Builder<'_>, compiler: Compiler, what: &str) {
+    let cfg = if builder.cfg_bootstrap(compiler) { "bootstrap" } else { "not(bootstrap)" };
+    builder.verbose(&format!("checking the code of {} under cfg({})", what, cfg));
+}
+
+/// Runs rustdoc over `crates` with the `doc` command `cargo`, private items
+/// included, for `--document-private-items`. The docs are only built to see
+/// that they do, in a directory of their own.
+fn check_private_docs(
+    builder: &Builder<'_>,
+    mut cargo: Cargo,
+    mode: Mode,
+    compiler: Compiler,
+    target: TargetSelection,
+    crates: &[Interned<String>],
+) {
+    let what = if mode == Mode::Std { "std" } else { "compiler" };
+    let out = private_docs_out(builder, compiler, mode);
+    cargo.env("CARGO_TARGET_DIR", &out);
+    cargo.arg("--no-deps");
+    for krate in crates {
+        cargo.arg("-p").arg(krate);
+    }
+    cargo.rustdocflag("--document-private-items");
+
+    builder.info(&format!(
+        "Checking {} documentation with private items ({} -> {})",
+        what, &compiler.host, target
+    ));
+    let stamp = out
+        .join(&*target.triple)
+        .join(builder.cargo_dir())
+        .join(format!(".{}-private-docs.stamp", what));
+    let result = try_run_cargo(builder, cargo, args(builder), &stamp, vec![], true);
+    if let Err(failure) = result {
+        eprintln!("error: the {} documentation failed to build", what);
+        failure.exit(builder);
+    }
+}
+
+/// The flags making rustdoc compile documentation examples without running
+/// them, for `--doctests`.
+pub(crate) const DOCTEST_RUSTDOCFLAGS: &[&str] = &["-Zunstable-options", "--no-run"];
+
+/// Makes sure the rustdoc of `compiler` knows `--no-run`, which rustdocs
+/// older than this tree's don't, rather than have it fail on an unknown flag.
+fn check_rustdoc_no_run(builder: &Builder<'_>, compiler: Compiler) -> Result<(), CargoFailure> {
+    if builder.config.dry_run {
+        return Ok(());
+    }
+    let output = t!(builder.rustdoc_cmd(compiler).arg("-Zunstable-options").arg("--help").output());
+    if String::from_utf8_lossy(&output.stdout).contains("--no-run") {
+        return Ok(());
+    }
+    Err(CargoFailure::Bootstrap(format!(
+        "`--doctests` needs a rustdoc that can compile examples without running them, which \
+         {} can't\nhelp: build a stage 1 rustdoc with `./x.py build --stage 1 src/tools/rustdoc` \
+         and set `build.rustc` to the rustc next to it",
+        builder.rustdoc(compiler).display()
+    )))
+}
+
+/// Makes sure the stage0 compiler knows `target`, as Cargo otherwise fails
+/// checking std for it with a long error about std missing for that target.
+fn check_stage0_target(
+    builder: &Builder<'_>,
+    compiler: Compiler,
+    target: TargetSelection,
+) -> Result<(), CargoFailure> {
+    if builder.config.dry_run || compiler.stage != 0 || target == compiler.host {
+        return Ok(());
+    }
+    let output = t!(Command::new(&builder.initial_rustc)
+        .arg("--target")
+        .arg(target.rustc_target_arg())
+        .arg("--print")
+        .arg("cfg")
+        .output());
+    if output.status.success() {
+        return Ok(());
+    }
+    let mut msg = format!("the stage0 compiler doesn't support the target {}", target);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if let Some(line) = stderr.lines().find(|line| !line.trim().is_empty()) {
+        msg.push_str(&format!("\nnote: {} said: {}", builder.initial_rustc.display(), line.trim()));
+    }
+    msg.push_str(&format!(
+        "\nhelp: pass a target specification instead, e.g. `--target path/to/{}.json`, and add \
+         it to `build.target` in config.toml",
+        target.triple
+    ));
+    msg.push_str(
+        "\nhelp: or build a stage 1 compiler with `./x.py build --stage 1 compiler/rustc`, which \
+         knows every target of this tree, and set `build.rustc` to it",
+    );
+    Err(CargoFailure::Bootstrap(msg))
+}
+
+/// Copies the sysroot std was just added to into the directory given with
+/// `--export-sysroot`, for out-of-tree consumers of the checked std.
+fn export_std_sysroot(
+    builder: &Builder<'_>,
+    compiler: Compiler,
+    target: TargetSelection,
+    stamp: &Path,
+) {
+    if let Subcommand::Check { export_sysroot: Some(ref dir), .. } = builder.config.cmd {
+        let dir = t!(env::current_dir()).join(dir);
+        compile::export_sysroot(builder, &dir, stamp, compiler.stage, compiler.host, target);
+        builder.info(&format!(
+            "Exported the checked std for {} to {}, pass `--sysroot {}` to rustc to use it",
+            target,
+            dir.display(),
+            dir.display()
+        ));
+    }
+}
+
+/// The sysroot given with `--with-sysroot` to check the tools for `target`
+/// against, after making sure it was exported for `target`.
+fn given_sysroot(
+    builder: &Builder<'_>,
+    compiler: Compiler,
+    target: TargetSelection,
+) -> Option<PathBuf> {
+    let dir = match builder.config.cmd {
+        Subcommand::Check { with_sysroot: Some(ref dir), .. } => t!(env::current_dir()).join(dir),
+        _ => return None,
+    };
+    if let Err(msg) = validate_sysroot(&dir, compiler.host, target) {
+        eprintln!("error: can't check against the sysroot at {}: {}", dir.display(), msg);
+        eprintln!(
+            "help: export it with `./x.py check library/std --target {} --export-sysroot {}`",
+            target,
+            dir.display()
+        );
+        process::exit(2);
+    }
+    Some(dir)
+}
+
+/// Makes sure the sysroot at `dir` was exported for `target` by `host`, and
+/// still holds every artifact its manifest lists.
+pub(crate) fn validate_sysroot(
+    dir: &Path,
+    host: TargetSelection,
+    target: TargetSelection,
+) -> Result<(), String> {
+    let rustlib = dir.join("lib").join("rustlib");
+    let manifest = rustlib.join(&*target.triple).join("sysroot.json");
+    let manifest = match fs::read_to_string(&manifest) {
+        Ok(manifest) => manifest,
+        Err(_) => {
+            let mut exported = fs::read_dir(&rustlib)
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().join("sysroot.json").exists())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            exported.sort();
+            return Err(if exported.is_empty() {
+                "it has no sysroot manifest, it wasn't exported with `--export-sysroot`".to_string()
+            } else {
+                format!("it was exported for {}, not for {}", exported.join(", "), target)
+            });
+        }
+    };
+    let manifest: compile::ExportedSysroot = serde_json::from_str(&manifest)
+        .map_err(|err| format!("its manifest for {} is invalid: {}", target, err))?;
+    if manifest.target != *target.triple {
+        return Err(format!(
+            "its manifest for {} describes artifacts for {}",
+            target, manifest.target
+        ));
+    }
+
+    // The artifacts are looked up by name, so the sysroot may have moved.
+    let target_lib = rustlib.join(&*target.triple).join("lib");
+    let dirs =
+        [target_lib.join("self-contained"), rustlib.join(&*host.triple).join("lib"), target_lib];
+    for artifact in &manifest.artifacts {
+        let name = match artifact.destination.file_name() {
+            Some(name) => name,
+            None => continue,
+        };
+        if !dirs.iter().any(|dir| dir.join(name).exists()) {
+            return Err(format!(
+                "{} is missing, though its manifest lists it",
+                Path::new(name).display()
+            ));
         }
     }
+    Ok(())
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// The second std pass of `--all-targets`, for the tests, benches and examples
+/// of the library crates.
+///
+/// It only runs once the rmeta files for the library crates are in the
+/// sysroot. This is needed because e.g., core's tests depend on `libtest` --
+/// Cargo presumes it will exist, but it doesn't since we initialize with an
+/// empty sysroot.
+fn check_std_test_targets(
+    builder: &Builder<'_>,
+    target: TargetSelection,
+) -> Result<(), CargoFailure> {
+    let compiler = check_compiler(builder);
+    let selection =
+        select_crates(builder, "test", target, &libstd_stamp(builder, compiler, target));
+    if let CrateSelection::Unchanged = selection {
+        let step = format!("{:?}", Std { target, check_test_targets: true });
+        builder.skip(&step, "no crates changed since the last check");
+        return Ok(());
+    }
+
+    let mut cargo =
+        builder.cargo(compiler, Mode::Std, SourceType::InTree, target, subcommand(builder));
+    // This shares the target directory, features and flags with the
+    // primary pass, so Cargo finds the library units it checked
+    // there fresh. Selecting only the test-like targets rather than
+    // `--all-targets` keeps the plain library targets out of this pass
+    // entirely; they're still pulled in as (fresh) dependencies.
+    std_cargo(builder, target, compiler.stage, &mut cargo);
+    add_rustc_args(builder, &mut cargo);
+    add_sanitizer(builder, &mut cargo);
+    cargo.args(&["--tests", "--benches", "--examples"]);
+    // With `--no-fail-fast`, cargo carries on with the other targets once one
+    // fails to compile, so that all of their errors are reported.
+    if let Subcommand::Check { fail_fast: false, keep_going: false, .. } = builder.config.cmd {
+        cargo.arg("--keep-going").arg("-Zunstable-options");
+    }
+
+    // Explicitly pass -p for all dependencies krates -- this will force cargo
+    // to also check the tests/benches/examples for these crates, rather
+    // than just the leaf crate.
+    for krate in selection.crates(builder, "test") {
+        cargo.arg("-p").arg(krate);
+    }
+
+    builder.info(&format!(
+        "Checking std test/bench/example targets ({} -> {})",
+        &compiler.host, target
+    ));
+    let stamp = libstd_test_stamp(builder, compiler, target);
+    try_run_cargo(builder, cargo, args(builder), &stamp, vec![], true)?;
+    Ok(())
+}
+
+/// A configuration of one of the `#![no_std]` facade crates that isn't part
+/// of any regular build, checked with `rust.check-facade-configs` so that code
+/// only compiled in it doesn't rot.
+struct FacadeConfig {
+    /// Names the configuration in messages, its target directory and stamp.
+    name: &'static str,
+    krate: &'static str,
+    /// Cargo features, enabled on top of `--no-default-features`.
+    features: &'static str,
+    cfgs: &'static [&'static str],
+}
+
+const FACADE_CONFIGS: &[FacadeConfig] = &[
+    FacadeConfig {
+        name: "no-default-features",
+        krate: "alloc",
+        features: "compiler-builtins-mem",
+        cfgs: &[],
+    },
+    FacadeConfig {
+        name: "no-global-oom-handling",
+        krate: "alloc",
+        features: "compiler-builtins-mem",
+        cfgs: &["no_global_oom_handling"],
+    },
+    FacadeConfig {
+        name: "no-global-oom-handling",
+        krate: "core",
+        features: "",
+        cfgs: &["no_global_oom_handling"],
+    },
+    FacadeConfig {
+        name: "panic-immediate-abort",
+        krate: "core",
+        features: "panic_immediate_abort",
+        cfgs: &[],
+    },
+];
+
+#[derive(Debug, PartialOrd, Ord, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Rustc {
     pub target: TargetSelection,
 }
@@ -135,19 +597,48 @@ impl Step for Rustc {
     /// the `compiler` targeting the `target` architecture. The artifacts
     /// created will also be linked into the sysroot directory.
     fn run(self, builder: &Builder<'_>) {
-        let compiler = builder.compiler(0, builder.config.build);
+        let compiler = check_compiler(builder);
         let target = self.target;
 
-        builder.ensure(Std { target });
+        let std_stamp = libstd_stamp(builder, compiler, target);
+        let reuse_std =
+            matches!(builder.config.cmd, Subcommand::Check { skip_std_check: true, .. })
+                && stamp_is_intact(builder, &std_stamp);
+        if reuse_std {
+            // The sysroot libdir is recreated on first use, so the rmeta files
+            // still need to be put back even though std isn't re-checked.
+            builder.info(&format!("Reusing checked std artifacts ({})", target));
+            let libdir = builder.sysroot_libdir(compiler, target);
+            let hostdir = builder.sysroot_libdir(compiler, compiler.host);
+            add_to_sysroot(&builder, &libdir, &hostdir, &std_stamp, compiler.stage, target);
+        } else {
+            ensure_std(builder, Std { target, check_test_targets: false });
+        }
 
-        let mut cargo = builder.cargo(
-            compiler,
-            Mode::Rustc,
-            SourceType::InTree,
-            target,
-            cargo_subcommand(builder.kind),
-        );
+        let stamp = librustc_stamp(builder, compiler, target);
+        let forced = force::<Self>(builder, &stamp, &crate_names(builder, "rustc-main"));
+        let libdir = builder.sysroot_libdir(compiler, target);
+        let hostdir = builder.sysroot_libdir(compiler, compiler.host);
+
+        let selection = select_crates(builder, "rustc-main", target, &stamp);
+        if let CrateSelection::Unchanged = selection {
+            builder.info(&format!("Skipping compiler, no crates changed ({})", target));
+            builder.skip(&format!("{:?}", self), "no crates changed since the last check");
+            add_to_sysroot(&builder, &libdir, &hostdir, &stamp, compiler.stage, target);
+            return;
+        }
+
+        let codegen = ["rustc_llvm", "rustc_codegen_llvm"];
+        if selection.crates(builder, "rustc-main").iter().any(|krate| codegen.contains(&&krate[..]))
+        {
+            check_llvm_prerequisites(builder, target);
+        }
+
+        let mut cargo =
+            builder.cargo(compiler, Mode::Rustc, SourceType::InTree, target, subcommand(builder));
         rustc_cargo(builder, &mut cargo, target);
+        add_rustc_args(builder, &mut cargo);
+        add_rustc_features(builder, &mut cargo);
         if let Subcommand::Check { all_targets: true, .. } = builder.config.cmd {
             cargo.arg("--all-targets");
         }
@@ -155,29 +646,122 @@ impl Step for Rustc {
         // Explicitly pass -p for all compiler krates -- this will force cargo
         // to also check the tests/benches/examples for these crates, rather
         // than just the leaf crate.
-        for krate in builder.in_tree_crates("rustc-main") {
-            cargo.arg("-p").arg(krate.name);
+        let mut crates = selection.crates(builder, "rustc-main");
+        // `rustc-main` is the binary with the driver glue and the
+        // `#[global_allocator]`, and nothing depends on it, so make sure it's
+        // checked rather than relying on it being the root of the tree. Its
+        // features are the ones `rustc_cargo` passes, as it's the package of
+        // the manifest.
+        let main = INTERNER.intern_str("rustc-main");
+        if let CrateSelection::All = selection {
+            if !crates.contains(&main) {
+                crates.insert(0, main);
+            }
+        }
+        // Proc-macros run on the host, so when cross-checking they're checked
+        // for the host on their own instead.
+        let (proc_macros, crates): (Vec<_>, Vec<_>) = crates
+            .into_iter()
+            .partition(|krate| target != compiler.host && builder.crate_graph()[krate].proc_macro);
+        for &krate in &crates {
+            cargo.arg("-p").arg(krate);
         }
+        publish_checked(builder, "check::Rustc", target, crates.clone());
 
+        let probe_key = fast_fresh_key(builder, &cargo);
         builder.info(&format!("Checking compiler artifacts ({} -> {})", &compiler.host, target));
-        run_cargo(
-            builder,
-            cargo,
-            args(builder.kind),
-            &librustc_stamp(builder, compiler, target),
-            vec![],
-            true,
-        );
+        explain_rebuild(builder, "the compiler", &stamp, probe_key, forced_cause(forced));
+        report_cfg_bootstrap(builder, compiler, "the compiler");
+        run_cargo(builder, cargo, args(builder), &stamp, vec![], true);
+        if !builder.config.dry_run {
+            compile::write_fresh_probe(&stamp, probe_key, &builder.out);
+        }
 
-        let libdir = builder.sysroot_libdir(compiler, target);
-        let hostdir = builder.sysroot_libdir(compiler, compiler.host);
-        add_to_sysroot(&builder, &libdir, &hostdir, &librustc_stamp(builder, compiler, target));
+        add_to_sysroot(&builder, &libdir, &hostdir, &stamp, compiler.stage, target);
+
+        if let Subcommand::Check { document_private_items: true, .. } = builder.config.cmd {
+            let mut cargo = builder.cargo(compiler, Mode::Rustc, SourceType::InTree, target, "doc");
+            rustc_cargo(builder, &mut cargo, target);
+            check_private_docs(builder, cargo, Mode::Rustc, compiler, target, &crates);
+        }
+        if !proc_macros.is_empty() {
+            check_proc_macros(builder, compiler, &proc_macros);
+            publish_checked(builder, "check::Rustc", target, proc_macros);
+        }
+
+        post_step_hooks(builder, "check::Rustc", target, &stamp);
+    }
+}
+
+/// Makes sure the LLVM the codegen crates are checked against for `target`
+/// is usable. Without a built LLVM, `rustc_llvm` skips it while checking, so
+/// only an `llvm-config` that was configured but doesn't exist is a problem.
+fn check_llvm_prerequisites(builder: &Builder<'_>, target: TargetSelection) {
+    if builder.config.dry_run || !builder.config.llvm_enabled() {
+        return;
+    }
+    let configured = builder.config.target_config.get(&target).and_then(|c| c.llvm_config.as_ref());
+    if let Some(llvm_config) = configured {
+        if !llvm_config.exists() {
+            eprintln!(
+                "error: can't check rustc_codegen_llvm for {}, the llvm-config configured for \
+                 it doesn't exist: {}",
+                target,
+                llvm_config.display()
+            );
+            eprintln!(
+                "help: fix `target.{}.llvm-config` in config.toml, or remove it to check the \
+                 compiler without LLVM",
+                target
+            );
+            process::exit(2);
+        }
+    } else if native::prebuilt_llvm_config(builder, target).is_err() {
+        builder
+            .verbose(&format!("LLVM isn't built for {}, checking rustc_llvm without it", target));
     }
 }
 
+/// Checks the compiler's proc-macro crates `krates` for the host `compiler`
+/// runs on.
+fn check_proc_macros(builder: &Builder<'_>, compiler: Compiler, krates: &[Interned<String>]) {
+    let host = compiler.host;
+    ensure_std(builder, Std { target: host, check_test_targets: false });
+
+    let mut cargo =
+        builder.cargo(compiler, Mode::Rustc, SourceType::InTree, host, subcommand(builder));
+    rustc_cargo(builder, &mut cargo, host);
+    add_rustc_args(builder, &mut cargo);
+    add_rustc_features(builder, &mut cargo);
+    if let Subcommand::Check { all_targets: true, .. } = builder.config.cmd {
+        cargo.arg("--all-targets");
+    }
+    for krate in krates {
+        cargo.arg("-p").arg(krate);
+    }
+
+    builder.info(&format!("Checking compiler proc-macros ({} -> {})", host, host));
+    run_cargo(
+        builder,
+        cargo,
+        args(builder),
+        &librustc_proc_macro_stamp(builder, compiler),
+        vec![],
+        true,
+    );
+}
+
 macro_rules! tool_check_step {
-    ($name:ident, $path:expr, $source_type:expr) => {
-        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+    (
+        $name:ident,
+        $path:expr,
+        $source_type:expr,
+        $mode:expr
+        $(, lib_package = $lib:expr $(, lib_path = $lib_path:expr)?)?
+        $(, packages = [$($package:expr),*])?
+        $(, members = [$($member:expr),*] $(, lib_members = [$($lib_member:expr),*])?)?
+    ) => {
+        #[derive(Debug, PartialOrd, Ord, Copy, Clone, PartialEq, Eq, Hash)]
         pub struct $name {
             pub target: TargetSelection,
         }
@@ -188,7 +772,10 @@ macro_rules! tool_check_step {
             const DEFAULT: bool = true;
 
             fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
-                run.path($path)
+                // Tools in submodules that aren't checked out are only checked
+                // when asked for explicitly.
+                let present = run.builder.src.join($path).join("Cargo.toml").exists();
+                run.paths(&[$path $($(, $lib_path)?)?]).default_condition(present)
             }
 
             fn make_run(run: RunConfig<'_>) {
@@ -196,88 +783,1029 @@ macro_rules! tool_check_step {
             }
 
             fn run(self, builder: &Builder<'_>) {
-                let compiler = builder.compiler(0, builder.config.build);
+                let compiler = check_compiler(builder);
                 let target = self.target;
 
-                builder.ensure(Rustc { target });
+                // The tool is checked for `target`, so it's the rustc_private
+                // crates for `target` it links against. The host-side ones it
+                // may need, the compiler's proc-macros, come along with them.
+                let sysroot = given_sysroot(builder, compiler, target);
+                if sysroot.is_none() {
+                    match $mode {
+                        Mode::ToolRustc => builder.ensure(Rustc { target }),
+                        _ => ensure_std(builder, Std { target, check_test_targets: false }),
+                    }
+                }
+
+                ensure_tool_source(builder, stringify!($name), $path);
 
                 let mut cargo = prepare_tool_cargo(
                     builder,
                     compiler,
-                    Mode::ToolRustc,
+                    $mode,
                     target,
-                    cargo_subcommand(builder.kind),
+                    subcommand(builder),
                     $path,
                     $source_type,
                     &[],
                 );
+                if let Some(ref sysroot) = sysroot {
+                    cargo.env("RUSTC_SYSROOT", sysroot);
+                }
+
+                if let Subcommand::Check { all_targets, ref tool_target_args, .. } =
+                    builder.config.cmd
+                {
+                    if all_targets {
+                        cargo.arg("--all-targets");
+                    }
+                    cargo.args(tool_target_args);
+                    $(
+                        if tool_target_args.iter().any(|arg| arg == "--lib") {
+                            cargo.arg("-p").arg($lib);
+                        } else if all_targets {
+                            // Cargo only looks at the targets of the packages
+                            // it's given, so name both to get the library's
+                            // own tests and benches as well.
+                            cargo.arg("-p").arg($lib);
+                            if let Some(tool) = package_at(builder, $path) {
+                                cargo.arg("-p").arg(tool);
+                            }
+                        }
+                    )?
+                }
 
+                // Only the crates the tool declares can be picked with `-p`,
+                // the others belong to other steps.
+                let packages: &[&str] = &[$($($package),*)?];
+                let members: &[&str] = &[$($($member),*)?];
+                let picked = builder.config.cmd.packages();
+                for package in &picked {
+                    if packages.contains(package) || members.contains(package) {
+                        cargo.arg("-p").arg(package);
+                    }
+                }
+                // Cargo only covers the targets of the tool's own package by
+                // default, so name all of its members to get their tests and
+                // benches as well.
                 if let Subcommand::Check { all_targets: true, .. } = builder.config.cmd {
-                    cargo.arg("--all-targets");
+                    if picked.is_empty() {
+                        for member in members {
+                            cargo.arg("-p").arg(member);
+                        }
+                    }
+                }
+                // Picking a member that's a library with binaries next to it
+                // checks only the library, unless other targets were asked for.
+                let lib_members: &[&str] = &[$($($($lib_member),*)?)?];
+                if let Subcommand::Check { all_targets: false, ref tool_target_args, .. } =
+                    builder.config.cmd
+                {
+                    let picked_lib = picked.iter().any(|package| lib_members.contains(package));
+                    if picked_lib && tool_target_args.is_empty() {
+                        cargo.arg("--lib");
+                    }
                 }
 
+                let mut crates = vec![$($lib,)? $($($package,)*)? $($($member,)*)?];
+                let tool = package_at(builder, $path);
+                crates.extend(tool.as_ref().map(|tool| &tool[..]));
+                let stamp = $name::stamp(builder, compiler, target);
+                let checked_before = stamp.exists();
+                let forced = force::<Self>(builder, &stamp, &crates);
+                let rustc_hash = match $mode {
+                    Mode::ToolRustc if sysroot.is_none() => {
+                        let rustc_stamp = librustc_stamp(builder, compiler, target);
+                        check_against_rustc(builder, &stamp, &rustc_stamp, &crates)
+                    }
+                    _ => None,
+                };
+                let cause = if !forced && checked_before && !stamp.exists() {
+                    Some("the compiler crates it links against changed")
+                } else {
+                    forced_cause(forced)
+                };
+
+                let probe_key = fast_fresh_key(builder, &cargo);
+                let name = stringify!($name).to_lowercase();
                 builder.info(&format!(
                     "Checking {} artifacts ({} -> {})",
-                    stringify!($name).to_lowercase(),
+                    name,
                     &compiler.host.triple,
                     target.triple
                 ));
-                run_cargo(
-                    builder,
-                    cargo,
-                    args(builder.kind),
-                    &stamp(builder, compiler, target),
-                    vec![],
-                    true,
-                );
+                explain_rebuild(builder, &name, &stamp, probe_key, cause);
+                let result = try_run_cargo(builder, cargo, args(builder), &stamp, vec![], true);
+                if let Err(failure) = result {
+                    if $mode == Mode::ToolRustc {
+                        report_rustc_fingerprint(builder, compiler, target);
+                    }
+                    failure.exit(builder);
+                }
+                if let Some(hash) = rustc_hash {
+                    t!(fs::write(rustc_hash_path(&stamp), hash));
+                }
+                if !builder.config.dry_run {
+                    compile::write_fresh_probe(&stamp, probe_key, &builder.out);
+                }
 
                 let libdir = builder.sysroot_libdir(compiler, target);
                 let hostdir = builder.sysroot_libdir(compiler, compiler.host);
-                add_to_sysroot(&builder, &libdir, &hostdir, &stamp(builder, compiler, target));
-
-                /// Cargo's output path in a given stage, compiled by a particular
-                /// compiler for the specified target.
-                fn stamp(
-                    builder: &Builder<'_>,
-                    compiler: Compiler,
-                    target: TargetSelection,
-                ) -> PathBuf {
-                    builder
-                        .cargo_out(compiler, Mode::ToolRustc, target)
-                        .join(format!(".{}-check.stamp", stringify!($name).to_lowercase()))
-                }
+                add_to_sysroot(&builder, &libdir, &hostdir, &stamp, compiler.stage, target);
+                post_step_hooks(builder, concat!("check::", stringify!($name)), target, &stamp);
             }
         }
+
+        impl $name {
+            /// Cargo's output path in a given stage, compiled by a particular
+            /// compiler for the specified target.
+            fn stamp(build: &Build, compiler: Compiler, target: TargetSelection) -> PathBuf {
+                build
+                    .check_cargo_out(compiler, $mode, target)
+                    .join(format!(".{}-check.stamp", stringify!($name).to_lowercase()))
+            }
+        }
+    };
+}
+
+/// Defines the check steps of all the tools, along with `TOOL_PATHS` listing
+/// their paths for `x.py check --help`.
+macro_rules! tool_check_steps {
+    ($($name:ident($path:expr, $($args:tt)*);)*) => {
+        $(tool_check_step!($name, $path, $($args)*);)*
+
+        /// The paths of the tools `x.py check` can check.
+        pub const TOOL_PATHS: &[&str] = &[$($path),*];
     };
 }
 
-tool_check_step!(Rustdoc, "src/tools/rustdoc", SourceType::InTree);
-// Clippy is a hybrid. It is an external tool, but uses a git subtree instead
-// of a submodule. Since the SourceType only drives the deny-warnings
-// behavior, treat it as in-tree so that any new warnings in clippy will be
-// rejected.
-tool_check_step!(Clippy, "src/tools/clippy", SourceType::InTree);
+tool_check_steps! {
+    // The rustdoc tool is only a binary wrapping librustdoc, so `--lib` checks
+    // that, and `x.py check src/librustdoc` is the same step.
+    Rustdoc(
+        "src/tools/rustdoc",
+        SourceType::InTree,
+        Mode::ToolRustc,
+        lib_package = "rustdoc",
+        lib_path = "src/librustdoc"
+    );
+    // Clippy is a hybrid. It is an external tool, but uses a git subtree instead
+    // of a submodule. Since the SourceType only drives the deny-warnings
+    // behavior, treat it as in-tree so that any new warnings in clippy will be
+    // rejected.
+    Clippy("src/tools/clippy", SourceType::InTree, Mode::ToolRustc);
+    // Rustfmt links against the rustc_private crates like clippy, but lives in
+    // a submodule. Its stamp is `.rustfmt-check.stamp`, which doesn't clash with
+    // the `rustfmt` and `cargo-fmt` binaries `tool::Rustfmt` builds next to it.
+    // `-p rustfmt-nightly` checks the library those binaries wrap on its own.
+    Rustfmt(
+        "src/tools/rustfmt",
+        SourceType::Submodule,
+        Mode::ToolRustc,
+        members = ["rustfmt-nightly", "rustfmt-config_proc_macro"],
+        lib_members = ["rustfmt-nightly"]
+    );
 
-tool_check_step!(Bootstrap, "src/bootstrap", SourceType::InTree);
+    Bootstrap("src/bootstrap", SourceType::InTree, Mode::ToolRustc);
+    CargoTest("src/tools/cargotest", SourceType::InTree, Mode::ToolBootstrap);
+    // Miri's submodule holds both the driver and the `cargo miri` front-end,
+    // either of which can be checked on its own with `-p`.
+    Miri(
+        "src/tools/miri",
+        SourceType::Submodule,
+        Mode::ToolRustc,
+        packages = ["miri", "cargo-miri"]
+    );
+    // Only linkchecker's own sources are checked here; the generated docs it
+    // runs against are only needed by `x.py test src/tools/linkchecker`.
+    Linkchecker("src/tools/linkchecker", SourceType::InTree, Mode::ToolBootstrap);
+    UnstableBookGen("src/tools/unstable-book-gen", SourceType::InTree, Mode::ToolBootstrap);
+    // Rustbook only wraps mdbook, which is built without its default features.
+    RustBook("src/tools/rustbook", SourceType::InTree, Mode::ToolBootstrap);
+}
+
+/// Implementation of `x.py check --manifest-path`: checks a crate none of the
+/// other steps know about like the tools linking against the compiler are.
+#[derive(Debug, PartialOrd, Ord, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Manifest {
+    pub target: TargetSelection,
+}
+
+impl Step for Manifest {
+    type Output = ();
+
+    fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
+        run.never()
+    }
+
+    fn make_run(run: RunConfig<'_>) {
+        run.builder.ensure(Manifest { target: run.target });
+    }
+
+    fn run(self, builder: &Builder<'_>) {
+        let path = match builder.config.cmd {
+            Subcommand::Check { manifest_path: Some(ref path), .. } => path,
+            _ => return,
+        };
+        let dir = if path.file_name().map_or(false, |name| name == "Cargo.toml") {
+            path.parent().unwrap()
+        } else {
+            path
+        };
+        if !builder.config.dry_run && !builder.src.join(dir).join("Cargo.toml").exists() {
+            eprintln!("error: can't check {}, it has no Cargo.toml", dir.display());
+            process::exit(2);
+        }
+        let compiler = check_compiler(builder);
+        let target = self.target;
+
+        let sysroot = given_sysroot(builder, compiler, target);
+        if sysroot.is_none() {
+            builder.ensure(Rustc { target });
+        }
+
+        // The crate isn't one of ours, so its warnings are left alone.
+        let mut cargo = prepare_tool_cargo(
+            builder,
+            compiler,
+            Mode::ToolRustc,
+            target,
+            subcommand(builder),
+            dir.to_str().unwrap(),
+            SourceType::Submodule,
+            &[],
+        );
+        if let Some(ref sysroot) = sysroot {
+            cargo.env("RUSTC_SYSROOT", sysroot);
+        }
+        if let Subcommand::Check { all_targets, ref tool_target_args, .. } = builder.config.cmd {
+            if all_targets {
+                cargo.arg("--all-targets");
+            }
+            cargo.args(tool_target_args);
+        }
+        for package in builder.config.cmd.packages() {
+            cargo.arg("-p").arg(package);
+        }
+
+        let stamp = Manifest::stamp(builder, compiler, target, dir);
+        let probe_key = fast_fresh_key(builder, &cargo);
+        builder.info(&format!(
+            "Checking {} artifacts ({} -> {})",
+            dir.display(),
+            &compiler.host.triple,
+            target.triple
+        ));
+        explain_rebuild(builder, &dir.display().to_string(), &stamp, probe_key, None);
+        let result = try_run_cargo(builder, cargo, args(builder), &stamp, vec![], true);
+        if let Err(failure) = result {
+            report_rustc_fingerprint(builder, compiler, target);
+            failure.exit(builder);
+        }
+        if !builder.config.dry_run {
+            compile::write_fresh_probe(&stamp, probe_key, &builder.out);
+        }
+        post_step_hooks(builder, "check::Manifest", target, &stamp);
+    }
+}
+
+impl Manifest {
+    /// The stamp of the crate in `dir`, named after the directory and told
+    /// apart from others of the same name by a hash of the path.
+    fn stamp(build: &Build, compiler: Compiler, target: TargetSelection, dir: &Path) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        dir.hash(&mut hasher);
+        let name = dir.file_name().map_or("root".into(), |name| name.to_string_lossy());
+        build.check_cargo_out(compiler, Mode::ToolRustc, target).join(format!(
+            ".{}-{:016x}-manifest-check.stamp",
+            name,
+            hasher.finish()
+        ))
+    }
+}
+
+/// The names of the crates in the tree of `root`.
+fn crate_names<'a>(builder: &'a Builder<'_>, root: &str) -> Vec<&'a str> {
+    builder.in_tree_crates(root).into_iter().map(|krate| &krate.name[..]).collect()
+}
+
+/// Implementation of `x.py check --force` for the step `S`, unless it's only
+/// run as a dependency of the steps asked for and `--force-deps` wasn't passed:
+/// drops `stamp` and the fingerprints Cargo keeps next to it for `crates`, so
+/// that they're checked again whatever either makes of their inputs. Returns
+/// whether it did.
+pub(crate) fn force<S: Step>(builder: &Builder<'_>, stamp: &Path, crates: &[&str]) -> bool {
+    let forced = match builder.config.cmd {
+        Subcommand::Check { force_deps: true, .. } => true,
+        Subcommand::Check { force: true, .. } => builder.is_requested::<S>(),
+        _ => false,
+    };
+    if !forced || builder.config.dry_run {
+        return false;
+    }
+    drop_stamp(builder, stamp, crates);
+    true
+}
+
+/// What `explain_rebuild` makes of a step `force` dropped the stamp of.
+fn forced_cause(forced: bool) -> Option<&'static str> {
+    if forced {
+        Some("forced from the command line")
+    } else {
+        None
+    }
+}
+
+/// Implementation of `x.py check --explain-rebuild`: prints why `what` is
+/// checked again, i.e. `cause` if the stamp was dropped on purpose, or else
+/// the first thing `compile::probe_staleness` finds different since its last
+/// check recorded its inputs next to `stamp`.
+fn explain_rebuild(builder: &Builder<'_>, what: &str, stamp: &Path, key: u64, cause: Option<&str>) {
+    if !builder.config.cmd.explain_rebuild() || builder.config.dry_run {
+        return;
+    }
+    let reason = match cause {
+        Some(cause) => Some(cause.to_string()),
+        None => compile::probe_staleness(stamp, key, &builder.out),
+    };
+    match reason {
+        Some(reason) => builder.info(&format!("  {} is stale: {}", what, reason)),
+        None => builder.info(&format!(
+            "  {} is up to date as far as bootstrap can tell, cargo may still rebuild it",
+            what
+        )),
+    }
+}
+
+/// Drops `stamp` and the fingerprints Cargo keeps next to it for `crates`.
+fn drop_stamp(builder: &Builder<'_>, stamp: &Path, crates: &[&str]) {
+    builder.verbose(&format!("dropping {} and the fingerprints of its crates", stamp.display()));
+    let _ = fs::remove_file(stamp);
+    let fingerprints = match fs::read_dir(stamp.parent().unwrap().join(".fingerprint")) {
+        Ok(fingerprints) => fingerprints,
+        Err(_) => return,
+    };
+    for entry in fingerprints {
+        let entry = t!(entry);
+        if is_fingerprint_of(&entry.file_name().to_string_lossy(), crates) {
+            t!(fs::remove_dir_all(entry.path()));
+        }
+    }
+}
+
+/// Makes a tool that links against the compiler crates listed in
+/// `rustc_stamp` be checked again, like `--force` does, if they changed since
+/// its `stamp` was written. Returns the hash of those crates to record once
+/// the tool was checked.
+pub(crate) fn check_against_rustc(
+    builder: &Builder<'_>,
+    stamp: &Path,
+    rustc_stamp: &Path,
+    crates: &[&str],
+) -> Option<String> {
+    let hash = librustc_stamp_hash(builder, rustc_stamp)?;
+    let recorded = fs::read_to_string(rustc_hash_path(stamp)).ok();
+    if recorded.as_deref() != Some(&hash[..]) && stamp.exists() {
+        builder.verbose("the compiler crates changed since the tool was checked");
+        drop_stamp(builder, stamp, crates);
+    }
+    Some(hash)
+}
+
+/// Hash of the compiler crates `stamp` lists, as they are on disk, which
+/// changes whenever one of them is checked again.
+fn librustc_stamp_hash(builder: &Builder<'_>, stamp: &Path) -> Option<String> {
+    if builder.config.dry_run || !stamp.exists() {
+        return None;
+    }
+    let mut hasher = DefaultHasher::new();
+    for (path, _) in builder.read_stamp_file(stamp) {
+        path.hash(&mut hasher);
+        mtime(&path).hash(&mut hasher);
+    }
+    Some(format!("{:016x}", hasher.finish()))
+}
+
+/// Where a tool's stamp records the `librustc_stamp_hash` of the compiler
+/// crates it was checked against.
+fn rustc_hash_path(stamp: &Path) -> PathBuf {
+    stamp.with_extension("stamp.rustc")
+}
+
+/// Whether the fingerprint directory `dir`, named after the crate and a hash
+/// like `rustc_middle-0123456789abcdef`, belongs to one of `crates`.
+fn is_fingerprint_of(dir: &str, crates: &[&str]) -> bool {
+    crates.iter().any(|krate| match dir.strip_prefix(krate) {
+        Some(hash) => hash.starts_with('-') && !hash[1..].contains('-'),
+        None => false,
+    })
+}
+
+/// The workspace package whose manifest is in `path`.
+fn package_at(builder: &Builder<'_>, path: &str) -> Option<Interned<String>> {
+    builder
+        .crate_graph()
+        .values()
+        .find(|krate| krate.local_path(builder) == Path::new(path))
+        .map(|krate| krate.name)
+}
+
+/// Makes sure the sources of the tool `name` at `path` are present before
+/// Cargo looks for them, checking out its submodule if it is one.
+fn ensure_tool_source(builder: &Builder<'_>, name: &str, path: &str) {
+    let path = Path::new(path);
+    if builder.config.dry_run || builder.src.join(path).join("Cargo.toml").exists() {
+        return;
+    }
+
+    let name = name.to_lowercase();
+    if !builder.is_submodule(path) {
+        eprintln!("error: can't check {}, {} has no Cargo.toml", name, path.display());
+        process::exit(2);
+    }
+    if !builder.config.submodules {
+        eprintln!(
+            "error: can't check {}, the {} submodule isn't checked out and \
+             `build.submodules` is disabled",
+            name,
+            path.display()
+        );
+        eprintln!("help: run `git submodule update --init --recursive {}`", path.display());
+        process::exit(2);
+    }
+
+    builder.info(&format!("Updating submodule {} for {}", path.display(), name));
+    builder.update_submodule(path);
+}
+
+/// The crates of a tree that a check step covers.
+enum CrateSelection {
+    /// Every crate in the tree, as without `--only-changed` or `-p`.
+    All,
+    /// Only the crates named with `-p` or affected by the changes, in the
+    /// tree's order.
+    Only(Vec<Interned<String>>),
+    /// None of the crates changed and the previous results are still intact.
+    Unchanged,
+}
+
+impl CrateSelection {
+    fn crates(&self, builder: &Builder<'_>, root: &str) -> Vec<Interned<String>> {
+        match self {
+            CrateSelection::Only(crates) => crates.clone(),
+            _ => builder.in_tree_crates(root).into_iter().map(|krate| krate.name).collect(),
+        }
+    }
+
+    /// Limits `cargo` to the selected crates, unless that's all of them.
+    fn restrict(&self, cargo: &mut Cargo) {
+        if let CrateSelection::Only(crates) = self {
+            for krate in crates {
+                cargo.arg("-p").arg(krate);
+            }
+        }
+    }
+}
+
+/// Runs the `hooks.post-step` commands of `step`, unless `--keep-going` let
+/// some of its crates fail.
+fn post_step_hooks(
+    builder: &Builder<'_>,
+    step: &'static str,
+    target: TargetSelection,
+    stamp: &Path,
+) {
+    if builder.kind == Kind::Check && !compile::is_partial_stamp(stamp) {
+        let sysroot = builder.sysroot(check_compiler(builder));
+        hooks::post_step(builder, step, target, stamp, &sysroot);
+    }
+}
+
+/// Records that the check step `step` checked `crates` for `target`, for
+/// `--checked-crates-output` and the `hooks.post-step` commands.
+fn publish_checked(
+    builder: &Builder<'_>,
+    step: &'static str,
+    target: TargetSelection,
+    crates: Vec<Interned<String>>,
+) {
+    builder.checked_crates.borrow_mut().extend(crates.iter().copied());
+    builder.check_results.borrow_mut().entry((step, target)).or_default().extend(crates);
+}
+
+/// The compiler the check steps use: the stage0 one, except for `x.py clippy
+/// --stage N`, where it's the stage N compiler the in-tree clippy pairs with.
+fn check_compiler(builder: &Builder<'_>) -> Compiler {
+    let stage = if builder.kind == Kind::Clippy { builder.top_stage } else { 0 };
+    builder.compiler(stage, builder.config.build)
+}
+
+/// Picks the crates of the tree rooted at `root` to check for `target`,
+/// honoring `-p` and then `--only-changed`, without the crates in the target's
+/// `skip-crates`. `stamp` is where the previous results of the step are.
+fn select_crates(
+    builder: &Builder<'_>,
+    root: &str,
+    target: TargetSelection,
+    stamp: &Path,
+) -> CrateSelection {
+    let selection = select_unskipped_crates(builder, root, stamp);
+    let skip = match builder.config.target_config.get(&target) {
+        Some(config) if !config.skip_crates.is_empty() => &config.skip_crates,
+        _ => return selection,
+    };
+    if let CrateSelection::Unchanged = selection {
+        return selection;
+    }
+
+    for krate in skip {
+        if !builder.crate_graph().keys().any(|name| &name[..] == krate) {
+            eprintln!("error: no crate named `{}` in the workspace", krate);
+            eprintln!("help: fix `target.{}.skip-crates` in config.toml", target);
+            process::exit(2);
+        }
+    }
+    let (skipped, crates): (Vec<_>, Vec<_>) = selection
+        .crates(builder, root)
+        .into_iter()
+        .partition(|krate| skip.iter().any(|name| name == &krate[..]));
+    if !skipped.is_empty() {
+        let skipped = skipped.iter().map(|krate| &krate[..]).collect::<Vec<_>>();
+        builder.info(&format!(
+            "Not checking {} for {}, as configured in `target.{}.skip-crates`",
+            skipped.join(", "),
+            target,
+            target
+        ));
+    }
+    CrateSelection::Only(crates)
+}
+
+/// `select_crates`, before leaving out the crates of `skip-crates`.
+fn select_unskipped_crates(builder: &Builder<'_>, root: &str, stamp: &Path) -> CrateSelection {
+    let packages = builder.config.cmd.packages();
+    if !packages.is_empty() {
+        warn_unknown_packages(builder, &packages);
+        // Crates of other trees don't narrow this one down, since it's still
+        // needed in the sysroot for them.
+        let requested = builder
+            .in_tree_crates(root)
+            .into_iter()
+            .map(|krate| krate.name)
+            .filter(|name| packages.contains(&&name[..]))
+            .collect::<Vec<_>>();
+        if !requested.is_empty() {
+            return CrateSelection::Only(requested);
+        }
+    }
+
+    let base = match builder.config.cmd.only_changed() {
+        Some(base) if !builder.config.dry_run => base,
+        _ => return CrateSelection::All,
+    };
+
+    let candidates = builder.in_tree_crates(root);
+    let changed = changed_crates(builder, base, &candidates);
+    if !changed.is_empty() {
+        CrateSelection::Only(changed)
+    } else if stamp_is_intact(builder, stamp) {
+        CrateSelection::Unchanged
+    } else {
+        // Later steps still need these crates in the sysroot.
+        builder.verbose(&format!("no previous check of {} to reuse, checking all crates", root));
+        CrateSelection::All
+    }
+}
+
+/// Warns about `-p` names that match no crate of the workspace, as Cargo would
+/// otherwise check nothing for them, suggesting the closest crate names.
+fn warn_unknown_packages(builder: &Builder<'_>, packages: &[&str]) {
+    if builder.unknown_packages_reported.replace(true) {
+        return;
+    }
+    for package in packages {
+        if builder.crate_graph().keys().any(|name| &name[..] == *package) {
+            continue;
+        }
+        let threshold = cmp::max(package.len() / 3, 1);
+        let mut suggestions = builder
+            .crate_graph()
+            .keys()
+            .map(|name| (util::edit_distance(package, name), &name[..]))
+            .filter(|&(distance, _)| distance <= threshold)
+            .collect::<Vec<_>>();
+        suggestions.sort();
+        match suggestions.first() {
+            Some((_, name)) => {
+                eprintln!("warning: no crate named `{}`, did you mean `{}`?", package, name)
+            }
+            None => eprintln!("warning: no crate named `{}` in the workspace", package),
+        }
+    }
+}
+
+/// Returns the `candidates` that own a file changed since the merge-base of
+/// `HEAD` and `base`, along with every candidate depending on one of those.
+fn changed_crates(
+    builder: &Builder<'_>,
+    base: &str,
+    candidates: &[&Crate],
+) -> Vec<Interned<String>> {
+    let mut changed = HashSet::new();
+    for file in changed_files(builder, base) {
+        let path = builder.src.join(&file);
+        let owner = builder
+            .crate_graph()
+            .values()
+            .filter(|krate| path.starts_with(&krate.path))
+            .max_by_key(|krate| krate.path.components().count());
+        match owner {
+            Some(krate) => {
+                changed.insert(krate.name);
+            }
+            None => builder.verbose(&format!(
+                "ignoring {} for --only-changed, it isn't part of any crate",
+                file.display()
+            )),
+        }
+    }
+
+    loop {
+        let len = changed.len();
+        for krate in candidates {
+            if krate.deps.iter().any(|dep| changed.contains(dep)) {
+                changed.insert(krate.name);
+            }
+        }
+        if changed.len() == len {
+            break;
+        }
+    }
+
+    candidates.iter().map(|krate| krate.name).filter(|name| changed.contains(name)).collect()
+}
+
+/// Returns the files, relative to the source root, that differ between the
+/// working tree and the merge-base of `HEAD` and `base`.
+fn changed_files(builder: &Builder<'_>, base: &str) -> Vec<PathBuf> {
+    let git = |args: &[&str]| {
+        let output = Command::new("git").current_dir(&builder.src).args(args).output();
+        match output {
+            Ok(output) if output.status.success() => {
+                t!(String::from_utf8(output.stdout))
+            }
+            _ => {
+                eprintln!(
+                    "error: `git {}` failed, can't tell which files changed for --only-changed",
+                    args.join(" ")
+                );
+                process::exit(2);
+            }
+        }
+    };
+
+    let merge_base = git(&["merge-base", "HEAD", base]);
+    git(&["diff", "--name-only", merge_base.trim()]).lines().map(PathBuf::from).collect()
+}
+
+/// Passes the `--rustc-args` given to `x.py check` or `x.py clippy` on to the
+/// target crates. Build scripts and proc macros are unaffected since Cargo
+/// doesn't apply `RUSTFLAGS` to host crates when `--target` is passed.
+///
+/// The same goes for the flags making the compiler print its statistics for
+/// `--compiler-stats`, whose output the rustc shim writes to a file per crate.
+fn add_rustc_args(builder: &Builder<'_>, cargo: &mut Cargo) {
+    for arg in builder.config.cmd.rustc_args() {
+        cargo.rustflag(arg);
+    }
+    if let Subcommand::Check { compiler_stats: Some(ref dir), .. } = builder.config.cmd {
+        let dir = t!(env::current_dir()).join(dir);
+        if !builder.config.dry_run {
+            t!(fs::create_dir_all(&dir));
+        }
+        cargo.rustflag("-Zquery-stats").rustflag("-Ztime-passes").rustflag("-Zhir-stats");
+        cargo.env("RUSTC_STATS_DIR", &dir);
+    }
+}
+
+/// Passes the `--features` and `--no-default-features` of `x.py check` on to
+/// Cargo for the compiler.
+fn add_rustc_features(builder: &Builder<'_>, cargo: &mut Cargo) {
+    if let Subcommand::Check { ref features, no_default_features, .. } = builder.config.cmd {
+        for features in features {
+            cargo.arg("--features").arg(features);
+        }
+        if no_default_features {
+            cargo.arg("--no-default-features");
+        }
+    }
+}
+
+/// Checks std with the sanitizer from `check.sanitizer` or `--sanitizer`.
+/// Checking never links, so this doesn't need the sanitizer runtimes, which
+/// only `x.py build` copies into the sysroot.
+fn add_sanitizer(builder: &Builder<'_>, cargo: &mut Cargo) {
+    if let Some(ref sanitizer) = builder.config.check_sanitizer {
+        cargo.rustflag(&format!("-Zsanitizer={}", sanitizer));
+    }
+}
+
+/// Suffix for the stamps of std, which also depend on the sanitizer it's
+/// checked with.
+fn libstd_suffix(build: &Build, target: TargetSelection) -> String {
+    match build.config.check_sanitizer {
+        Some(ref sanitizer) => format!("{}-{}", rustc_args_suffix(build, target), sanitizer),
+        None => rustc_args_suffix(build, target),
+    }
+}
+
+/// Suffix for the stamps of the std that ends up in the sysroot, whose panic
+/// runtime `--panic-strategy` picks. The other profiles have their own stamps.
+fn libstd_panic_suffix(build: &Build) -> &'static str {
+    match build.config.cmd.panic_strategy() {
+        StdProfile::PanicUnwind => "",
+        StdProfile::PanicAbort => "-panic-abort",
+    }
+}
+
+/// Suffix for the compiler's stamps, which also depend on the features it's
+/// checked with.
+fn librustc_suffix(build: &Build, target: TargetSelection) -> String {
+    match build.config.cmd {
+        Subcommand::Check { ref features, no_default_features, .. }
+            if !features.is_empty() || no_default_features =>
+        {
+            let mut hasher = DefaultHasher::new();
+            let rustflags = build.target_rustflags(target);
+            (build.config.cmd.rustc_args(), rustflags, features, no_default_features)
+                .hash(&mut hasher);
+            format!("-{:016x}", hasher.finish())
+        }
+        _ => rustc_args_suffix(build, target),
+    }
+}
+
+/// Suffix for the stamps of crates affected by `--rustc-args`, the `rustflags`
+/// of `target` and the clippy lint set file, so that checks with different
+/// flags don't pass for each other.
+fn rustc_args_suffix(build: &Build, target: TargetSelection) -> String {
+    let args = build.config.cmd.rustc_args();
+    let rustflags = build.target_rustflags(target);
+    let lints = &build.config.clippy_lints.hash;
+    if args.is_empty() && rustflags.is_empty() && lints.is_none() {
+        return String::new();
+    }
+    let mut hasher = DefaultHasher::new();
+    args.hash(&mut hasher);
+    // Only hashed when set, so the stamps of targets without any stay put.
+    if !rustflags.is_empty() {
+        rustflags.hash(&mut hasher);
+    }
+    if let Some(lints) = lints {
+        lints.hash(&mut hasher);
+    }
+    format!("-{:016x}", hasher.finish())
+}
+
+/// Identifies the Cargo invocation a fast-fresh probe is valid for, along with
+/// the compiler it runs, which can be replaced in place.
+fn fast_fresh_key(builder: &Builder<'_>, cargo: &Cargo) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    format!("{:?} {:?}", cargo, args(builder)).hash(&mut hasher);
+    if let Ok(metadata) = builder.initial_rustc.metadata() {
+        metadata.len().hash(&mut hasher);
+        FileTime::from_last_modification_time(&metadata).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Returns whether `stamp` is complete and current and every artifact it
+/// lists is still on disk and hasn't been rewritten since, e.g. by a check with
+/// other `--rustc-args`.
+fn stamp_is_intact(builder: &Builder<'_>, stamp: &Path) -> bool {
+    if builder.config.dry_run || !builder.validate_stamp(stamp) || compile::is_partial_stamp(stamp)
+    {
+        return false;
+    }
+    let stamp_mtime = mtime(stamp);
+    builder
+        .read_stamp_file(stamp)
+        .iter()
+        .all(|(path, _)| path.exists() && mtime(path) <= stamp_mtime)
+}
 
 /// Cargo's output path for the standard library in a given stage, compiled
 /// by a particular compiler for the specified target.
-fn libstd_stamp(builder: &Builder<'_>, compiler: Compiler, target: TargetSelection) -> PathBuf {
-    builder.cargo_out(compiler, Mode::Std, target).join(".libstd-check.stamp")
+fn libstd_stamp(build: &Build, compiler: Compiler, target: TargetSelection) -> PathBuf {
+    build.check_cargo_out(compiler, Mode::Std, target).join(format!(
+        ".libstd-check{}{}.stamp",
+        libstd_suffix(build, target),
+        libstd_panic_suffix(build)
+    ))
 }
 
 /// Cargo's output path for the standard library in a given stage, compiled
 /// by a particular compiler for the specified target.
-fn libstd_test_stamp(
-    builder: &Builder<'_>,
+fn libstd_test_stamp(build: &Build, compiler: Compiler, target: TargetSelection) -> PathBuf {
+    build.check_cargo_out(compiler, Mode::Std, target).join(format!(
+        ".libstd-check-test{}{}.stamp",
+        libstd_suffix(build, target),
+        libstd_panic_suffix(build)
+    ))
+}
+
+/// Cargo's target directory for the standard library's documentation examples.
+fn doctests_out(build: &Build, compiler: Compiler) -> PathBuf {
+    build.check_stage_out(compiler, Mode::Std).join("doctests")
+}
+
+/// Cargo's target directory for the docs `--document-private-items` builds of
+/// the crates of `mode`.
+fn private_docs_out(build: &Build, compiler: Compiler, mode: Mode) -> PathBuf {
+    build.check_stage_out(compiler, mode).join("private-docs")
+}
+
+/// Cargo's output path for the standard library's documentation examples,
+/// compiled by a particular compiler for the specified target.
+fn libstd_doctests_stamp(build: &Build, compiler: Compiler, target: TargetSelection) -> PathBuf {
+    doctests_out(build, compiler)
+        .join(&*target.triple)
+        .join(build.cargo_dir())
+        .join(format!(".libstd-check-doctests{}.stamp", rustc_args_suffix(build, target)))
+}
+
+/// Cargo's target directory for the standard library checked with a
+/// non-default `profile`.
+fn profile_out(build: &Build, compiler: Compiler, profile: StdProfile) -> PathBuf {
+    build.check_stage_out(compiler, Mode::Std).join(profile.name().replace('-', "_"))
+}
+
+/// Cargo's output path for the standard library checked with a non-default
+/// `profile`, compiled by a particular compiler for the specified target.
+fn libstd_profile_stamp(
+    build: &Build,
     compiler: Compiler,
     target: TargetSelection,
+    profile: StdProfile,
 ) -> PathBuf {
-    builder.cargo_out(compiler, Mode::Std, target).join(".libstd-check-test.stamp")
+    let profile_name = profile.name().replace('-', "_");
+    profile_out(build, compiler, profile)
+        .join(&*target.triple)
+        .join(build.cargo_dir())
+        .join(format!(".libstd-check-{}{}.stamp", profile_name, libstd_suffix(build, target)))
+}
+
+/// Cargo's target directory for a facade crate checked in `facade`.
+fn facade_out(build: &Build, compiler: Compiler, facade: &FacadeConfig) -> PathBuf {
+    let name = facade.name.replace('-', "_");
+    build.check_stage_out(compiler, Mode::Std).join(format!("facade_{}_{}", facade.krate, name))
+}
+
+/// Cargo's output path for a facade crate checked in `facade`, compiled by a
+/// particular compiler for the specified target.
+fn facade_stamp(
+    build: &Build,
+    compiler: Compiler,
+    target: TargetSelection,
+    facade: &FacadeConfig,
+) -> PathBuf {
+    let name = facade.name.replace('-', "_");
+    let suffix = rustc_args_suffix(build, target);
+    facade_out(build, compiler, facade)
+        .join(&*target.triple)
+        .join(build.cargo_dir())
+        .join(format!(".lib{}-check-{}{}.stamp", facade.krate, name, suffix))
 }
 
 /// Cargo's output path for librustc in a given stage, compiled by a particular
 /// compiler for the specified target.
-fn librustc_stamp(builder: &Builder<'_>, compiler: Compiler, target: TargetSelection) -> PathBuf {
-    builder.cargo_out(compiler, Mode::Rustc, target).join(".librustc-check.stamp")
+fn librustc_stamp(build: &Build, compiler: Compiler, target: TargetSelection) -> PathBuf {
+    build
+        .check_cargo_out(compiler, Mode::Rustc, target)
+        .join(format!(".librustc-check{}.stamp", librustc_suffix(build, target)))
+}
+
+/// Says which check of the compiler the rustc crates a tool failed to check
+/// against came from, since after a rebase that's often the culprit.
+fn report_rustc_fingerprint(builder: &Builder<'_>, compiler: Compiler, target: TargetSelection) {
+    let stamp = librustc_stamp(builder, compiler, target);
+    let path = sysroot_fingerprint_path(&builder.sysroot_libdir(compiler, target), &stamp);
+    let fingerprint = match SysrootFingerprint::read(&path) {
+        Some(fingerprint) => fingerprint,
+        None => {
+            eprintln!("note: the rustc crates in the check sysroot have no fingerprint");
+            return;
+        }
+    };
+    eprintln!(
+        "note: the rustc crates in the check sysroot were checked at commit {} (stamp {})",
+        fingerprint.commit.as_deref().unwrap_or("unknown"),
+        fingerprint.stamp_hash
+    );
+
+    let current =
+        if stamp.exists() { Some(SysrootFingerprint::new(builder, &stamp)) } else { None };
+    if current.as_ref() != Some(&fingerprint) {
+        eprintln!(
+            "help: they're older than the working tree, run `x.py check compiler` and try again"
+        );
+    }
+}
+
+/// Cargo's output path for the compiler's proc-macros when they're checked on
+/// their own for the host of a cross-check.
+fn librustc_proc_macro_stamp(build: &Build, compiler: Compiler) -> PathBuf {
+    build
+        .check_cargo_out(compiler, Mode::Rustc, compiler.host)
+        .join(format!(".librustc-proc-macro-check{}.stamp", librustc_suffix(build, compiler.host)))
+}
+
+/// Implementation of `x.py check --print-stamp-path`: prints the std and rustc
+/// stamps of every target, as `<mode>\t<target>\t<path>`.
+pub fn print_stamp_paths(build: &Build) {
+    let compiler = Compiler { stage: 0, host: build.build };
+    for &target in &build.targets {
+        println!("std\t{}\t{}", target, libstd_stamp(build, compiler, target).display());
+        println!("rustc\t{}\t{}", target, librustc_stamp(build, compiler, target).display());
+    }
+}
+
+/// What `x.py check --print-config` prints.
+#[derive(Serialize)]
+struct ResolvedConfig<'a> {
+    kind: &'static str,
+    stage: u32,
+    initial_rustc: &'a Path,
+    initial_cargo: &'a Path,
+    build: String,
+    hosts: Vec<String>,
+    targets: Vec<String>,
+    all_targets: bool,
+    incremental: bool,
+    deny_warnings: bool,
+    jobs: u32,
+    /// Whether std and the compiler are checked with `cfg(bootstrap)` set.
+    cfg_bootstrap: bool,
+    /// The `RUSTFLAGS` std is checked with for each target.
+    rustflags: BTreeMap<String, String>,
+    steps: &'a [PlannedStep],
+}
+
+/// Implementation of `x.py check --print-config`: walks the steps without
+/// running Cargo, and prints the resolved configuration along with them as
+/// JSON.
+pub fn print_config(build: &Build) {
+    let builder = Builder::new(build);
+    builder.execute_cli();
+
+    let steps = build.planned_steps.borrow();
+    let mut rustflags = BTreeMap::new();
+    for step in steps.iter().filter(|step| step.step == "check::Std") {
+        if let Some(ref target) = step.target {
+            rustflags.entry(target.clone()).or_insert_with(|| step.rustflags.clone());
+        }
+    }
+    let config = ResolvedConfig {
+        kind: "check",
+        stage: builder.top_stage,
+        initial_rustc: &build.initial_rustc,
+        initial_cargo: &build.initial_cargo,
+        build: build.build.to_string(),
+        hosts: build.hosts.iter().map(|host| host.to_string()).collect(),
+        targets: build.targets.iter().map(|target| target.to_string()).collect(),
+        all_targets: matches!(build.config.cmd, Subcommand::Check { all_targets: true, .. }),
+        incremental: builder.incremental(),
+        deny_warnings: build.config.deny_warnings,
+        jobs: build.config.cmd.check_jobs().unwrap_or_else(|| build.jobs()),
+        cfg_bootstrap: builder.cfg_bootstrap(check_compiler(&builder)),
+        rustflags,
+        steps: &steps,
+    };
+    println!("{}", t!(serde_json::to_string_pretty(&config)));
+}
+
+/// All the stamps the check steps write for `compiler` and `target`, used by
+/// `x.py clean --stamps` to tell live stamps from stale ones.
+pub fn stamps(build: &Build, compiler: Compiler, target: TargetSelection) -> Vec<PathBuf> {
+    let facade_stamps =
+        FACADE_CONFIGS.iter().map(|facade| facade_stamp(build, compiler, target, facade));
+    let mut stamps = vec![
+        libstd_stamp(build, compiler, target),
+        libstd_test_stamp(build, compiler, target),
+        libstd_doctests_stamp(build, compiler, target),
+        librustc_stamp(build, compiler, target),
+        librustc_proc_macro_stamp(build, compiler),
+        Rustdoc::stamp(build, compiler, target),
+        Clippy::stamp(build, compiler, target),
+        Rustfmt::stamp(build, compiler, target),
+        Bootstrap::stamp(build, compiler, target),
+        CargoTest::stamp(build, compiler, target),
+        Linkchecker::stamp(build, compiler, target),
+        Miri::stamp(build, compiler, target),
+        UnstableBookGen::stamp(build, compiler, target),
+        RustBook::stamp(build, compiler, target),
+        libstd_profile_stamp(build, compiler, target, StdProfile::PanicAbort),
+    ];
+    stamps.extend(facade_stamps);
+    stamps
 }
@@ -3,38 +3,180 @@
 //! Responsible for cleaning out a build directory of all old and stale
 //! artifacts to prepare for a fresh build. Currently doesn't remove the
 //! `build/cache` directory (download cache) or the `build/$target/llvm`
-//! directory unless the `--all` flag is present.
+//! directory unless the `--all` flag is present. With `--stamps` only the
+//! stamp files left behind by steps that no longer exist are removed.
 
+use std::collections::HashSet;
 use std::fs;
 use std::io::{self, ErrorKind};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use build_helper::t;
 
-use crate::Build;
+use crate::config::SANITIZERS;
+use crate::{check, compile};
+use crate::{Build, Compiler};
+
+pub fn clean(build: &Build, all: bool, stamps: bool) {
+    if stamps {
+        return prune_stamps(build);
+    }
 
-pub fn clean(build: &Build, all: bool) {
     rm_rf("tmp".as_ref());
 
     if all {
         rm_rf(&build.out);
+        if let Some(ref dir) = build.config.check_out_dir {
+            rm_rf(dir);
+        }
     } else {
         rm_rf(&build.out.join("tmp"));
         rm_rf(&build.out.join("dist"));
 
         for host in &build.hosts {
-            let entries = match build.out.join(host.triple).read_dir() {
-                Ok(iter) => iter,
-                Err(_) => continue,
-            };
-
-            for entry in entries {
-                let entry = t!(entry);
-                if entry.file_name().to_str() == Some("llvm") {
-                    continue;
+            for root in roots(build) {
+                let entries = match root.join(host.triple).read_dir() {
+                    Ok(iter) => iter,
+                    Err(_) => continue,
+                };
+
+                for entry in entries {
+                    let entry = t!(entry);
+                    if entry.file_name().to_str() == Some("llvm") {
+                        continue;
+                    }
+                    let path = t!(entry.path().canonicalize());
+                    rm_rf(&path);
                 }
-                let path = t!(entry.path().canonicalize());
-                rm_rf(&path);
+            }
+        }
+    }
+}
+
+/// The directories Cargo's output is in: the build directory, and the one
+/// `build.check-out-dir` moves the output of the check-like commands to.
+fn roots(build: &Build) -> Vec<&Path> {
+    let mut roots = vec![build.out.as_path()];
+    roots.extend(build.config.check_out_dir.as_deref());
+    roots
+}
+
+/// Removes the stamp files in Cargo's output directories that none of the
+/// current steps write, e.g. because a step was renamed, along with any
+/// artifacts that only those stamps refer to.
+fn prune_stamps(build: &Build) {
+    if build.config.dry_run {
+        return;
+    }
+
+    let known = known_stamps(build);
+    let mut removed = 0;
+    let mut reclaimed = 0;
+    let dirs = build
+        .hosts
+        .iter()
+        .flat_map(|host| roots(build).into_iter().map(move |root| root.join(host.triple)));
+    for dir in dirs {
+        let entries = match dir.read_dir() {
+            Ok(iter) => iter,
+            Err(_) => continue,
+        };
+
+        for entry in entries {
+            let entry = t!(entry);
+            if !entry.file_name().to_string_lossy().starts_with("stage") {
+                continue;
+            }
+
+            let mut stamps = Vec::new();
+            find_stamps(&entry.path(), 3, &mut stamps);
+            let (live, stale): (Vec<_>, Vec<_>) = stamps.into_iter().partition(|stamp| {
+                known.contains(&stamp_base_name(&stamp.file_name().unwrap().to_string_lossy()))
+            });
+
+            // Live stamps may share artifacts with stale ones (e.g. after a
+            // stamp was renamed), so those have to stay.
+            let in_use = live
+                .iter()
+                .flat_map(|stamp| build.read_stamp_file(stamp))
+                .map(|(path, _)| path)
+                .collect::<HashSet<_>>();
+            for stamp in stale {
+                println!("Removing stale stamp {}", stamp.display());
+                for (path, _) in build.read_stamp_file(&stamp) {
+                    if !in_use.contains(&path) {
+                        if let Ok(metadata) = path.symlink_metadata() {
+                            reclaimed += metadata.len();
+                            rm_rf(&path);
+                        }
+                    }
+                }
+                reclaimed += t!(stamp.symlink_metadata()).len();
+                rm_rf(&stamp);
+                removed += 1;
+            }
+        }
+    }
+
+    println!(
+        "Removed {} stale stamp(s), reclaiming {:.1} MiB",
+        removed,
+        reclaimed as f64 / (1024.0 * 1024.0)
+    );
+}
+
+/// File names of the stamps written by the current check and build steps, as
+/// `stamp_base_name` has them.
+fn known_stamps(build: &Build) -> HashSet<String> {
+    let compiler = Compiler { stage: 0, host: build.build };
+    let mut stamps = check::stamps(build, compiler, build.build);
+    stamps.push(compile::libstd_stamp(build, compiler, build.build));
+    stamps.push(compile::librustc_stamp(build, compiler, build.build));
+    stamps
+        .iter()
+        .map(|stamp| stamp_base_name(&stamp.file_name().unwrap().to_string_lossy()))
+        .collect()
+}
+
+/// Strips from a stamp name what depends on how the check was run rather than
+/// on the step: the `--panic-strategy` and the sanitizer std was checked with,
+/// and the hash of the `--rustc-args`, if it has them.
+fn stamp_base_name(name: &str) -> String {
+    let mut stem = name.trim_end_matches(".stamp");
+    stem = stem.strip_suffix("-panic-abort").unwrap_or(stem);
+    if let Some(i) = stem.rfind('-') {
+        if SANITIZERS.contains(&&stem[i + 1..]) {
+            stem = &stem[..i];
+        }
+    }
+    match stem.rfind('-') {
+        Some(i) if is_args_hash(&stem[i + 1..]) => format!("{}.stamp", &stem[..i]),
+        _ => format!("{}.stamp", stem),
+    }
+}
+
+fn is_args_hash(s: &str) -> bool {
+    s.len() == 16 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Collects the `.<name>.stamp` files in `dir` and up to `depth` levels of its
+/// subdirectories, skipping the directories where Cargo keeps its own state.
+fn find_stamps(dir: &Path, depth: u32, stamps: &mut Vec<PathBuf>) {
+    for entry in t!(dir.read_dir()) {
+        let entry = t!(entry);
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let file_type = t!(entry.file_type());
+        if file_type.is_file()
+            && name.starts_with('.')
+            && name.ends_with(".stamp")
+            && name != ".stamp"
+        {
+            stamps.push(entry.path());
+        } else if file_type.is_dir() && depth > 0 {
+            match &*name {
+                "build" | "deps" | "examples" | "incremental" | ".fingerprint" => {}
+                _ => find_stamps(&entry.path(), depth - 1, stamps),
             }
         }
     }
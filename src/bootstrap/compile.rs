@@ -7,27 +7,38 @@
 //! goes along from the output of the previous stage.
 
 use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::env;
+use std::fmt;
 use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
-use std::process::{exit, Command, Stdio};
+use std::process::{exit, Child, Command, Stdio};
 use std::str;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use build_helper::{output, t, up_to_date};
 use filetime::FileTime;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
+use crate::budgets;
 use crate::builder::Cargo;
 use crate::builder::{Builder, Kind, RunConfig, ShouldRun, Step};
 use crate::cache::{Interned, INTERNER};
-use crate::config::TargetSelection;
+use crate::config::{LinkArtifacts, StdProfile, TargetSelection};
 use crate::dist;
 use crate::native;
+use crate::report;
+use crate::sarif;
 use crate::tool::SourceType;
-use crate::util::{exe, is_dylib, symlink_dir};
-use crate::{Compiler, DependencyType, GitRepo, Mode};
+use crate::util::{self, exe, is_dylib, symlink_dir, CiEnv};
+use crate::{Build, Compiler, DependencyType, GitRepo, Mode, Subcommand};
 
 #[derive(Debug, PartialOrd, Ord, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Std {
@@ -208,8 +219,21 @@ fn copy_self_contained_objects(
 }
 
 /// Configure cargo to compile the standard library, adding appropriate env vars
-/// and such.
+/// and such. `x.py check --panic-strategy` picks the panic runtime it's
+/// compiled with.
 pub fn std_cargo(builder: &Builder<'_>, target: TargetSelection, stage: u32, cargo: &mut Cargo) {
+    std_profile_cargo(builder, target, stage, builder.config.cmd.panic_strategy(), cargo);
+}
+
+/// Same as `std_cargo`, but compiles the standard library with the runtime
+/// configuration described by `profile`.
+pub fn std_profile_cargo(
+    builder: &Builder<'_>,
+    target: TargetSelection,
+    stage: u32,
+    profile: StdProfile,
+    cargo: &mut Cargo,
+) {
     if let Some(target) = env::var_os("MACOSX_STD_DEPLOYMENT_TARGET") {
         cargo.env("MACOSX_DEPLOYMENT_TARGET", target);
     }
@@ -251,7 +275,7 @@ pub fn std_cargo(builder: &Builder<'_>, target: TargetSelection, stage: u32, car
             .arg("--features")
             .arg(features);
     } else {
-        let mut features = builder.std_features();
+        let mut features = builder.std_features(profile);
         features.push_str(compiler_builtins_c_feature);
 
         cargo
@@ -298,6 +322,12 @@ pub fn std_cargo(builder: &Builder<'_>, target: TargetSelection, stage: u32, car
     if target.contains("riscv") {
         cargo.rustflag("-Cforce-unwind-tables=yes");
     }
+
+    if profile == StdProfile::PanicAbort {
+        cargo.rustflag("-Cpanic=abort");
+    }
+
+    add_target_rustflags(builder, cargo, target);
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -332,7 +362,8 @@ impl Step for StdLink {
         ));
         let libdir = builder.sysroot_libdir(target_compiler, target);
         let hostdir = builder.sysroot_libdir(target_compiler, compiler.host);
-        add_to_sysroot(builder, &libdir, &hostdir, &libstd_stamp(builder, compiler, target));
+        let stamp = libstd_stamp(builder, compiler, target);
+        add_to_sysroot(builder, &libdir, &hostdir, &stamp, target_compiler.stage, target);
     }
 }
 
@@ -529,6 +560,18 @@ pub fn rustc_cargo(builder: &Builder<'_>, cargo: &mut Cargo, target: TargetSelec
         .arg("--manifest-path")
         .arg(builder.src.join("compiler/rustc/Cargo.toml"));
     rustc_cargo_env(builder, cargo, target);
+    add_target_rustflags(builder, cargo, target);
+}
+
+/// Adds the `rustflags` of `target` in config.toml, which only apply when
+/// checking, so that they never change the artifacts that are built.
+fn add_target_rustflags(builder: &Builder<'_>, cargo: &mut Cargo, target: TargetSelection) {
+    if !builder.is_check_like() {
+        return;
+    }
+    for flag in builder.target_rustflags(target) {
+        cargo.rustflag(flag);
+    }
 }
 
 pub fn rustc_cargo_env(builder: &Builder<'_>, cargo: &mut Cargo, target: TargetSelection) {
@@ -636,24 +679,22 @@ impl Step for RustcLink {
             &builder.sysroot_libdir(target_compiler, target),
             &builder.sysroot_libdir(target_compiler, compiler.host),
             &librustc_stamp(builder, compiler, target),
+            target_compiler.stage,
+            target,
         );
     }
 }
 
 /// Cargo's output path for the standard library in a given stage, compiled
 /// by a particular compiler for the specified target.
-pub fn libstd_stamp(builder: &Builder<'_>, compiler: Compiler, target: TargetSelection) -> PathBuf {
-    builder.cargo_out(compiler, Mode::Std, target).join(".libstd.stamp")
+pub fn libstd_stamp(build: &Build, compiler: Compiler, target: TargetSelection) -> PathBuf {
+    build.cargo_out(compiler, Mode::Std, target).join(".libstd.stamp")
 }
 
 /// Cargo's output path for librustc in a given stage, compiled by a particular
 /// compiler for the specified target.
-pub fn librustc_stamp(
-    builder: &Builder<'_>,
-    compiler: Compiler,
-    target: TargetSelection,
-) -> PathBuf {
-    builder.cargo_out(compiler, Mode::Rustc, target).join(".librustc.stamp")
+pub fn librustc_stamp(build: &Build, compiler: Compiler, target: TargetSelection) -> PathBuf {
+    build.cargo_out(compiler, Mode::Rustc, target).join(".librustc.stamp")
 }
 
 pub fn compiler_file(
@@ -841,18 +882,255 @@ pub fn add_to_sysroot(
     sysroot_dst: &Path,
     sysroot_host_dst: &Path,
     stamp: &Path,
+    stage: u32,
+    target: TargetSelection,
 ) {
     let self_contained_dst = &sysroot_dst.join("self-contained");
-    t!(fs::create_dir_all(&sysroot_dst));
-    t!(fs::create_dir_all(&sysroot_host_dst));
-    t!(fs::create_dir_all(&self_contained_dst));
+    t!(fs::create_dir_all(util::long_path(sysroot_dst)));
+    t!(fs::create_dir_all(util::long_path(sysroot_host_dst)));
+    t!(fs::create_dir_all(util::long_path(self_contained_dst)));
+    let mut artifacts = Vec::new();
     for (path, dependency_type) in builder.read_stamp_file(stamp) {
         let dst = match dependency_type {
             DependencyType::Host => sysroot_host_dst,
             DependencyType::Target => sysroot_dst,
             DependencyType::TargetSelfContained => self_contained_dst,
         };
-        builder.copy(&path, &dst.join(path.file_name().unwrap()));
+        let dst = dst.join(path.file_name().unwrap());
+        if !builder.config.dry_run && path != dst {
+            builder.verbose_than(1, &format!("Link {:?} to {:?}", path, dst));
+            let mode = builder.config.link_artifacts;
+            let (src, long_dst) = (util::long_path(&path), util::long_path(&dst));
+            let linked = link_or_copy(&src, &long_dst, mode, |src, dst| fs::hard_link(src, dst));
+            if let Err(e) = linked {
+                panic!("failed to link `{}` to `{}`: {}", path.display(), dst.display(), e);
+            }
+        }
+        artifacts.push(SysrootArtifact::new(path, dst));
+    }
+
+    if builder.config.emit_sysroot_manifest && !builder.config.dry_run {
+        write_sysroot_manifest(
+            builder,
+            &stamp.with_extension("sysroot.json"),
+            stage,
+            target,
+            artifacts,
+        );
+    }
+
+    if builder.is_check_like() && !builder.config.dry_run {
+        let fingerprint = SysrootFingerprint::new(builder, stamp);
+        t!(fs::write(sysroot_fingerprint_path(sysroot_dst, stamp), fingerprint.to_string()));
+    }
+}
+
+/// Puts the artifact `src` at `dst` the way `mode` says, with `hard_link`
+/// making the links, and returns whether it was linked. `dst` is replaced
+/// rather than written to, so a step writing there later would have to do the
+/// same not to change `src` through the link.
+fn link_or_copy(
+    src: &Path,
+    dst: &Path,
+    mode: LinkArtifacts,
+    hard_link: impl Fn(&Path, &Path) -> io::Result<()>,
+) -> io::Result<bool> {
+    // A virus scanner may still be looking at the previous artifact.
+    let _ = util::retry_io(|| fs::remove_file(dst));
+    if mode != LinkArtifacts::Copy {
+        match hard_link(src, dst) {
+            Ok(()) => return Ok(true),
+            Err(e) if mode == LinkArtifacts::Hardlink && !cannot_link(&e) => return Err(e),
+            Err(_) => {}
+        }
+    }
+    util::retry_io(|| fs::copy(src, dst))?;
+    let metadata = fs::metadata(src)?;
+    let atime = FileTime::from_last_access_time(&metadata);
+    let mtime = FileTime::from_last_modification_time(&metadata);
+    filetime::set_file_times(dst, atime, mtime)?;
+    Ok(false)
+}
+
+/// Whether `err` says the files can't be linked because they're on different
+/// devices, rather than that something is wrong with them or the sysroot.
+fn cannot_link(err: &io::Error) -> bool {
+    // EXDEV on Unix, ERROR_NOT_SAME_DEVICE on Windows.
+    let cross_device = if cfg!(windows) { 17 } else { 18 };
+    err.raw_os_error() == Some(cross_device)
+}
+
+/// Copies the artifacts listed in `stamp` into the sysroot at `dir`, laid out
+/// like the ones `add_to_sysroot` fills, and writes their manifest next to
+/// them. Artifacts that are already up to date are left alone and the ones no
+/// longer listed are removed, so exporting to the same directory again is
+/// cheap and never leaves two versions of a crate behind.
+pub fn export_sysroot(
+    builder: &Builder<'_>,
+    dir: &Path,
+    stamp: &Path,
+    stage: u32,
+    host: TargetSelection,
+    target: TargetSelection,
+) {
+    if builder.config.dry_run {
+        return;
+    }
+    let rustlib = dir.join("lib").join("rustlib");
+    let target_dst = rustlib.join(&*target.triple).join("lib");
+    let host_dst = rustlib.join(&*host.triple).join("lib");
+    let self_contained_dst = target_dst.join("self-contained");
+    t!(fs::create_dir_all(&host_dst));
+    t!(fs::create_dir_all(&self_contained_dst));
+
+    let mut artifacts = Vec::new();
+    let mut exported = HashSet::new();
+    for (path, dependency_type) in builder.read_stamp_file(stamp) {
+        let dst = match dependency_type {
+            DependencyType::Host => &host_dst,
+            DependencyType::Target => &target_dst,
+            DependencyType::TargetSelfContained => &self_contained_dst,
+        };
+        let dst = dst.join(path.file_name().unwrap());
+        if !up_to_date(&path, &dst) {
+            builder.copy(&path, &dst);
+        }
+        exported.insert(dst.clone());
+        artifacts.push(SysrootArtifact::new(path, dst));
+    }
+
+    // The host directory may hold the export for the host target itself.
+    for dir in &[&target_dst, &self_contained_dst] {
+        for entry in t!(fs::read_dir(dir)) {
+            let path = t!(entry).path();
+            if path.is_file() && !exported.contains(&path) {
+                builder.verbose(&format!("Removing stale {}", path.display()));
+                t!(fs::remove_file(&path));
+            }
+        }
+    }
+
+    let manifest = rustlib.join(&*target.triple).join("sysroot.json");
+    write_sysroot_manifest(builder, &manifest, stage, target, artifacts);
+}
+
+fn write_sysroot_manifest(
+    builder: &Builder<'_>,
+    dst: &Path,
+    stage: u32,
+    target: TargetSelection,
+    artifacts: Vec<SysrootArtifact>,
+) {
+    let manifest = SysrootManifest {
+        kind: format!("{:?}", builder.kind).to_lowercase(),
+        stage,
+        target: &target.triple,
+        artifacts,
+    };
+    // Write to a temporary file first so that consumers never observe a
+    // partially written manifest.
+    let (tmp, dst) = (util::long_path(&dst.with_extension("json.tmp")), util::long_path(dst));
+    t!(fs::write(&tmp, t!(serde_json::to_string_pretty(&manifest))));
+    t!(util::retry_io(|| fs::rename(&tmp, &dst)));
+}
+
+/// Where `add_to_sysroot` records the fingerprint of the check whose
+/// artifacts it copied from `stamp` into `sysroot_dst`.
+pub fn sysroot_fingerprint_path(sysroot_dst: &Path, stamp: &Path) -> PathBuf {
+    let name = stamp.file_stem().unwrap().to_str().unwrap();
+    sysroot_dst.join(format!("{}.fingerprint", name))
+}
+
+/// Identifies the check that put a set of crates into the sysroot, so that
+/// when something checked against them fails, it can say which revision of
+/// them it saw.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SysrootFingerprint {
+    /// The commit the crates were checked at, if the source is a git repo.
+    pub commit: Option<String>,
+    /// Hash of the contents of the stamp listing the crates.
+    pub stamp_hash: String,
+}
+
+impl SysrootFingerprint {
+    pub fn new(builder: &Builder<'_>, stamp: &Path) -> SysrootFingerprint {
+        let mut hasher = DefaultHasher::new();
+        t!(fs::read(stamp)).hash(&mut hasher);
+        SysrootFingerprint {
+            commit: builder.rust_info.sha().map(|sha| sha.to_string()),
+            stamp_hash: format!("{:016x}", hasher.finish()),
+        }
+    }
+
+    pub fn read(path: &Path) -> Option<SysrootFingerprint> {
+        SysrootFingerprint::parse(&fs::read_to_string(path).ok()?)
+    }
+
+    fn parse(contents: &str) -> Option<SysrootFingerprint> {
+        let mut commit = None;
+        let mut stamp_hash = None;
+        for line in contents.lines() {
+            if let Some(value) = line.strip_prefix("commit: ") {
+                commit = Some(value).filter(|value| *value != "unknown").map(String::from);
+            } else if let Some(value) = line.strip_prefix("stamp: ") {
+                stamp_hash = Some(value.to_string());
+            }
+        }
+        Some(SysrootFingerprint { commit, stamp_hash: stamp_hash? })
+    }
+}
+
+impl fmt::Display for SysrootFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "commit: {}", self.commit.as_deref().unwrap_or("unknown"))?;
+        writeln!(f, "stamp: {}", self.stamp_hash)
+    }
+}
+
+/// A sysroot manifest as read back, for checking against an exported sysroot.
+#[derive(Deserialize)]
+pub struct ExportedSysroot {
+    pub target: String,
+    pub artifacts: Vec<ExportedArtifact>,
+}
+
+#[derive(Deserialize)]
+pub struct ExportedArtifact {
+    pub destination: PathBuf,
+}
+
+/// Machine-readable description of what `add_to_sysroot` copied, written next
+/// to the stamp file when `--emit-sysroot-manifest` is passed.
+#[derive(Serialize)]
+struct SysrootManifest<'a> {
+    kind: String,
+    stage: u32,
+    target: &'a str,
+    artifacts: Vec<SysrootArtifact>,
+}
+
+#[derive(Serialize)]
+struct SysrootArtifact {
+    source: PathBuf,
+    destination: PathBuf,
+    #[serde(rename = "crate")]
+    krate: String,
+    hash: Option<String>,
+}
+
+impl SysrootArtifact {
+    fn new(source: PathBuf, destination: PathBuf) -> SysrootArtifact {
+        // Artifact names look like `libstd-<hash>.rmeta`, or `std-<hash>.dll`
+        // for Windows dylibs, which don't get the `lib` prefix.
+        let name = source.file_name().unwrap().to_str().unwrap();
+        let stem = name.split('.').next().unwrap();
+        let stem =
+            if name.contains(".dll") { stem } else { stem.strip_prefix("lib").unwrap_or(stem) };
+        let (krate, hash) = match stem.rfind('-') {
+            Some(i) => (stem[..i].to_string(), Some(stem[i + 1..].to_string())),
+            None => (stem.to_string(), None),
+        };
+        SysrootArtifact { source, destination, krate, hash }
     }
 }
 
@@ -864,8 +1142,89 @@ pub fn run_cargo(
     additional_target_deps: Vec<(PathBuf, DependencyType)>,
     is_check: bool,
 ) -> Vec<PathBuf> {
+    try_run_cargo(builder, cargo, tail_args, stamp, additional_target_deps, is_check)
+        .unwrap_or_else(|failure| failure.exit(builder))
+}
+
+/// Why a `run_cargo` invocation failed, which determines the exit code that
+/// `x.py` reports.
+#[derive(Debug, Clone)]
+pub enum CargoFailure {
+    /// Cargo compiled the crates and found errors in them.
+    Compile,
+    /// Cargo failed before compiling anything (e.g. a missing stage0
+    /// component, a missing submodule or a bad manifest).
+    Bootstrap(String),
+    /// Cargo's output didn't make sense to bootstrap.
+    Internal(String),
+    /// Cargo ran into `build.step-timeout` or `build.step-budget` and was
+    /// killed, most likely because something hung.
+    Timeout(String),
+}
+
+impl CargoFailure {
+    /// The exit code `x.py` reports for the failure, as documented in
+    /// `x.py check --help`.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            CargoFailure::Compile => 1,
+            CargoFailure::Bootstrap(_) | CargoFailure::Timeout(_) => 2,
+            CargoFailure::Internal(_) => 3,
+        }
+    }
+
+    /// Reports the failure and exits with the matching exit code, writing
+    /// the `--report` first since it's most useful for failed checks.
+    pub fn exit(self, build: &Build) -> ! {
+        report::finish(build);
+        match self {
+            CargoFailure::Compile => {}
+            CargoFailure::Bootstrap(ref msg) | CargoFailure::Timeout(ref msg) => {
+                eprintln!("error: {}", msg)
+            }
+            CargoFailure::Internal(ref msg) => {
+                eprintln!("error: internal bootstrap error: {}", msg)
+            }
+        }
+        exit(self.exit_code())
+    }
+}
+
+/// Leading entry of every stamp written by `run_cargo`. Bump this whenever the
+/// stamp format or the meaning of its entries changes.
+pub const STAMP_VERSION: &str = "v2";
+
+/// Same as `run_cargo`, but returns the failure instead of exiting if Cargo
+/// fails, so that the caller can explain what was being compiled.
+pub fn try_run_cargo(
+    builder: &Builder<'_>,
+    mut cargo: Cargo,
+    tail_args: Vec<String>,
+    stamp: &Path,
+    additional_target_deps: Vec<(PathBuf, DependencyType)>,
+    is_check: bool,
+) -> Result<Vec<PathBuf>, CargoFailure> {
     if builder.config.dry_run {
-        return Vec::new();
+        if let Subcommand::Check { print_config: true, .. } = builder.config.cmd {
+            builder.planned_steps.borrow_mut().push(PlannedStep::new(builder, &cargo, stamp));
+        } else if builder.config.explicit_dry_run && builder.kind == Kind::Check {
+            print_plan(builder, &cargo, stamp);
+        }
+        return Ok(Vec::new());
+    }
+
+    if !builder.initial_cargo.exists() {
+        return Err(CargoFailure::Bootstrap(format!(
+            "the stage0 cargo is missing, expected it at {}",
+            builder.initial_cargo.display()
+        )));
+    }
+
+    // A stamp from an older bootstrap, or one cut short by an interrupted
+    // run, can't be trusted to describe what cargo produced, so drop it up
+    // front; it's only rewritten once cargo succeeds.
+    if stamp.exists() {
+        builder.validate_stamp(stamp);
     }
 
     // `target_root_dir` looks like $dir/$target/release
@@ -885,14 +1244,62 @@ pub fn run_cargo(
     // files we need to probe for later.
     let mut deps = Vec::new();
     let mut toplevel = Vec::new();
-    let ok = stream_cargo(builder, cargo, tail_args, &mut |msg| {
+    let mut build_finished = false;
+    // Crates rustc reported errors for, or otherwise the last one it said
+    // anything about, for explaining a failure.
+    let mut failed_crates = BTreeSet::new();
+    let mut last_crate = None;
+    // What the build scripts that ran, or were fresh, depend on.
+    let mut build_inputs = Vec::new();
+    // How long checking each library took, for the check budgets, and the
+    // size of its metadata. A library also checked as a test counts once, by
+    // the longer of the two.
+    let timed = is_check && budgets::enabled(builder);
+    if timed {
+        cargo.arg("-Ztimings=json");
+    }
+    let mut timings = BTreeMap::new();
+    let mut metadata_sizes = HashMap::new();
+    let command_line = cargo.command_line();
+    let ok = try_stream_cargo(builder, cargo, tail_args, &mut |msg| {
         let (filenames, crate_types) = match msg {
             CargoMessage::CompilerArtifact {
                 filenames,
-                target: CargoTarget { crate_types },
+                target: CargoTarget { crate_types, name },
                 ..
-            } => (filenames, crate_types),
-            _ => return,
+            } => {
+                if timed {
+                    let rmeta = filenames.iter().find(|filename| filename.ends_with(".rmeta"));
+                    if let Some(metadata) = rmeta.and_then(|rmeta| fs::metadata(&**rmeta).ok()) {
+                        metadata_sizes.insert(name.to_string(), metadata.len());
+                    }
+                }
+                (filenames, crate_types)
+            }
+            CargoMessage::TimingInfo { target, duration } => {
+                if target.crate_types.iter().any(|t| LIBRARY_CRATE_TYPES.contains(&&t[..])) {
+                    let seconds = timings.entry(target.name.to_string()).or_insert(0.0);
+                    *seconds = f64::max(*seconds, duration);
+                }
+                return;
+            }
+            CargoMessage::BuildFinished { .. } => {
+                build_finished = true;
+                return;
+            }
+            CargoMessage::CompilerMessage { package_id, message } => {
+                let krate = report::crate_name(&package_id).to_string();
+                if message.level.starts_with("error") {
+                    failed_crates.insert(krate.clone());
+                }
+                last_crate = Some(krate);
+                return;
+            }
+            CargoMessage::BuildScriptExecuted { package_id, out_dir } => {
+                let package_dir = package_dir(&package_id);
+                build_inputs.extend(build_script_inputs(Path::new(&*out_dir), package_dir));
+                return;
+            }
         };
         for filename in filenames {
             // Skip files like executables
@@ -942,10 +1349,48 @@ pub fn run_cargo(
 
             toplevel.push((file_stem, extension, expected_len));
         }
-    });
+    })?;
+
+    if timed {
+        let target = target_root_dir.parent().and_then(Path::file_name).unwrap_or_default();
+        builder.check_timings.borrow_mut().extend(timings.into_iter().map(|(name, seconds)| {
+            budgets::CrateTiming {
+                metadata_bytes: metadata_sizes.get(&name).copied(),
+                name,
+                target: target.to_string_lossy().into_owned(),
+                seconds,
+            }
+        }));
+    }
 
     if !ok {
-        exit(1);
+        // Cargo only reports the end of the build once it has started
+        // compiling, so without it the failure wasn't in the crates themselves.
+        if !build_finished {
+            return Err(CargoFailure::Bootstrap(format!(
+                "cargo failed before compiling anything for {:?}",
+                stamp
+            )));
+        }
+        if failed_crates.is_empty() {
+            failed_crates.extend(last_crate);
+        }
+        // Only the check-like commands get the diagnostics as JSON, which is
+        // where the failed crates come from.
+        if builder.is_check_like() {
+            print_failure_trailer(builder, &command_line, &failed_crates);
+        }
+        if !builder.config.cmd.keep_going() {
+            return Err(CargoFailure::Compile);
+        }
+        // With `--keep-going`, what did compile still goes in the stamp so the
+        // steps depending on it can carry on, and the failure is reported once
+        // everything ran.
+        let failed = failed_crates.iter().map(|krate| &krate[..]).collect::<Vec<_>>();
+        builder
+            .delayed_failures
+            .borrow_mut()
+            .push(format!("failed to compile {}", failed.join(", ")));
     }
 
     // Ok now we need to actually find all the files listed in `toplevel`. We've
@@ -967,7 +1412,12 @@ pub fn run_cargo(
             .max_by_key(|&&(_, _, ref metadata)| FileTime::from_last_modification_time(metadata));
         let path_to_add = match max {
             Some(triple) => triple.0.to_str().unwrap(),
-            None => panic!("no output generated for {:?} {:?}", prefix, extension),
+            None => {
+                return Err(CargoFailure::Internal(format!(
+                    "no output generated for {:?} {:?}",
+                    prefix, extension
+                )));
+            }
         };
         if is_dylib(path_to_add) {
             let candidate = format!("{}.lib", path_to_add);
@@ -980,19 +1430,521 @@ pub fn run_cargo(
     }
 
     deps.extend(additional_target_deps);
-    deps.sort();
-    let mut new_contents = Vec::new();
+    // A retried Cargo reports the fresh artifacts again.
+    dedup_stamp_entries(&mut deps, cfg!(windows));
+    // Write to a temporary file first so that an interrupted run never leaves
+    // a partially written stamp behind.
+    let tmp = util::long_path(&stamp.with_extension("stamp.tmp"));
+    t!(fs::write(&tmp, encode_stamp(&deps, &builder.out)));
+    t!(util::retry_io(|| fs::rename(&tmp, util::long_path(stamp))));
+    build_inputs.sort();
+    build_inputs.dedup();
+    if build_inputs.is_empty() {
+        let _ = fs::remove_file(build_inputs_path(stamp));
+    } else {
+        t!(fs::write(build_inputs_path(stamp), build_inputs.join("\n") + "\n"));
+    }
+    if ok {
+        let _ = fs::remove_file(partial_marker(stamp));
+    } else {
+        t!(fs::write(partial_marker(stamp), ""));
+    }
+    Ok(deps.into_iter().map(|(d, _)| d).collect())
+}
+
+/// The file marking that `stamp` was written by a `--keep-going` run in which
+/// some crates failed, so it doesn't cover everything the step produces.
+fn partial_marker(stamp: &Path) -> PathBuf {
+    stamp.with_extension("stamp.partial")
+}
+
+/// Whether `stamp` is missing the artifacts of crates that failed to compile.
+pub fn is_partial_stamp(stamp: &Path) -> bool {
+    partial_marker(stamp).exists()
+}
+
+/// The file listing the inputs of the build scripts of the crates in `stamp`,
+/// one per line: a path, or `$NAME` for an environment variable.
+fn build_inputs_path(stamp: &Path) -> PathBuf {
+    stamp.with_extension("stamp.build-inputs")
+}
+
+/// The directory of the package `package_id` refers to, unless it's not a
+/// path dependency, e.g. `libc 0.2.86 (path+file:///rust/library/libc)`.
+fn package_dir(package_id: &str) -> Option<&Path> {
+    let start = package_id.find("path+file://")? + "path+file://".len();
+    let path = &package_id[start..];
+    let end = path.find(|c| c == ')' || c == '#').unwrap_or(path.len());
+    Some(Path::new(&path[..end]))
+}
+
+/// The inputs of the build script that ran with the `OUT_DIR` `out_dir` for
+/// the package in `package_dir`, which Cargo reruns it for: the files and
+/// environment variables its `rerun-if-changed` and `rerun-if-env-changed`
+/// directives name. Without any files named, Cargo watches the whole package.
+///
+/// The generated code only shows up in the dep-info files as the files in
+/// `OUT_DIR`, which don't change until the build script runs again, so these
+/// are what tells that the generated code is outdated.
+fn build_script_inputs(out_dir: &Path, package_dir: Option<&Path>) -> Vec<String> {
+    let output = match out_dir.parent().map(|dir| fs::read_to_string(dir.join("output"))) {
+        Some(Ok(output)) => output,
+        _ => return Vec::new(),
+    };
+    let mut inputs = Vec::new();
+    let mut watches_files = false;
+    for line in output.lines() {
+        if let Some(path) = line.strip_prefix("cargo:rerun-if-changed=") {
+            watches_files = true;
+            if let Some(package_dir) = package_dir {
+                inputs.push(package_dir.join(path).display().to_string());
+            }
+        } else if let Some(name) = line.strip_prefix("cargo:rerun-if-env-changed=") {
+            inputs.push(format!("${}", name));
+        }
+    }
+    match package_dir {
+        Some(package_dir) if !watches_files => {
+            for entry in ignore::WalkBuilder::new(package_dir).build() {
+                let entry = t!(entry);
+                if entry.file_type().map_or(false, |ty| ty.is_file()) {
+                    inputs.push(entry.path().display().to_string());
+                }
+            }
+        }
+        _ => {}
+    }
+    inputs
+}
+
+/// Why a stamp read back by bootstrap can't be trusted.
+#[derive(Debug, PartialEq, Eq)]
+pub enum StampError {
+    /// The stamp was written by a bootstrap using another stamp format.
+    Outdated,
+    /// The stamp doesn't end in a trailer matching its contents.
+    Truncated,
+}
+
+impl fmt::Display for StampError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StampError::Outdated => f.write_str("outdated"),
+            StampError::Truncated => f.write_str("truncated"),
+        }
+    }
+}
+
+/// Sorts the stamp entries `deps` and drops the duplicates. On a
+/// case-insensitive filesystem, that includes paths only differing in case or
+/// separators, e.g. `C:\build` and `c:/build`, of which the first in sort order
+/// is kept.
+fn dedup_stamp_entries(deps: &mut Vec<(PathBuf, DependencyType)>, case_insensitive: bool) {
+    if !case_insensitive {
+        deps.sort();
+        deps.dedup();
+        return;
+    }
+    let key = |path: &Path| path.to_string_lossy().replace('/', "\\").to_lowercase();
+    deps.sort_by_cached_key(|(path, dependency_type)| (key(path), *dependency_type, path.clone()));
+    deps.dedup_by(|(path, dependency_type), (kept, kept_type)| {
+        dependency_type == kept_type && key(path) == key(kept)
+    });
+}
+
+/// Serializes the entries of a stamp. Each entry is a type byte followed by
+/// the path and a NUL byte. The entries are preceded by `STAMP_VERSION` and
+/// followed by a `c` trailer holding their count and a checksum of everything
+/// before it, so that `decode_stamp` can tell whether a stamp is complete.
+///
+/// Paths within the build directory `root` are stored relative to it, so that
+/// the stamps stay valid when the build directory is moved or copied.
+pub fn encode_stamp(deps: &[(PathBuf, DependencyType)], root: &Path) -> Vec<u8> {
+    let mut contents = STAMP_VERSION.as_bytes().to_vec();
+    contents.extend(b"\0");
     for (dep, dependency_type) in deps.iter() {
-        new_contents.extend(match *dependency_type {
+        contents.extend(match *dependency_type {
             DependencyType::Host => b"h",
             DependencyType::Target => b"t",
             DependencyType::TargetSelfContained => b"s",
         });
-        new_contents.extend(dep.to_str().unwrap().as_bytes());
-        new_contents.extend(b"\0");
+        let dep = dep.strip_prefix(root).unwrap_or(dep);
+        contents.extend(dep.to_str().unwrap().as_bytes());
+        contents.extend(b"\0");
     }
-    t!(fs::write(&stamp, &new_contents));
-    deps.into_iter().map(|(d, _)| d).collect()
+    let trailer = format!("c{}:{:016x}\0", deps.len(), stamp_checksum(&contents));
+    contents.extend(trailer.as_bytes());
+    contents
+}
+
+/// Parses a stamp written by `encode_stamp`, rejecting it unless it's in the
+/// current format and complete. Relative paths are resolved against the build
+/// directory `root`.
+pub fn decode_stamp(
+    contents: &[u8],
+    root: &Path,
+) -> Result<Vec<(PathBuf, DependencyType)>, StampError> {
+    let mut header = STAMP_VERSION.as_bytes().to_vec();
+    header.push(0);
+    if !contents.starts_with(&header) {
+        // A stamp cut short within the header could still be a current one.
+        return Err(if header.starts_with(contents) {
+            StampError::Truncated
+        } else {
+            StampError::Outdated
+        });
+    }
+
+    // The trailer is the last NUL-terminated part, and nothing follows it.
+    let body = match contents.split_last() {
+        Some((0, body)) => body,
+        _ => return Err(StampError::Truncated),
+    };
+    let trailer_start = body.iter().rposition(|b| *b == 0).map_or(0, |i| i + 1);
+    let trailer = str::from_utf8(&body[trailer_start..]).map_err(|_| StampError::Truncated)?;
+    let (count, checksum) = trailer
+        .strip_prefix('c')
+        .and_then(|trailer| {
+            let mut parts = trailer.splitn(2, ':');
+            let count = parts.next()?.parse::<usize>().ok()?;
+            let checksum = u64::from_str_radix(parts.next()?, 16).ok()?;
+            Some((count, checksum))
+        })
+        .ok_or(StampError::Truncated)?;
+    if checksum != stamp_checksum(&contents[..trailer_start]) {
+        return Err(StampError::Truncated);
+    }
+
+    let mut deps = Vec::new();
+    for part in contents[header.len()..trailer_start].split(|b| *b == 0) {
+        if part.is_empty() {
+            continue;
+        }
+        let dependency_type = match part[0] as char {
+            'h' => DependencyType::Host,
+            's' => DependencyType::TargetSelfContained,
+            't' => DependencyType::Target,
+            _ => return Err(StampError::Truncated),
+        };
+        let path = str::from_utf8(&part[1..]).map_err(|_| StampError::Truncated)?;
+        deps.push((root.join(path), dependency_type));
+    }
+    if deps.len() != count {
+        return Err(StampError::Truncated);
+    }
+    Ok(deps)
+}
+
+/// FNV-1a, which unlike `DefaultHasher` is stable across Rust releases.
+fn stamp_checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Explains which step and crates a failed Cargo invocation was for, as the
+/// banner of the step has usually scrolled away by then, and how to check only
+/// those crates again.
+fn print_failure_trailer(builder: &Builder<'_>, command_line: &str, crates: &BTreeSet<String>) {
+    let step = builder.current_step().unwrap_or("bootstrap");
+    if crates.is_empty() {
+        eprintln!("error: `{}` failed", step);
+        return;
+    }
+    let crates = crates.iter().map(|krate| &krate[..]).collect::<Vec<_>>();
+    eprintln!("error: `{}` failed in {}", step, crates.join(", "));
+    if builder.kind == Kind::Check {
+        let packages = crates.iter().map(|krate| format!(" -p {}", krate)).collect::<String>();
+        eprintln!("To check only these crates again, run `x.py check{}`", packages);
+    }
+    eprintln!("To run Cargo for them directly, run:");
+    eprintln!("    {}", restrict_command_line(command_line, &crates));
+}
+
+/// Shows what a Cargo invocation of `x.py check --dry-run` would check.
+fn print_plan(builder: &Builder<'_>, cargo: &Cargo, stamp: &Path) {
+    let plan = PlannedStep::new(builder, cargo, stamp);
+    match plan.target {
+        Some(ref target) => println!("{} ({})", plan.step, target),
+        None => println!("{}", plan.step),
+    }
+    if plan.crates.is_empty() {
+        println!("    crates: (default members)");
+    } else {
+        println!("    crates: {}", plan.crates.join(", "));
+    }
+    println!("    stamp: {}", stamp.display());
+    builder.verbose(&format!("    command: {}", cargo.command_line()));
+}
+
+/// A Cargo invocation a step would run, as `--dry-run` and `--print-config`
+/// show it.
+#[derive(Serialize)]
+pub struct PlannedStep {
+    pub step: String,
+    pub target: Option<String>,
+    /// The crates selected with `-p`, or none for the default members.
+    pub crates: Vec<String>,
+    pub stamp: PathBuf,
+    pub rustflags: String,
+}
+
+impl PlannedStep {
+    fn new(builder: &Builder<'_>, cargo: &Cargo, stamp: &Path) -> PlannedStep {
+        let command_line = cargo.command_line();
+        let args = split_command_line(&command_line);
+        let arg_after = |flag: &str| {
+            let flag = format!("{:?}", flag);
+            args.windows(2)
+                .filter(move |pair| pair[0] == flag)
+                .map(|pair| pair[1].trim_matches('"').to_string())
+        };
+        // The order of `-p` follows the crate graph, which isn't stable.
+        let mut crates = arg_after("-p").collect::<Vec<_>>();
+        crates.sort();
+        PlannedStep {
+            step: builder.current_step().unwrap_or("bootstrap").to_string(),
+            target: arg_after("--target").next(),
+            crates,
+            stamp: stamp.to_path_buf(),
+            rustflags: cargo.rustflags().to_string(),
+        }
+    }
+}
+
+/// Rewrites the quoted `command_line` of a Cargo invocation so that it selects
+/// only `crates`, by dropping the `-p` flags for any others.
+pub fn restrict_command_line(command_line: &str, crates: &[&str]) -> String {
+    let mut restricted = Vec::new();
+    let mut selected_any = false;
+    let mut args = split_command_line(command_line).into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "\"-p\"" {
+            let package = args.next().unwrap_or_default();
+            if crates.iter().any(|krate| package == format!("{:?}", krate)) {
+                restricted.push(arg);
+                restricted.push(package);
+                selected_any = true;
+            }
+            continue;
+        }
+        restricted.push(arg);
+    }
+    if !selected_any {
+        for krate in crates {
+            restricted.push(String::from("\"-p\""));
+            restricted.push(format!("{:?}", krate));
+        }
+    }
+    restricted.join(" ")
+}
+
+/// Splits a command line as formatted by `Command`'s `Debug` implementation
+/// into its quoted arguments, which can contain escaped quotes.
+fn split_command_line(command_line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in command_line.chars() {
+        if quoted {
+            current.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => quoted = false,
+                _ => {}
+            }
+        } else if c == ' ' {
+            if !current.is_empty() {
+                args.push(current.clone());
+                current.clear();
+            }
+        } else {
+            quoted = c == '"';
+            current.push(c);
+        }
+    }
+    if !current.is_empty() {
+        args.push(current);
+    }
+    args
+}
+
+/// Where the fast-fresh probe of `stamp` is kept: the hash of every source
+/// file that went into the stamped artifacts, so that a later run can tell
+/// they're unchanged without starting Cargo.
+fn fresh_probe_path(stamp: &Path) -> PathBuf {
+    stamp.with_extension("fresh")
+}
+
+/// The first line of a fast-fresh probe, tying it to the invocation `key` and
+/// to the exact stamp it was written for.
+fn fresh_probe_header(stamp: &Path, key: u64) -> Option<String> {
+    let contents = fs::read(stamp).ok()?;
+    Some(format!("{:016x} {:016x}", key, stamp_checksum(&contents)))
+}
+
+/// Records the inputs of the artifacts in `stamp`, as listed by the dep-info
+/// files next to them, along with those of the build scripts that generated
+/// code for them, for `probe_is_fresh`. No probe is kept if any dep-info file
+/// is missing.
+pub fn write_fresh_probe(stamp: &Path, key: u64, root: &Path) {
+    let probe = fresh_probe_path(stamp);
+    let _ = fs::remove_file(&probe);
+    if is_partial_stamp(stamp) {
+        return;
+    }
+    let header = match fresh_probe_header(stamp, key) {
+        Some(header) => header,
+        None => return,
+    };
+    let deps = match fs::read(stamp).ok().and_then(|contents| decode_stamp(&contents, root).ok()) {
+        Some(deps) => deps,
+        None => return,
+    };
+
+    let mut inputs = Vec::new();
+    for (artifact, _) in deps {
+        let stem = match artifact.file_stem().and_then(|stem| stem.to_str()) {
+            Some(stem) => stem,
+            None => return,
+        };
+        // Dep-info files are named after the crate, without the `lib` prefix
+        // most platforms give libraries.
+        let dep_info = [stem.strip_prefix("lib").unwrap_or(stem), stem]
+            .iter()
+            .map(|name| artifact.with_file_name(format!("{}.d", name)))
+            .find(|path| path.exists());
+        match dep_info.and_then(|path| fs::read_to_string(path).ok()) {
+            Some(contents) => inputs.extend(parse_dep_info(&contents)),
+            None => return,
+        }
+    }
+    let mut inputs = inputs.iter().map(|input| input.display().to_string()).collect::<Vec<_>>();
+    if let Ok(build_inputs) = fs::read_to_string(build_inputs_path(stamp)) {
+        inputs.extend(build_inputs.lines().map(String::from));
+    }
+    inputs.sort();
+    inputs.dedup();
+
+    let mut contents = header;
+    contents.push('\n');
+    for input in inputs {
+        let hash = match input_hash(&input) {
+            Some(hash) => hash,
+            None => return,
+        };
+        contents.push_str(&format!("{} {}\n", hash, input));
+    }
+    t!(fs::write(&probe, contents));
+}
+
+/// The hash of a fast-fresh probe input: the contents of a file, or the value
+/// of the environment variable `$NAME`.
+fn input_hash(input: &str) -> Option<String> {
+    let hash = match input.strip_prefix('$') {
+        Some(name) => stamp_checksum(format!("{:?}", env::var_os(name)).as_bytes()),
+        None => stamp_checksum(&fs::read(input).ok()?),
+    };
+    Some(format!("{:016x}", hash))
+}
+
+/// Returns whether the probe written by `write_fresh_probe` for the same `key`
+/// is still accurate: the stamp is the one it was written for, every artifact
+/// is still there and no input changed.
+pub fn probe_is_fresh(stamp: &Path, key: u64, root: &Path) -> bool {
+    probe_staleness(stamp, key, root).is_none()
+}
+
+/// Returns why the probe written by `write_fresh_probe` doesn't vouch for
+/// `stamp` anymore, e.g. which input changed, or `None` if it still does.
+pub fn probe_staleness(stamp: &Path, key: u64, root: &Path) -> Option<String> {
+    if !stamp.exists() {
+        return Some("stamp missing".to_string());
+    }
+    if is_partial_stamp(stamp) {
+        return Some("the last check didn't finish".to_string());
+    }
+    let contents = match fs::read_to_string(fresh_probe_path(stamp)) {
+        Ok(contents) => contents,
+        Err(_) => return Some("no inputs were recorded by the last check".to_string()),
+    };
+    let mut lines = contents.lines();
+    let header = lines.next().unwrap_or("");
+    let expected = match fresh_probe_header(stamp, key) {
+        Some(expected) => expected,
+        None => return Some("stamp unreadable".to_string()),
+    };
+    if header != expected {
+        return Some(if header.get(..16) != expected.get(..16) {
+            "invoked differently, e.g. rustflags changed".to_string()
+        } else {
+            "stamp rewritten since the inputs were recorded".to_string()
+        });
+    }
+    let deps = match fs::read(stamp).ok().and_then(|contents| decode_stamp(&contents, root).ok()) {
+        Some(deps) => deps,
+        None => return Some("stamp unreadable".to_string()),
+    };
+    if let Some((artifact, _)) = deps.iter().find(|(artifact, _)| !artifact.exists()) {
+        return Some(format!("`{}` missing", artifact.display()));
+    }
+    for line in lines {
+        let mut parts = line.splitn(2, ' ');
+        let (hash, input) = match (parts.next(), parts.next()) {
+            (Some(hash), Some(input)) => (hash, input),
+            _ => return Some("recorded inputs unreadable".to_string()),
+        };
+        match input_hash(input) {
+            Some(ref current) if current == hash => {}
+            Some(_) => return Some(format!("`{}` changed", input)),
+            None => return Some(format!("`{}` missing", input)),
+        }
+    }
+    None
+}
+
+/// Returns the inputs listed in a Makefile-style dep-info file as written by
+/// rustc, where spaces within paths are escaped with a backslash.
+pub fn parse_dep_info(contents: &str) -> Vec<PathBuf> {
+    let mut inputs = Vec::new();
+    for line in contents.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        // The target can contain a drive letter's colon but not a colon
+        // followed by a space, which is what separates it from its inputs.
+        let deps = match line.find(": ") {
+            Some(i) => &line[i + 2..],
+            None => continue,
+        };
+        let mut current = String::new();
+        let mut chars = deps.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(' ') => current.push(' '),
+                    Some(c) => {
+                        current.push('\\');
+                        current.push(c);
+                    }
+                    None => current.push('\\'),
+                },
+                ' ' => {
+                    if !current.is_empty() {
+                        inputs.push(PathBuf::from(&current));
+                        current.clear();
+                    }
+                }
+                c => current.push(c),
+            }
+        }
+        if !current.is_empty() {
+            inputs.push(PathBuf::from(current));
+        }
+    }
+    inputs
 }
 
 pub fn stream_cargo(
@@ -1001,20 +1953,43 @@ pub fn stream_cargo(
     tail_args: Vec<String>,
     cb: &mut dyn FnMut(CargoMessage<'_>),
 ) -> bool {
+    try_stream_cargo(builder, cargo, tail_args, cb).unwrap_or_else(|failure| failure.exit(builder))
+}
+
+/// Like `stream_cargo`, but returns the failure instead of exiting if Cargo
+/// ran into one of the `build.step-timeout` and `build.step-budget` limits,
+/// or kept failing for a transient reason after `build.cargo-retries` retries.
+fn try_stream_cargo(
+    builder: &Builder<'_>,
+    cargo: Cargo,
+    tail_args: Vec<String>,
+    cb: &mut dyn FnMut(CargoMessage<'_>),
+) -> Result<bool, CargoFailure> {
     let mut cargo = Command::from(cargo);
     if builder.config.dry_run {
-        return true;
+        return Ok(true);
     }
     // Instruct Cargo to give us json messages on stdout, critically leaving
-    // stderr as piped so we can get those pretty colors.
-    let mut message_format = if builder.config.json_output {
+    // stderr as piped so we can get those pretty colors. The error format
+    // only picks how Cargo renders diagnostics, the messages we parse here
+    // are always JSON.
+    //
+    // For `--report`, and for the check-like commands, which trace a failure
+    // back to the crates with errors, the diagnostics have to be part of the
+    // JSON as well, so then they're printed from here instead of by Cargo.
+    let error_format = builder.config.rustc_error_format.as_deref();
+    let json_diagnostics = builder.config.json_output || error_format == Some("json");
+    let report = report::path(builder).is_some() || sarif::path(builder).is_some();
+    let mut message_format = if json_diagnostics || report || builder.is_check_like() {
         String::from("json")
     } else {
         String::from("json-render-diagnostics")
     };
-    if let Some(s) = &builder.config.rustc_error_format {
-        message_format.push_str(",json-diagnostic-");
-        message_format.push_str(s);
+    if !json_diagnostics && message_format == "json" && colors_diagnostics(builder) {
+        message_format.push_str(",json-diagnostic-rendered-ansi");
+    }
+    if error_format == Some("short") {
+        message_format.push_str(",json-diagnostic-short");
     }
     cargo.arg("--message-format").arg(message_format).stdout(Stdio::piped());
 
@@ -1022,48 +1997,340 @@ pub fn stream_cargo(
         cargo.arg(arg);
     }
 
-    builder.verbose(&format!("running: {:?}", cargo));
-    let mut child = match cargo.spawn() {
-        Ok(child) => child,
-        Err(e) => panic!("failed to execute command: {:?}\nerror: {}", cargo, e),
-    };
+    // With a limit, Cargo's progress on stderr counts as output too, and the
+    // whole tree of processes has to go if it's hit.
+    let limits =
+        StepLimits { idle: builder.config.step_timeout, total: builder.config.step_budget };
+    let limited = limits.idle.is_some() || limits.total.is_some();
+    if limited {
+        util::ProcessTree::prepare(&mut cargo);
+    }
+    // Retrying needs to see what went wrong, which Cargo tells on stderr.
+    let retries = builder.config.cargo_retries;
+    if limited || retries > 0 {
+        cargo.stderr(Stdio::piped());
+        if util::stderr_is_terminal() {
+            cargo.env("CARGO_TERM_COLOR", "always");
+        }
+    }
 
-    // Spawn Cargo slurping up its JSON output. We'll start building up the
-    // `deps` array of all files it generated along with a `toplevel` array of
-    // files we need to probe for later.
-    let stdout = BufReader::new(child.stdout.take().unwrap());
-    for line in stdout.lines() {
-        let line = t!(line);
-        match serde_json::from_str::<CargoMessage<'_>>(&line) {
-            Ok(msg) => {
-                if builder.config.json_output {
-                    // Forward JSON to stdout.
-                    println!("{}", line);
+    let mut attempt = 0;
+    loop {
+        builder.verbose(&format!("running: {:?}", cargo));
+        let mut child = match cargo.spawn() {
+            Ok(child) => child,
+            Err(e) => panic!("failed to execute command: {:?}\nerror: {}", cargo, e),
+        };
+        let tree = if limited { Some(util::ProcessTree::new(&child)) } else { None };
+
+        // Slurp up Cargo's JSON output. We'll start building up the `deps`
+        // array of all files it generated along with a `toplevel` array of
+        // files we need to probe for later.
+        let mut tail = OutputTail::default();
+        // Cargo's own errors, and whether rustc reported any, for telling a
+        // transient failure from one that running Cargo again won't fix.
+        let mut stderr = Vec::new();
+        let mut compile_errors = false;
+        let watched = watch_output(&mut child, limits, &mut |line| {
+            let line = match line {
+                OutputLine::Stdout(line) => line,
+                OutputLine::Stderr(line) => {
+                    eprintln!("{}", line);
+                    tail.push(&line);
+                    stderr.push(line);
+                    return;
+                }
+            };
+            on_cargo_line(builder, &line, json_diagnostics, report, &mut tail, &mut |msg| {
+                if let CargoMessage::CompilerMessage { ref message, .. } = msg {
+                    compile_errors |= message.level.starts_with("error");
                 }
                 cb(msg)
+            });
+        });
+
+        if let Err(timeout) = watched {
+            if let Some(tree) = tree {
+                tree.kill(&mut child);
             }
-            // If this was informational, just print it out and continue
-            Err(_) => println!("{}", line),
+            return Err(CargoFailure::Timeout(format!(
+                "`{}` {}, so it was killed\nlast {} lines of output:\n{}",
+                builder.current_step().unwrap_or("cargo"),
+                timeout,
+                tail.lines.len(),
+                tail
+            )));
         }
-    }
 
-    // Make sure Cargo actually succeeded after we read all of its stdout.
-    let status = t!(child.wait());
-    if !status.success() {
+        // Make sure Cargo actually succeeded after we read all of its stdout.
+        let status = t!(child.wait());
+        if status.success() {
+            return Ok(true);
+        }
         eprintln!(
             "command did not execute successfully: {:?}\n\
                   expected success, got: {}",
             cargo, status
         );
+        let transient = if compile_errors { None } else { classify_failure(&stderr) };
+        match transient {
+            Some(failure) if attempt < retries => {
+                attempt += 1;
+                let delay = Duration::from_secs(1 << attempt);
+                println!(
+                    "cargo failed with {}, retrying in {}s ({} of {})",
+                    failure,
+                    delay.as_secs(),
+                    attempt,
+                    retries
+                );
+                thread::sleep(delay);
+            }
+            Some(failure) => {
+                return Err(CargoFailure::Bootstrap(format!(
+                    "cargo failed with {} after {} {}",
+                    failure,
+                    attempt,
+                    if attempt == 1 { "retry" } else { "retries" }
+                )));
+            }
+            None => return Ok(false),
+        }
+    }
+}
+
+/// Handles a line Cargo printed to stdout, printing the diagnostics in it
+/// and passing the message on to `cb`.
+/// Whether Cargo would color the diagnostics it renders, for rendering them
+/// from its JSON messages instead: as `CARGO_TERM_COLOR` says, and otherwise
+/// in CI, where Cargo is passed `--color always`, or if stderr is a terminal.
+fn colors_diagnostics(builder: &Builder<'_>) -> bool {
+    match env::var("CARGO_TERM_COLOR").as_deref() {
+        Ok("always") => true,
+        Ok("never") => false,
+        _ => builder.ci_env != CiEnv::None || util::stderr_is_terminal(),
+    }
+}
+
+fn on_cargo_line(
+    builder: &Builder<'_>,
+    line: &str,
+    json_diagnostics: bool,
+    report: bool,
+    tail: &mut OutputTail,
+    cb: &mut dyn FnMut(CargoMessage<'_>),
+) {
+    match serde_json::from_str::<CargoMessage<'_>>(line) {
+        Ok(msg) => {
+            if builder.config.json_output {
+                // Forward JSON to stdout.
+                println!("{}", line);
+            }
+            if let CargoMessage::CompilerMessage { ref package_id, ref message } = msg {
+                builder.diagnostic_count.set(builder.diagnostic_count.get() + 1);
+                if !builder.config.json_output && !sarif::only(builder) {
+                    if json_diagnostics {
+                        println!("{}", line);
+                    } else if let Some(ref rendered) = message.rendered {
+                        eprint!("{}", rendered);
+                    }
+                }
+                if let Some(ref rendered) = message.rendered {
+                    tail.push(rendered);
+                }
+                if report {
+                    let entry = report::Entry::new(package_id, message.clone());
+                    builder.diagnostics.borrow_mut().push(entry);
+                }
+            }
+            cb(msg)
+        }
+        // If this was informational, just print it out and continue
+        Err(_) => {
+            println!("{}", line);
+            tail.push(line);
+        }
+    }
+}
+
+/// Why a failed Cargo invocation might well succeed when run again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransientFailure {
+    /// Fetching the registry index or a crate failed.
+    Network,
+    /// Another process held on to one of Cargo's locks.
+    FileLock,
+}
+
+impl fmt::Display for TransientFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransientFailure::Network => f.write_str("a network error"),
+            TransientFailure::FileLock => f.write_str("a file lock error"),
+        }
+    }
+}
+
+/// Lowercase snippets of what Cargo prints to stderr for transient failures.
+const TRANSIENT_FAILURES: &[(&str, TransientFailure)] = &[
+    ("failed to update registry", TransientFailure::Network),
+    ("failed to download", TransientFailure::Network),
+    ("failed to fetch", TransientFailure::Network),
+    ("spurious network error", TransientFailure::Network),
+    ("couldn't resolve host", TransientFailure::Network),
+    ("could not resolve host", TransientFailure::Network),
+    ("connection reset by peer", TransientFailure::Network),
+    ("connection refused", TransientFailure::Network),
+    ("operation timed out", TransientFailure::Network),
+    ("ssl connect error", TransientFailure::Network),
+    ("failed to lock file", TransientFailure::FileLock),
+    ("failed to acquire package cache lock", TransientFailure::FileLock),
+    ("could not acquire package cache lock", TransientFailure::FileLock),
+    ("resource temporarily unavailable", TransientFailure::FileLock),
+];
+
+/// Tells from the `stderr` of a failed Cargo invocation whether it failed for
+/// a transient reason. A failing build script may well print the same things,
+/// but running it again is no use.
+fn classify_failure(stderr: &[String]) -> Option<TransientFailure> {
+    let stderr = stderr.iter().map(|line| line.to_lowercase()).collect::<Vec<_>>();
+    if stderr.iter().any(|line| line.contains("failed to run custom build command")) {
+        return None;
+    }
+    stderr.iter().find_map(|line| {
+        TRANSIENT_FAILURES
+            .iter()
+            .find(|(pattern, _)| line.contains(pattern))
+            .map(|&(_, failure)| failure)
+    })
+}
+
+/// Limits on how long Cargo may run, from `build.step-timeout` (or
+/// `--timeout`) and `build.step-budget`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StepLimits {
+    /// How long Cargo may go without printing anything.
+    pub idle: Option<Duration>,
+    /// How long Cargo may run in total.
+    pub total: Option<Duration>,
+}
+
+/// Which of the `StepLimits` Cargo ran into.
+#[derive(Debug, PartialEq)]
+pub enum Timeout {
+    Idle(Duration),
+    Total(Duration),
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Timeout::Idle(limit) => write!(f, "printed nothing for {}s", limit.as_secs()),
+            Timeout::Total(limit) => write!(f, "ran for more than {}s", limit.as_secs()),
+        }
+    }
+}
+
+/// A line of output of a watched process.
+pub enum OutputLine {
+    Stdout(String),
+    Stderr(String),
+}
+
+/// Hands each line `child` prints on its piped stdout, and stderr if that's
+/// piped as well, to `on_line` until both are closed, or until it runs into
+/// one of `limits`.
+pub fn watch_output(
+    child: &mut Child,
+    limits: StepLimits,
+    on_line: &mut dyn FnMut(OutputLine),
+) -> Result<(), Timeout> {
+    let (tx, rx) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                if tx.send(OutputLine::Stdout(t!(line))).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    if let Some(stderr) = child.stderr.take() {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines() {
+                if tx.send(OutputLine::Stderr(t!(line))).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(tx);
+
+    let start = Instant::now();
+    loop {
+        let remaining = limits.total.map(|total| total.checked_sub(start.elapsed()));
+        let (wait, timeout) = match (limits.idle, remaining) {
+            (_, Some(None)) => return Err(Timeout::Total(limits.total.unwrap())),
+            (Some(idle), Some(Some(left))) if left < idle => {
+                (Some(left), Timeout::Total(limits.total.unwrap()))
+            }
+            (Some(idle), _) => (Some(idle), Timeout::Idle(idle)),
+            (None, Some(Some(left))) => (Some(left), Timeout::Total(limits.total.unwrap())),
+            (None, None) => (None, Timeout::Idle(Duration::from_secs(0))),
+        };
+        let line = match wait {
+            Some(wait) => rx.recv_timeout(wait),
+            None => rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+        };
+        match line {
+            Ok(line) => on_line(line),
+            Err(mpsc::RecvTimeoutError::Timeout) => return Err(timeout),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+/// The last lines Cargo printed, for explaining where it got stuck.
+#[derive(Default)]
+struct OutputTail {
+    lines: VecDeque<String>,
+}
+
+impl OutputTail {
+    const LEN: usize = 50;
+
+    fn push(&mut self, text: &str) {
+        for line in text.lines() {
+            if self.lines.len() == OutputTail::LEN {
+                self.lines.pop_front();
+            }
+            self.lines.push_back(line.to_string());
+        }
+    }
+}
+
+impl fmt::Display for OutputTail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "    {}", line)?;
+        }
+        Ok(())
     }
-    status.success()
 }
 
 #[derive(Deserialize)]
 pub struct CargoTarget<'a> {
     crate_types: Vec<Cow<'a, str>>,
+    // Only used for the check budgets, which aren't worth failing over.
+    #[serde(default)]
+    name: Cow<'a, str>,
 }
 
+/// The crate types of the targets whose check the check budgets cover.
+const LIBRARY_CRATE_TYPES: &[&str] = &["lib", "rlib", "dylib", "proc-macro"];
+
 #[derive(Deserialize)]
 #[serde(tag = "reason", rename_all = "kebab-case")]
 pub enum CargoMessage<'a> {
@@ -1075,8 +2342,21 @@ pub enum CargoMessage<'a> {
     },
     BuildScriptExecuted {
         package_id: Cow<'a, str>,
+        out_dir: Cow<'a, str>,
     },
     BuildFinished {
         success: bool,
     },
+    CompilerMessage {
+        package_id: Cow<'a, str>,
+        message: report::Diagnostic,
+    },
+    /// How long Cargo took over a unit, with `-Ztimings=json`.
+    TimingInfo {
+        target: CargoTarget<'a>,
+        duration: f64,
+    },
 }
+
+#[cfg(test)]
+mod tests;
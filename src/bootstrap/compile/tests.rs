@@ -0,0 +1,447 @@
+use super::*;
+
+fn deps() -> Vec<(PathBuf, DependencyType)> {
+    vec![
+        (PathBuf::from("/build/deps/libcore-0123456789abcdef.rmeta"), DependencyType::Target),
+        (PathBuf::from("/build/deps/libstd-0123456789abcdef.rmeta"), DependencyType::Target),
+        (PathBuf::from("/build/release/deps/libserde_derive-fedcba.so"), DependencyType::Host),
+        (PathBuf::from("/build/self-contained/crt1.o"), DependencyType::TargetSelfContained),
+        (PathBuf::from("/musl/lib/crti.o"), DependencyType::TargetSelfContained),
+    ]
+}
+
+fn root() -> &'static Path {
+    Path::new("/build")
+}
+
+#[test]
+fn stamp_roundtrip() {
+    assert_eq!(decode_stamp(&encode_stamp(&deps(), root()), root()), Ok(deps()));
+    assert_eq!(decode_stamp(&encode_stamp(&[], root()), root()), Ok(vec![]));
+}
+
+#[test]
+fn stamp_survives_moving_the_build_dir() {
+    let moved = decode_stamp(&encode_stamp(&deps(), root()), Path::new("/moved/build")).unwrap();
+    let paths = moved.iter().map(|(path, _)| path.to_str().unwrap()).collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        [
+            "/moved/build/deps/libcore-0123456789abcdef.rmeta",
+            "/moved/build/deps/libstd-0123456789abcdef.rmeta",
+            "/moved/build/release/deps/libserde_derive-fedcba.so",
+            "/moved/build/self-contained/crt1.o",
+            // Only paths within the build directory move along with it.
+            "/musl/lib/crti.o",
+        ]
+    );
+}
+
+#[test]
+fn truncated_stamp_is_rejected() {
+    let contents = encode_stamp(&deps(), root());
+    for len in 0..contents.len() {
+        assert_eq!(
+            decode_stamp(&contents[..len], root()),
+            Err(StampError::Truncated),
+            "stamp cut to {} bytes was accepted",
+            len
+        );
+    }
+}
+
+#[test]
+fn corrupted_stamp_is_rejected() {
+    let mut contents = encode_stamp(&deps(), root());
+    let entry = STAMP_VERSION.len() + 2;
+    contents[entry] = b'X';
+    assert_eq!(decode_stamp(&contents, root()), Err(StampError::Truncated));
+}
+
+#[test]
+fn stamp_in_older_format_is_outdated() {
+    // What `run_cargo` wrote before stamps had a header and a trailer.
+    let legacy = b"t/build/stage0-std/deps/libcore-0123456789abcdef.rmeta\0";
+    assert_eq!(decode_stamp(legacy, root()), Err(StampError::Outdated));
+
+    let mut contents = encode_stamp(&deps(), root());
+    contents[1] = b'1';
+    assert_eq!(decode_stamp(&contents, root()), Err(StampError::Outdated));
+}
+
+#[test]
+fn dep_info_inputs() {
+    let contents = "\
+/build/deps/core-0123.rmeta: library/core/src/lib.rs library/core/src/my\\ file.rs
+
+/build/deps/core-0123.d: library/core/src/lib.rs library/core/src/my\\ file.rs
+
+library/core/src/lib.rs:
+library/core/src/my\\ file.rs:
+
+# env-dep:CFG_RELEASE=1.50.0
+";
+    let inputs = parse_dep_info(contents);
+    assert_eq!(
+        inputs,
+        [
+            "library/core/src/lib.rs",
+            "library/core/src/my file.rs",
+            "library/core/src/lib.rs",
+            "library/core/src/my file.rs",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn fresh_probe_tracks_inputs() {
+    let dir = env::temp_dir().join(format!("bootstrap-fresh-probe-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    t!(fs::create_dir_all(dir.join("deps")));
+    let source = dir.join("lib.rs");
+    let artifact = dir.join("deps/libcore-0123.rmeta");
+    let stamp = dir.join(".libcore.stamp");
+    t!(fs::write(&source, "pub fn f() {}"));
+    t!(fs::write(&artifact, ""));
+    let dep_info = format!("{}: {}\n", artifact.display(), source.display());
+    t!(fs::write(dir.join("deps/core-0123.d"), dep_info));
+    t!(fs::write(&stamp, encode_stamp(&[(artifact, DependencyType::Target)], &dir)));
+
+    assert!(!probe_is_fresh(&stamp, 1, &dir));
+    write_fresh_probe(&stamp, 1, &dir);
+    assert!(probe_is_fresh(&stamp, 1, &dir));
+    assert!(!probe_is_fresh(&stamp, 2, &dir), "probe was accepted for another invocation");
+
+    t!(fs::write(&source, "pub fn g() {}"));
+    assert!(!probe_is_fresh(&stamp, 1, &dir), "probe was accepted after an input changed");
+
+    t!(fs::remove_dir_all(&dir));
+}
+
+#[test]
+fn stamp_entries_deduplicated_ignoring_case() {
+    let entries = || {
+        vec![
+            (PathBuf::from("C:/build/deps/libcore-0123.rmeta"), DependencyType::Target),
+            (PathBuf::from(r"c:\build\deps\libcore-0123.rmeta"), DependencyType::Target),
+            (PathBuf::from(r"C:\build\deps\libcore-0123.rmeta"), DependencyType::Host),
+            (PathBuf::from(r"C:\build\deps\liballoc-4567.rmeta"), DependencyType::Target),
+            (PathBuf::from(r"C:\build\deps\liballoc-4567.rmeta"), DependencyType::Target),
+        ]
+    };
+
+    let mut deps = entries();
+    dedup_stamp_entries(&mut deps, true);
+    assert_eq!(
+        deps,
+        [
+            (PathBuf::from(r"C:\build\deps\liballoc-4567.rmeta"), DependencyType::Target),
+            (PathBuf::from(r"C:\build\deps\libcore-0123.rmeta"), DependencyType::Host),
+            (PathBuf::from("C:/build/deps/libcore-0123.rmeta"), DependencyType::Target),
+        ]
+    );
+
+    let mut deps = entries();
+    dedup_stamp_entries(&mut deps, false);
+    assert_eq!(deps.len(), 4);
+}
+
+#[test]
+fn probe_staleness_names_the_cause() {
+    let dir = env::temp_dir().join(format!("bootstrap-probe-staleness-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    t!(fs::create_dir_all(dir.join("deps")));
+    let source = dir.join("lib.rs");
+    let artifact = dir.join("deps/libcore-0123.rmeta");
+    let stamp = dir.join(".libcore.stamp");
+    let staleness = || probe_staleness(&stamp, 1, &dir);
+
+    assert_eq!(staleness().as_deref(), Some("stamp missing"));
+    t!(fs::write(&source, "pub fn f() {}"));
+    t!(fs::write(&artifact, ""));
+    let dep_info = format!("{}: {}\n", artifact.display(), source.display());
+    t!(fs::write(dir.join("deps/core-0123.d"), dep_info));
+    t!(fs::write(&stamp, encode_stamp(&[(artifact.clone(), DependencyType::Target)], &dir)));
+    assert_eq!(staleness().as_deref(), Some("no inputs were recorded by the last check"));
+
+    write_fresh_probe(&stamp, 1, &dir);
+    assert_eq!(staleness(), None);
+    assert_eq!(
+        probe_staleness(&stamp, 2, &dir).as_deref(),
+        Some("invoked differently, e.g. rustflags changed")
+    );
+
+    t!(fs::write(&source, "pub fn g() {}"));
+    assert_eq!(staleness(), Some(format!("`{}` changed", source.display())));
+    t!(fs::remove_file(&source));
+    assert_eq!(staleness(), Some(format!("`{}` missing", source.display())));
+    t!(fs::remove_file(&artifact));
+    assert_eq!(staleness(), Some(format!("`{}` missing", artifact.display())));
+
+    t!(fs::remove_dir_all(&dir));
+}
+
+#[test]
+fn command_line_restricted_to_failed_crates() {
+    let command_line = r#"RUSTFLAGS="-Zmacro-backtrace -Cfoo=\"a b\"" "cargo" "check" "-p" "rustc_middle" "-p" "rustc_typeck" "-p" "rustc_driver" "--release""#;
+    assert_eq!(
+        restrict_command_line(command_line, &["rustc_typeck"]),
+        r#"RUSTFLAGS="-Zmacro-backtrace -Cfoo=\"a b\"" "cargo" "check" "-p" "rustc_typeck" "--release""#
+    );
+    assert_eq!(
+        restrict_command_line(
+            r#""cargo" "check" "--manifest-path" "src/tools/miri/Cargo.toml""#,
+            &["miri"]
+        ),
+        r#""cargo" "check" "--manifest-path" "src/tools/miri/Cargo.toml" "-p" "miri""#
+    );
+}
+
+#[test]
+fn sysroot_fingerprint_roundtrip() {
+    let fingerprint = SysrootFingerprint {
+        commit: Some("0123456789abcdef0123456789abcdef01234567".to_string()),
+        stamp_hash: "00000000deadbeef".to_string(),
+    };
+    assert_eq!(SysrootFingerprint::parse(&fingerprint.to_string()), Some(fingerprint));
+
+    let outside_git = SysrootFingerprint { commit: None, stamp_hash: "1".to_string() };
+    assert_eq!(SysrootFingerprint::parse(&outside_git.to_string()), Some(outside_git));
+    assert_eq!(SysrootFingerprint::parse("commit: unknown\n"), None);
+}
+
+/// Runs `script` as a stand-in for a slow Cargo the way `stream_cargo` runs
+/// the real one, and returns the lines it printed before it exited or ran
+/// into `limits`.
+#[cfg(unix)]
+fn watch_fake_cargo(
+    name: &str,
+    script: &str,
+    limits: StepLimits,
+) -> (Vec<String>, Result<(), Timeout>) {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = env::temp_dir().join(format!("bootstrap-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    t!(fs::create_dir_all(&dir));
+    let cargo = dir.join("cargo");
+    t!(fs::write(&cargo, format!("#!/bin/sh\n{}\n", script)));
+    t!(fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)));
+
+    let mut cmd = Command::new(&cargo);
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    util::ProcessTree::prepare(&mut cmd);
+    let mut child = t!(cmd.spawn());
+    let tree = util::ProcessTree::new(&child);
+    let mut lines = Vec::new();
+    let result = watch_output(&mut child, limits, &mut |line| match line {
+        OutputLine::Stdout(line) => lines.push(line),
+        OutputLine::Stderr(line) => lines.push(format!("stderr: {}", line)),
+    });
+    if result.is_err() {
+        tree.kill(&mut child);
+    } else {
+        t!(child.wait());
+    }
+    t!(fs::remove_dir_all(&dir));
+    (lines, result)
+}
+
+#[test]
+#[cfg(unix)]
+fn silent_cargo_is_killed() {
+    let idle = Duration::from_secs(1);
+    let limits = StepLimits { idle: Some(idle), total: None };
+    let start = Instant::now();
+    let (lines, result) = watch_fake_cargo("idle-timeout", "echo one\nsleep 30", limits);
+    assert_eq!(result, Err(Timeout::Idle(idle)));
+    assert_eq!(lines, ["one"]);
+    assert!(start.elapsed() < Duration::from_secs(10), "killing the sleeping cargo hung");
+}
+
+#[test]
+#[cfg(unix)]
+fn chatty_cargo_is_killed_once_over_budget() {
+    let total = Duration::from_secs(1);
+    let limits = StepLimits { idle: Some(Duration::from_secs(5)), total: Some(total) };
+    let script = "while true; do echo compiling; echo waiting >&2; sleep 0.1; done";
+    let (lines, result) = watch_fake_cargo("step-budget", script, limits);
+    assert_eq!(result, Err(Timeout::Total(total)));
+    assert!(lines.iter().any(|line| line == "compiling"));
+    assert!(lines.iter().any(|line| line == "stderr: waiting"));
+}
+
+#[test]
+#[cfg(unix)]
+fn quick_cargo_finishes_within_limits() {
+    let limits =
+        StepLimits { idle: Some(Duration::from_secs(5)), total: Some(Duration::from_secs(30)) };
+    let (lines, result) = watch_fake_cargo("quick", "echo one\nsleep 0.2\necho two", limits);
+    assert_eq!(result, Ok(()));
+    assert_eq!(lines, ["one", "two"]);
+}
+
+fn classify(stderr: &str) -> Option<TransientFailure> {
+    classify_failure(&stderr.lines().map(|line| line.to_string()).collect::<Vec<_>>())
+}
+
+#[test]
+fn registry_and_download_errors_are_transient() {
+    let index = "    Updating crates.io index
+error: failed to update registry `crates-io`
+
+Caused by:
+  failed to fetch `https://github.com/rust-lang/crates.io-index`
+
+Caused by:
+  network failure seems to have happened
+  if a proxy or similar is necessary `net.git-fetch-with-cli` may help here";
+    assert_eq!(classify(index), Some(TransientFailure::Network));
+
+    let download = " Downloading crates ...
+error: failed to download from `https://crates.io/api/v1/crates/cc/1.0.60/download`
+
+Caused by:
+  [6] Couldn't resolve host name (Could not resolve host: crates.io)";
+    assert_eq!(classify(download), Some(TransientFailure::Network));
+}
+
+#[test]
+fn lock_errors_are_transient() {
+    let lock = "error: failed to lock file: /build/x86_64-unknown-linux-gnu/.cargo-lock
+
+Caused by:
+  Resource temporarily unavailable (os error 11)";
+    assert_eq!(classify(lock), Some(TransientFailure::FileLock));
+}
+
+#[test]
+fn other_failures_are_not_transient() {
+    let compile = "error: could not compile `core`
+
+To learn more, run the command again with --verbose.";
+    assert_eq!(classify(compile), None);
+
+    let build_script = "error: failed to run custom build command for `openssl-sys v0.9.58`
+
+Caused by:
+  process didn't exit successfully: `build-script-build` (exit code: 101)
+  --- stderr
+  curl: (7) Failed to connect to example.com port 443: Connection refused";
+    assert_eq!(classify(build_script), None);
+
+    let manifest = "error: failed to parse manifest at `/src/library/std/Cargo.toml`";
+    assert_eq!(classify(manifest), None);
+}
+
+#[test]
+fn fresh_probe_tracks_build_script_inputs() {
+    let dir = env::temp_dir().join(format!("bootstrap-build-inputs-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    t!(fs::create_dir_all(dir.join("deps")));
+    t!(fs::create_dir_all(dir.join("build/std-0123/out")));
+    let package = dir.join("std");
+    t!(fs::create_dir_all(&package));
+    t!(fs::write(package.join("build.rs"), "fn main() {}"));
+    t!(fs::write(
+        dir.join("build/std-0123/output"),
+        "cargo:rerun-if-changed=build.rs\ncargo:rerun-if-env-changed=BOOTSTRAP_TEST_BUILD_INPUT\n"
+    ));
+    let package_id = format!("std 0.0.0 (path+file://{})", package.display());
+    assert_eq!(package_dir(&package_id), Some(&*package));
+    assert_eq!(
+        package_dir("libc 0.2.86 (registry+https://github.com/rust-lang/crates.io-index)"),
+        None
+    );
+    let inputs = build_script_inputs(&dir.join("build/std-0123/out"), package_dir(&package_id));
+    assert_eq!(
+        inputs,
+        [package.join("build.rs").display().to_string(), "$BOOTSTRAP_TEST_BUILD_INPUT".to_string()]
+    );
+
+    // The generated code is the only input rustc reports.
+    let generated = dir.join("build/std-0123/out/generated.rs");
+    let artifact = dir.join("deps/libstd-0123.rmeta");
+    let stamp = dir.join(".libstd.stamp");
+    t!(fs::write(&generated, ""));
+    t!(fs::write(&artifact, ""));
+    t!(fs::write(
+        dir.join("deps/std-0123.d"),
+        format!("{}: {}\n", artifact.display(), generated.display())
+    ));
+    t!(fs::write(&stamp, encode_stamp(&[(artifact, DependencyType::Target)], &dir)));
+    t!(fs::write(build_inputs_path(&stamp), inputs.join("\n") + "\n"));
+
+    write_fresh_probe(&stamp, 1, &dir);
+    assert!(probe_is_fresh(&stamp, 1, &dir));
+    env::set_var("BOOTSTRAP_TEST_BUILD_INPUT", "1");
+    assert!(!probe_is_fresh(&stamp, 1, &dir), "probe was accepted after an env input changed");
+    env::remove_var("BOOTSTRAP_TEST_BUILD_INPUT");
+    assert!(probe_is_fresh(&stamp, 1, &dir));
+    t!(fs::write(package.join("build.rs"), "fn main() { println!(); }"));
+    assert!(!probe_is_fresh(&stamp, 1, &dir), "probe was accepted after build.rs changed");
+
+    // Without files to watch, Cargo reruns the build script for any change
+    // to the package.
+    t!(fs::write(dir.join("build/std-0123/output"), ""));
+    let inputs = build_script_inputs(&dir.join("build/std-0123/out"), Some(&package));
+    assert_eq!(inputs, [package.join("build.rs").display().to_string()]);
+
+    t!(fs::remove_dir_all(&dir));
+}
+
+#[test]
+fn link_artifacts_into_the_sysroot() {
+    let dir = env::temp_dir().join(format!("bootstrap-link-artifacts-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    t!(fs::create_dir_all(&dir));
+    let src = dir.join("libcore-0123.rmeta");
+    let dst = dir.join("sysroot-libcore-0123.rmeta");
+    t!(fs::write(&src, "core"));
+    let cross_device = || {
+        let code = if cfg!(windows) { 17 } else { 18 };
+        Err(io::Error::from_raw_os_error(code))
+    };
+    let broken = || Err(io::Error::new(io::ErrorKind::Other, "broken"));
+    let denied = || Err(io::Error::new(io::ErrorKind::PermissionDenied, "denied"));
+
+    // A link that already exists is replaced, not written through.
+    t!(fs::write(&dst, "stale"));
+    assert!(t!(link_or_copy(&src, &dst, LinkArtifacts::Hardlink, |s, d| fs::hard_link(s, d))));
+    assert_eq!(t!(fs::read_to_string(&dst)), "core");
+
+    // Across devices, linking falls back to a copy that keeps the mtime.
+    let mtime = FileTime::from_unix_time(1_000_000_000, 0);
+    t!(filetime::set_file_mtime(&src, mtime));
+    assert!(!t!(link_or_copy(&src, &dst, LinkArtifacts::Hardlink, |_, _| cross_device())));
+    assert_eq!(t!(fs::read_to_string(&dst)), "core");
+    assert_eq!(FileTime::from_last_modification_time(&t!(fs::metadata(&dst))), mtime);
+
+    // Only `auto` copies whatever went wrong.
+    assert!(link_or_copy(&src, &dst, LinkArtifacts::Hardlink, |_, _| broken()).is_err());
+    assert!(link_or_copy(&src, &dst, LinkArtifacts::Hardlink, |_, _| denied()).is_err());
+    assert!(!t!(link_or_copy(&src, &dst, LinkArtifacts::Auto, |_, _| broken())));
+    assert!(!t!(link_or_copy(&src, &dst, LinkArtifacts::Copy, |_, _| unreachable!())));
+    assert_eq!(t!(fs::read_to_string(&dst)), "core");
+    t!(fs::remove_dir_all(&dir));
+}
+
+#[test]
+fn timing_info_message() {
+    let line = concat!(
+        r#"{"reason":"timing-info","package_id":"rustc_middle 0.0.0 (path+file:///rust)","#,
+        r#""target":{"kind":["lib"],"crate_types":["lib"],"name":"rustc_middle","#,
+        r#""src_path":"/rust/src/lib.rs","edition":"2018","doctest":true,"test":true},"#,
+        r#""mode":"check","duration":61.25,"rmeta_time":null}"#
+    );
+    match serde_json::from_str::<CargoMessage<'_>>(line).unwrap() {
+        CargoMessage::TimingInfo { target, duration } => {
+            assert_eq!(target.name, "rustc_middle");
+            assert_eq!(duration, 61.25);
+        }
+        _ => panic!("not a timing-info message"),
+    }
+}
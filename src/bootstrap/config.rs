@@ -10,15 +10,32 @@ use std::ffi::OsString;
 use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
 
 use crate::cache::{Interned, INTERNER};
 use crate::flags::Flags;
 pub use crate::flags::Subcommand;
+use crate::lints::LintSet;
 use crate::util::exe;
 use build_helper::t;
 use merge::Merge;
 use serde::Deserialize;
 
+/// The sanitizers std can be checked with, as `-Zsanitizer` takes them.
+pub(crate) const SANITIZERS: &[&str] = &["address", "hwaddress", "leak", "memory", "thread"];
+
+/// The tier 1 targets, which `--target tier1` stands for.
+const TIER1_TARGETS: &[&str] = &[
+    "i686-pc-windows-gnu",
+    "i686-pc-windows-msvc",
+    "i686-unknown-linux-gnu",
+    "x86_64-apple-darwin",
+    "x86_64-pc-windows-gnu",
+    "x86_64-pc-windows-msvc",
+    "x86_64-unknown-linux-gnu",
+];
+
 macro_rules! check_ci_llvm {
     ($name:expr) => {
         assert!(
@@ -52,7 +69,12 @@ pub struct Config {
     pub compiler_docs: bool,
     pub docs: bool,
     pub locked_deps: bool,
+    pub step_timeout: Option<Duration>,
+    pub step_budget: Option<Duration>,
+    pub cargo_retries: u32,
     pub vendor: bool,
+    pub offline: bool,
+    pub check_out_dir: Option<PathBuf>,
     pub target_config: HashMap<TargetSelection, Target>,
     pub full_bootstrap: bool,
     pub extended: bool,
@@ -64,6 +86,9 @@ pub struct Config {
     pub include_default_paths: bool,
     pub rustc_error_format: Option<String>,
     pub json_output: bool,
+    pub emit_sysroot_manifest: bool,
+    pub link_artifacts: LinkArtifacts,
+    pub hermetic_env: bool,
     pub test_compare_mode: bool,
     pub llvm_libunwind: bool,
 
@@ -78,6 +103,9 @@ pub struct Config {
     pub cmd: Subcommand,
     pub incremental: bool,
     pub dry_run: bool,
+    // `dry_run` is also set for the pass preceding every build, this is only
+    // set for `--dry-run`.
+    pub explicit_dry_run: bool,
 
     pub deny_warnings: bool,
     pub backtrace_on_ice: bool,
@@ -122,6 +150,7 @@ pub struct Config {
     pub rust_debuginfo_level_tests: u32,
     pub rust_rpath: bool,
     pub rustc_parallel: bool,
+    pub rust_check_threads: Option<u32>,
     pub rustc_default_linker: Option<String>,
     pub rust_optimize_tests: bool,
     pub rust_dist_src: bool,
@@ -130,11 +159,28 @@ pub struct Config {
     pub rust_thin_lto_import_instr_limit: Option<u32>,
     pub rust_remap_debuginfo: bool,
     pub rust_new_symbol_mangling: bool,
+    pub rust_check_std_profiles: Vec<StdProfile>,
+    pub rust_check_facade_configs: bool,
+
+    pub fix_auto_format: bool,
+
+    pub check_all_targets: bool,
+    pub check_default_paths: Vec<PathBuf>,
+    pub check_incremental: Option<bool>,
+    pub check_sanitizer: Option<String>,
+    pub check_allowed_duplicates: Vec<String>,
+    pub check_clippy_lints: Option<PathBuf>,
+    pub clippy_lints: LintSet,
+
+    pub hooks_post_check: Vec<String>,
+    pub hooks_post_step: HashMap<String, Vec<String>>,
+    pub hooks_strict: bool,
 
     pub build: TargetSelection,
     pub hosts: Vec<TargetSelection>,
     pub targets: Vec<TargetSelection>,
     pub local_rebuild: bool,
+    pub require_matching_stage0: bool,
     pub jemalloc: bool,
     pub control_flow_guard: bool,
 
@@ -238,6 +284,66 @@ impl PartialEq<&str> for TargetSelection {
     }
 }
 
+/// A configuration of the standard library that `x.py check` can verify, as
+/// listed in `rust.check-std-profiles`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum StdProfile {
+    /// The default configuration, which is also what ends up in the sysroot.
+    PanicUnwind,
+    /// Built with `-Cpanic=abort` and without the `panic_unwind` runtime.
+    PanicAbort,
+}
+
+impl StdProfile {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "panic-unwind" => Ok(StdProfile::PanicUnwind),
+            "panic-abort" => Ok(StdProfile::PanicAbort),
+            _ => Err(format!("unknown std profile `{}`", name)),
+        }
+    }
+
+    /// The name used in `config.toml` and in progress messages.
+    pub fn name(self) -> &'static str {
+        match self {
+            StdProfile::PanicUnwind => "panic-unwind",
+            StdProfile::PanicAbort => "panic-abort",
+        }
+    }
+}
+
+/// How `add_to_sysroot` puts artifacts into the sysroot, as set by
+/// `build.link-artifacts`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LinkArtifacts {
+    /// Hard link them, and copy them if that fails for any reason.
+    Auto,
+    /// Hard link them, and only copy them when they're on different devices.
+    Hardlink,
+    /// Always copy them.
+    Copy,
+}
+
+impl Default for LinkArtifacts {
+    fn default() -> LinkArtifacts {
+        LinkArtifacts::Auto
+    }
+}
+
+impl LinkArtifacts {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "auto" => Ok(LinkArtifacts::Auto),
+            "hardlink" => Ok(LinkArtifacts::Hardlink),
+            "copy" => Ok(LinkArtifacts::Copy),
+            _ => Err(format!(
+                "unknown `build.link-artifacts` value `{}`, expected one of: auto, hardlink, copy",
+                name
+            )),
+        }
+    }
+}
+
 /// Per-target configuration stored in the global configuration structure.
 #[derive(Default)]
 pub struct Target {
@@ -257,6 +363,10 @@ pub struct Target {
     pub wasi_root: Option<PathBuf>,
     pub qemu_rootfs: Option<PathBuf>,
     pub no_std: bool,
+    /// Crates `x.py check` leaves out for this target, as they don't build for it.
+    pub skip_crates: Vec<String>,
+    /// Extra flags for rustc when compiling std and the compiler for this target.
+    pub rustflags: Vec<String>,
 }
 
 impl Target {
@@ -283,13 +393,28 @@ struct TomlConfig {
     rust: Option<Rust>,
     target: Option<HashMap<String, TomlTarget>>,
     dist: Option<Dist>,
+    fix: Option<Fix>,
+    check: Option<Check>,
+    hooks: Option<Hooks>,
     profile: Option<String>,
 }
 
 impl Merge for TomlConfig {
     fn merge(
         &mut self,
-        TomlConfig { build, install, llvm, rust, dist, target, profile: _, changelog_seen: _ }: Self,
+        TomlConfig {
+            build,
+            install,
+            llvm,
+            rust,
+            dist,
+            fix,
+            check,
+            hooks,
+            target,
+            profile: _,
+            changelog_seen: _,
+        }: Self,
     ) {
         fn do_merge<T: Merge>(x: &mut Option<T>, y: Option<T>) {
             if let Some(new) = y {
@@ -305,6 +430,9 @@ impl Merge for TomlConfig {
         do_merge(&mut self.llvm, llvm);
         do_merge(&mut self.rust, rust);
         do_merge(&mut self.dist, dist);
+        do_merge(&mut self.fix, fix);
+        do_merge(&mut self.check, check);
+        do_merge(&mut self.hooks, hooks);
         assert!(target.is_none(), "merging target-specific config is not currently supported");
     }
 }
@@ -329,7 +457,13 @@ struct Build {
     nodejs: Option<String>,
     python: Option<String>,
     locked_deps: Option<bool>,
+    step_timeout: Option<u64>,
+    step_budget: Option<u64>,
+    cargo_retries: Option<u32>,
     vendor: Option<bool>,
+    offline: Option<bool>,
+    check_out_dir: Option<String>,
+    target_groups: Option<HashMap<String, Vec<String>>>,
     full_bootstrap: Option<bool>,
     extended: Option<bool>,
     tools: Option<HashSet<String>>,
@@ -340,7 +474,11 @@ struct Build {
     low_priority: Option<bool>,
     configure_args: Option<Vec<String>>,
     local_rebuild: Option<bool>,
+    require_matching_stage0: Option<bool>,
     print_step_timings: Option<bool>,
+    emit_sysroot_manifest: Option<bool>,
+    link_artifacts: Option<String>,
+    hermetic_env: Option<bool>,
     doc_stage: Option<u32>,
     build_stage: Option<u32>,
     test_stage: Option<u32>,
@@ -404,6 +542,48 @@ struct Dist {
     missing_tools: Option<bool>,
 }
 
+/// TOML representation of how `x.py check` behaves.
+#[derive(Deserialize, Default, Clone, Merge)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Check {
+    all_targets: Option<bool>,
+    default_paths: Option<Vec<String>>,
+    incremental: Option<bool>,
+    sanitizer: Option<String>,
+    allowed_duplicates: Option<Vec<String>>,
+    clippy_lints: Option<String>,
+    profiles: Option<HashMap<String, CheckProfile>>,
+}
+
+/// TOML representation of a set of `x.py check` options, selected with
+/// `--profile`.
+#[derive(Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct CheckProfile {
+    all_targets: Option<bool>,
+    targets: Option<Vec<String>>,
+    deny_warnings: Option<bool>,
+    features: Option<Vec<String>>,
+    rustc_args: Option<Vec<String>>,
+    paths: Option<Vec<String>>,
+}
+
+/// TOML representation of the commands run after checks.
+#[derive(Deserialize, Default, Clone, Merge)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Hooks {
+    post_check: Option<Vec<String>>,
+    post_step: Option<HashMap<String, Vec<String>>>,
+    strict: Option<bool>,
+}
+
+/// TOML representation of how `x.py fix` behaves.
+#[derive(Deserialize, Default, Clone, Merge)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Fix {
+    auto_format: Option<bool>,
+}
+
 #[derive(Deserialize)]
 #[serde(untagged)]
 enum StringOrBool {
@@ -436,6 +616,7 @@ struct Rust {
     backtrace: Option<bool>,
     incremental: Option<bool>,
     parallel_compiler: Option<bool>,
+    check_threads: Option<u32>,
     default_linker: Option<String>,
     channel: Option<String>,
     musl_root: Option<String>,
@@ -460,6 +641,8 @@ struct Rust {
     llvm_libunwind: Option<bool>,
     control_flow_guard: Option<bool>,
     new_symbol_mangling: Option<bool>,
+    check_std_profiles: Option<Vec<String>>,
+    check_facade_configs: Option<bool>,
 }
 
 /// TOML representation of how each build target is configured.
@@ -480,6 +663,8 @@ struct TomlTarget {
     wasi_root: Option<String>,
     qemu_rootfs: Option<String>,
     no_std: Option<bool>,
+    skip_crates: Option<Vec<String>>,
+    rustflags: Option<Vec<String>>,
 }
 
 impl Config {
@@ -512,6 +697,7 @@ impl Config {
         config.ignore_git = false;
         config.rust_dist_src = true;
         config.rust_codegen_backends = vec![INTERNER.intern_str("llvm")];
+        config.rust_check_std_profiles = vec![StdProfile::PanicUnwind];
         config.deny_warnings = true;
         config.missing_tools = false;
 
@@ -529,6 +715,19 @@ impl Config {
     }
 
     pub fn parse(args: &[String]) -> Config {
+        Config::parse_inner(args, None)
+    }
+
+    /// Same as `parse`, but with the contents of config.toml given as `toml`,
+    /// for driving bootstrap as a library. `args` are the command line
+    /// arguments, which override the configuration as usual.
+    pub fn parse_with_toml(args: &[String], toml: &str) -> Result<Config, String> {
+        let toml = toml::from_str(toml)
+            .map_err(|err| format!("failed to parse TOML configuration: {}", err))?;
+        Ok(Config::parse_inner(args, Some(toml)))
+    }
+
+    fn parse_inner(args: &[String], toml: Option<TomlConfig>) -> Config {
         let flags = Flags::parse(&args);
 
         let mut config = Config::default_opts();
@@ -536,17 +735,16 @@ impl Config {
         config.include_default_paths = flags.include_default_paths;
         config.rustc_error_format = flags.rustc_error_format;
         config.json_output = flags.json_output;
+        config.emit_sysroot_manifest = flags.emit_sysroot_manifest;
         config.on_fail = flags.on_fail;
         config.jobs = flags.jobs.map(threads_from_config);
         config.cmd = flags.cmd;
         config.incremental = flags.incremental;
         config.dry_run = flags.dry_run;
+        config.explicit_dry_run = flags.dry_run;
         config.keep_stage = flags.keep_stage;
         config.keep_stage_std = flags.keep_stage_std;
         config.bindir = "bin".into(); // default
-        if let Some(value) = flags.deny_warnings {
-            config.deny_warnings = value;
-        }
 
         if config.dry_run {
             let dir = config.out.join("tmp-dry-run");
@@ -554,23 +752,10 @@ impl Config {
             config.out = dir;
         }
 
-        #[cfg(test)]
-        let get_toml = |_| TomlConfig::default();
-        #[cfg(not(test))]
-        let get_toml = |file: &Path| {
-            use std::process;
-
-            let contents = t!(fs::read_to_string(file), "`include` config not found");
-            match toml::from_str(&contents) {
-                Ok(table) => table,
-                Err(err) => {
-                    println!("failed to parse TOML configuration '{}': {}", file.display(), err);
-                    process::exit(2);
-                }
-            }
+        let mut toml = match toml {
+            Some(toml) => toml,
+            None => flags.config.as_deref().map(get_toml).unwrap_or_else(TomlConfig::default),
         };
-
-        let mut toml = flags.config.as_deref().map(get_toml).unwrap_or_else(TomlConfig::default);
         if let Some(include) = &toml.profile {
             let mut include_path = config.src.clone();
             include_path.push("src");
@@ -582,28 +767,57 @@ impl Config {
         }
 
         config.changelog_seen = toml.changelog_seen;
+
+        let profile = check_profile(&mut config.cmd, toml.check.as_ref());
+        let deny_warnings = flags.deny_warnings.or(profile.deny_warnings);
+        if let Some(value) = deny_warnings {
+            config.deny_warnings = value;
+        }
         if let Some(cfg) = flags.config {
             config.config = cfg;
         }
 
         let build = toml.build.unwrap_or_default();
+        let groups = build.target_groups.unwrap_or_default();
 
         config.hosts = if let Some(arg_host) = flags.host {
-            arg_host
+            expand_target_groups(arg_host, &groups)
         } else if let Some(file_host) = build.host {
-            file_host.iter().map(|h| TargetSelection::from_user(h)).collect()
+            let hosts = file_host.iter().map(|h| TargetSelection::from_user(h)).collect();
+            expand_target_groups(hosts, &groups)
         } else {
             vec![config.build]
         };
-        config.targets = if let Some(arg_target) = flags.target {
-            arg_target
-        } else if let Some(file_target) = build.target {
-            file_target.iter().map(|h| TargetSelection::from_user(h)).collect()
+        let file_targets = if let Some(file_target) = build.target {
+            let targets = file_target.iter().map(|h| TargetSelection::from_user(h)).collect();
+            expand_target_groups(targets, &groups)
         } else {
             // If target is *not* configured, then default to the host
             // toolchains.
             config.hosts.clone()
         };
+        let arg_target = flags.target.or_else(|| {
+            let targets = profile.targets.as_ref()?;
+            Some(targets.iter().map(|t| TargetSelection::from_user(t)).collect())
+        });
+        config.targets = match arg_target {
+            // `--target all` stands for every target config.toml mentions,
+            // including the ones that only have a `[target.*]` section.
+            Some(arg_target) if arg_target.iter().any(|t| *t == "all") => {
+                let mut targets = file_targets;
+                let mut configured = toml.target.iter().flat_map(|t| t.keys()).collect::<Vec<_>>();
+                configured.sort();
+                for triple in configured {
+                    let target = TargetSelection::from_user(triple);
+                    if !targets.contains(&target) {
+                        targets.push(target);
+                    }
+                }
+                targets
+            }
+            Some(arg_target) => expand_target_groups(arg_target, &groups),
+            None => file_targets,
+        };
 
         config.nodejs = build.nodejs.map(PathBuf::from);
         config.gdb = build.gdb.map(PathBuf::from);
@@ -614,7 +828,12 @@ impl Config {
         set(&mut config.submodules, build.submodules);
         set(&mut config.fast_submodules, build.fast_submodules);
         set(&mut config.locked_deps, build.locked_deps);
+        config.step_timeout = build.step_timeout.map(Duration::from_secs);
+        config.step_budget = build.step_budget.map(Duration::from_secs);
+        set(&mut config.cargo_retries, build.cargo_retries);
         set(&mut config.vendor, build.vendor);
+        set(&mut config.offline, build.offline);
+        config.check_out_dir = build.check_out_dir.map(|dir| config.src.join(dir));
         set(&mut config.full_bootstrap, build.full_bootstrap);
         set(&mut config.extended, build.extended);
         config.tools = build.tools;
@@ -627,7 +846,22 @@ impl Config {
         set(&mut config.cargo_native_static, build.cargo_native_static);
         set(&mut config.configure_args, build.configure_args);
         set(&mut config.local_rebuild, build.local_rebuild);
+        set(&mut config.require_matching_stage0, build.require_matching_stage0);
         set(&mut config.print_step_timings, build.print_step_timings);
+        // in the case "false" is set explicitly, do not overwrite the command line args
+        if let Some(true) = build.emit_sysroot_manifest {
+            config.emit_sysroot_manifest = true;
+        }
+        set(&mut config.hermetic_env, build.hermetic_env);
+        if let Subcommand::Check { deterministic_env: true, .. } = config.cmd {
+            config.hermetic_env = true;
+        }
+        if let Some(ref link_artifacts) = build.link_artifacts {
+            config.link_artifacts = LinkArtifacts::parse(link_artifacts).unwrap_or_else(|err| {
+                eprintln!("error: {}", err);
+                process::exit(2);
+            });
+        }
 
         // See https://github.com/rust-lang/compiler-team/issues/326
         config.stage = match config.cmd {
@@ -645,6 +879,8 @@ impl Config {
             | Subcommand::Fix { .. }
             | Subcommand::Run { .. }
             | Subcommand::Setup { .. }
+            | Subcommand::SetupEditor { .. }
+            | Subcommand::Presubmit { .. }
             | Subcommand::Format { .. } => flags.stage.unwrap_or(0),
         };
 
@@ -670,6 +906,8 @@ impl Config {
                 | Subcommand::Fix { .. }
                 | Subcommand::Run { .. }
                 | Subcommand::Setup { .. }
+                | Subcommand::SetupEditor { .. }
+                | Subcommand::Presubmit { .. }
                 | Subcommand::Format { .. } => {}
             }
         }
@@ -812,10 +1050,11 @@ impl Config {
             set(&mut config.lld_enabled, rust.lld);
             set(&mut config.llvm_tools_enabled, rust.llvm_tools);
             config.rustc_parallel = rust.parallel_compiler.unwrap_or(false);
+            config.rust_check_threads = rust.check_threads;
             config.rustc_default_linker = rust.default_linker;
             config.musl_root = rust.musl_root.map(PathBuf::from);
             config.save_toolstates = rust.save_toolstates.map(PathBuf::from);
-            set(&mut config.deny_warnings, flags.deny_warnings.or(rust.deny_warnings));
+            set(&mut config.deny_warnings, deny_warnings.or(rust.deny_warnings));
             set(&mut config.backtrace_on_ice, rust.backtrace_on_ice);
             set(&mut config.rust_verify_llvm_ir, rust.verify_llvm_ir);
             config.rust_thin_lto_import_instr_limit = rust.thin_lto_import_instr_limit;
@@ -827,10 +1066,40 @@ impl Config {
                     backends.iter().map(|s| INTERNER.intern_str(s)).collect();
             }
 
+            if let Some(ref profiles) = rust.check_std_profiles {
+                config.rust_check_std_profiles = profiles
+                    .iter()
+                    .map(|s| {
+                        StdProfile::parse(s).unwrap_or_else(|err| {
+                            eprintln!("error: {} in `rust.check-std-profiles`", err);
+                            process::exit(2);
+                        })
+                    })
+                    .collect();
+            }
+
+            set(&mut config.rust_check_facade_configs, rust.check_facade_configs);
+
             config.rust_codegen_units = rust.codegen_units.map(threads_from_config);
             config.rust_codegen_units_std = rust.codegen_units_std.map(threads_from_config);
         }
 
+        if let Subcommand::Check { threads: Some(threads), .. } = config.cmd {
+            config.rust_check_threads = Some(threads);
+        }
+        if let Subcommand::Check { facade_configs: true, .. } = config.cmd {
+            config.rust_check_facade_configs = true;
+        }
+        if let Subcommand::Check { offline: true, .. } = config.cmd {
+            config.offline = true;
+        }
+        if let Subcommand::Check { locked: true, .. } = config.cmd {
+            config.locked_deps = true;
+        }
+        if let Subcommand::Check { timeout: Some(timeout), .. } = config.cmd {
+            config.step_timeout = Some(Duration::from_secs(timeout));
+        }
+
         if let Some(t) = toml.target {
             for (triple, cfg) in t {
                 let mut target = Target::from_triple(&triple);
@@ -857,6 +1126,17 @@ impl Config {
                 target.musl_libdir = cfg.musl_libdir.map(PathBuf::from);
                 target.wasi_root = cfg.wasi_root.map(PathBuf::from);
                 target.qemu_rootfs = cfg.qemu_rootfs.map(PathBuf::from);
+                target.skip_crates = cfg.skip_crates.unwrap_or_default();
+                target.rustflags = cfg.rustflags.unwrap_or_default();
+                // They end up in `RUSTFLAGS`, which is split on spaces.
+                if let Some(flag) = target.rustflags.iter().find(|flag| flag.contains(' ')) {
+                    eprintln!(
+                        "error: `target.{}.rustflags` has `{}`, which has a space; \
+                         give each argument separately",
+                        triple, flag
+                    );
+                    process::exit(2);
+                }
 
                 config.target_config.insert(TargetSelection::from_user(&triple), target);
             }
@@ -876,6 +1156,69 @@ impl Config {
             build_target.llvm_filecheck = Some(ci_llvm_bin.join(exe("FileCheck", config.build)));
         }
 
+        if let Some(t) = toml.check {
+            set(&mut config.check_all_targets, t.all_targets);
+            if let Some(paths) = t.default_paths {
+                config.check_default_paths = paths.into_iter().map(PathBuf::from).collect();
+            }
+            config.check_incremental = t.incremental;
+            config.check_sanitizer = t.sanitizer;
+            config.check_allowed_duplicates = t.allowed_duplicates.unwrap_or_default();
+            config.check_clippy_lints = t.clippy_lints.map(|path| config.src.join(path));
+        }
+        if let Some(t) = toml.hooks {
+            config.hooks_post_check = t.post_check.unwrap_or_default();
+            config.hooks_post_step = t.post_step.unwrap_or_default();
+            set(&mut config.hooks_strict, t.strict);
+        }
+        if flags.incremental {
+            config.check_incremental = Some(true);
+        }
+        if let Subcommand::Check { sanitizer: Some(ref sanitizer), .. } = config.cmd {
+            config.check_sanitizer = Some(sanitizer.clone());
+        }
+        if let Some(ref sanitizer) = config.check_sanitizer {
+            if !SANITIZERS.contains(&&sanitizer[..]) {
+                eprintln!(
+                    "error: unknown sanitizer `{}` to check std with, expected one of: {}",
+                    sanitizer,
+                    SANITIZERS.join(", ")
+                );
+                process::exit(2);
+            }
+        }
+        // Only an explicit `--all-targets` or `--no-all-targets` beats the
+        // config, as does asking for specific targets of the tools.
+        if let Subcommand::Check {
+            ref mut all_targets,
+            no_all_targets: false,
+            ref tool_target_args,
+            ..
+        } = config.cmd
+        {
+            *all_targets |= config.check_all_targets && tool_target_args.is_empty();
+        }
+
+        // The default lint set file may be missing from the tree, one named in
+        // the config may not.
+        if let Subcommand::Clippy { .. } = config.cmd {
+            let default = config.src.join("src/tools/clippy-lints.toml");
+            let path = config.check_clippy_lints.as_ref().unwrap_or(&default);
+            if config.check_clippy_lints.is_some() || path.exists() {
+                config.clippy_lints = crate::lints::load(path, &config.src).unwrap_or_else(|err| {
+                    eprintln!("error: {}", err);
+                    process::exit(2);
+                });
+            }
+        }
+
+        if let Some(t) = toml.fix {
+            set(&mut config.fix_auto_format, t.auto_format);
+        }
+        if let Subcommand::Fix { format: true, .. } = config.cmd {
+            config.fix_auto_format = true;
+        }
+
         if let Some(t) = toml.dist {
             config.dist_sign_folder = t.sign_folder.map(PathBuf::from);
             config.dist_gpg_password_file = t.gpg_password_file.map(PathBuf::from);
@@ -964,6 +1307,130 @@ impl Config {
     }
 }
 
+/// Applies the `[check.profiles]` entry `x.py check --profile` names to the
+/// options of `cmd` the command line left alone, and returns it for the
+/// options that live outside of `cmd`.
+fn check_profile(cmd: &mut Subcommand, check: Option<&Check>) -> CheckProfile {
+    let name = match cmd {
+        Subcommand::Check { profile: Some(name), .. } => name,
+        _ => return CheckProfile::default(),
+    };
+    let profiles = check.and_then(|check| check.profiles.as_ref());
+    let profile = match profiles.and_then(|profiles| profiles.get(&name[..])) {
+        Some(profile) => profile.clone(),
+        None => {
+            let mut available =
+                profiles.iter().flat_map(|profiles| profiles.keys()).collect::<Vec<_>>();
+            available.sort();
+            if available.is_empty() {
+                eprintln!(
+                    "error: unknown check profile `{}`, config.toml defines none under \
+                     `[check.profiles]`",
+                    name
+                );
+            } else {
+                eprintln!(
+                    "error: unknown check profile `{}`, expected one of: {}",
+                    name,
+                    available.into_iter().map(|name| &name[..]).collect::<Vec<_>>().join(", ")
+                );
+            }
+            process::exit(2);
+        }
+    };
+
+    if let Subcommand::Check {
+        paths,
+        features,
+        rustc_args,
+        all_targets,
+        no_all_targets,
+        tool_target_args,
+        ..
+    } = cmd
+    {
+        if paths.is_empty() {
+            if let Some(ref profile_paths) = profile.paths {
+                *paths = profile_paths.iter().map(PathBuf::from).collect();
+            }
+        }
+        if features.is_empty() {
+            if let Some(ref profile_features) = profile.features {
+                *features = profile_features.clone();
+            }
+        }
+        if rustc_args.is_empty() {
+            if let Some(ref profile_rustc_args) = profile.rustc_args {
+                *rustc_args = profile_rustc_args.clone();
+            }
+        }
+        // Like the command line flags, a profile beats `check.all-targets`.
+        if !*all_targets && !*no_all_targets && tool_target_args.is_empty() {
+            match profile.all_targets {
+                Some(true) => *all_targets = true,
+                Some(false) => *no_all_targets = true,
+                None => {}
+            }
+        }
+    }
+    profile
+}
+
+/// Replaces the names of target groups in `selections`, the built-in `tier1`
+/// or the ones `build.target-groups` defines, with the targets they stand for.
+fn expand_target_groups(
+    selections: Vec<TargetSelection>,
+    groups: &HashMap<String, Vec<String>>,
+) -> Vec<TargetSelection> {
+    let mut targets = Vec::new();
+    for selection in selections {
+        let expansion = if selection.file.is_some() {
+            vec![selection]
+        } else if let Some(group) = groups.get(&*selection.triple) {
+            group.iter().map(|triple| TargetSelection::from_user(triple)).collect()
+        } else if selection == "tier1" {
+            TIER1_TARGETS.iter().map(|triple| TargetSelection::from_user(triple)).collect()
+        } else if selection.contains("-") || selection == "all" {
+            vec![selection]
+        } else {
+            // Every target triple has at least two components, so a bare
+            // name was meant to be a group.
+            let mut available = groups.keys().map(|name| &name[..]).collect::<Vec<_>>();
+            available.push("tier1");
+            available.sort();
+            eprintln!(
+                "error: unknown target group `{}`, expected a target or one of: {}",
+                selection,
+                available.join(", ")
+            );
+            process::exit(2);
+        };
+        for target in expansion {
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+    }
+    targets
+}
+
+#[cfg(test)]
+fn get_toml(_: &Path) -> TomlConfig {
+    TomlConfig::default()
+}
+
+#[cfg(not(test))]
+fn get_toml(file: &Path) -> TomlConfig {
+    let contents = t!(fs::read_to_string(file), "`include` config not found");
+    match toml::from_str(&contents) {
+        Ok(table) => table,
+        Err(err) => {
+            println!("failed to parse TOML configuration '{}': {}", file.display(), err);
+            process::exit(2);
+        }
+    }
+}
+
 fn set<T>(field: &mut T, val: Option<T>) {
     if let Some(v) = val {
         *field = v;
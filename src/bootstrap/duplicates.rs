@@ -0,0 +1,160 @@
+//! Implementation of `x.py check --duplicate-crates`.
+//!
+//! Lists the crates the checked crates pull in more than one version of, with
+//! the chain of dependencies that leads to each version, as resolved in
+//! `Cargo.lock`. With `--deny-duplicates`, the check fails if any of them isn't
+//! listed in `check.allowed-duplicates`.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs;
+use std::process;
+
+use build_helper::t;
+use serde::Deserialize;
+
+use crate::{Build, Subcommand};
+
+#[derive(Deserialize)]
+struct Lockfile {
+    package: Vec<LockPackage>,
+}
+
+#[derive(Deserialize)]
+struct LockPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+    #[serde(default)]
+    dependencies: Vec<String>,
+}
+
+/// A crate present in more than one version, along with the shortest chain
+/// of dependencies from a checked crate to each version.
+#[derive(Debug, PartialEq)]
+struct Duplicate {
+    name: String,
+    versions: Vec<(String, Vec<String>)>,
+}
+
+pub fn report(build: &Build) {
+    let deny = match build.config.cmd {
+        Subcommand::Check { duplicate_crates, deny_duplicates, .. }
+            if duplicate_crates || deny_duplicates =>
+        {
+            deny_duplicates
+        }
+        _ => return,
+    };
+
+    let lockfile = t!(fs::read_to_string(build.src.join("Cargo.lock")));
+    let lockfile: Lockfile = t!(toml::from_str(&lockfile));
+    let roots = build.checked_crates.borrow();
+    let duplicates = find_duplicates(&lockfile, roots.iter().map(|krate| &krate[..]));
+
+    print!("{}", render(&duplicates));
+    if !deny {
+        return;
+    }
+    let denied = duplicates
+        .iter()
+        .filter(|duplicate| !build.config.check_allowed_duplicates.contains(&duplicate.name))
+        .map(|duplicate| &duplicate.name[..])
+        .collect::<Vec<_>>();
+    if !denied.is_empty() {
+        eprintln!(
+            "error: crates present in more than one version: {}\n\
+             help: unify their versions, or add them to `check.allowed-duplicates`",
+            denied.join(", ")
+        );
+        process::exit(1);
+    }
+}
+
+/// Walks the dependency graph of `lockfile` from the workspace crates named
+/// `roots`, and returns the crates reached in more than one version, sorted
+/// by name and then in the order `Cargo.lock` lists them.
+fn find_duplicates<'a>(
+    lockfile: &Lockfile,
+    roots: impl Iterator<Item = &'a str>,
+) -> Vec<Duplicate> {
+    let packages = &lockfile.package;
+    let roots = roots.collect::<BTreeSet<_>>();
+
+    // How each package was first reached, for the shortest chain to it.
+    let mut parent: Vec<Option<Option<usize>>> = vec![None; packages.len()];
+    let mut queue = VecDeque::new();
+    for (i, package) in packages.iter().enumerate() {
+        if package.source.is_none() && roots.contains(&package.name[..]) {
+            parent[i] = Some(None);
+            queue.push_back(i);
+        }
+    }
+    while let Some(i) = queue.pop_front() {
+        for dep in &packages[i].dependencies {
+            let j = match resolve(packages, dep) {
+                Some(j) => j,
+                None => continue,
+            };
+            if parent[j].is_none() {
+                parent[j] = Some(Some(i));
+                queue.push_back(j);
+            }
+        }
+    }
+
+    let mut by_name: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (i, package) in packages.iter().enumerate() {
+        if parent[i].is_some() {
+            by_name.entry(&package.name).or_default().push(i);
+        }
+    }
+    by_name
+        .into_iter()
+        .filter(|(_, versions)| versions.len() > 1)
+        .map(|(name, versions)| Duplicate {
+            name: name.to_string(),
+            versions: versions
+                .into_iter()
+                .map(|i| {
+                    let mut chain = vec![packages[i].name.clone()];
+                    let mut next = parent[i].unwrap();
+                    while let Some(j) = next {
+                        chain.push(packages[j].name.clone());
+                        next = parent[j].unwrap();
+                    }
+                    chain.reverse();
+                    (packages[i].version.clone(), chain)
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Finds the package a `dependencies` entry of `Cargo.lock` refers to, which
+/// is `name`, or `name version` when there's more than one version of it.
+fn resolve(packages: &[LockPackage], dep: &str) -> Option<usize> {
+    let mut parts = dep.split_whitespace();
+    let name = parts.next()?;
+    let version = parts.next();
+    packages.iter().position(|package| {
+        package.name == name && version.map_or(true, |version| package.version == version)
+    })
+}
+
+fn render(duplicates: &[Duplicate]) -> String {
+    if duplicates.is_empty() {
+        return "no duplicate crates in the check graph\n".to_string();
+    }
+    let mut out = String::from("duplicate crates in the check graph:\n");
+    for duplicate in duplicates {
+        out.push_str(&duplicate.name);
+        out.push('\n');
+        for (version, chain) in &duplicate.versions {
+            out.push_str(&format!("    {}: {}\n", version, chain.join(" -> ")));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,71 @@
+use super::*;
+
+const LOCKFILE: &str = r#"
+[[package]]
+name = "proc-macro2"
+version = "1.0.24"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "rustc-main"
+version = "0.0.0"
+dependencies = [
+ "rustc_macros",
+]
+
+[[package]]
+name = "rustc_macros"
+version = "0.1.0"
+dependencies = [
+ "syn 1.0.60",
+ "synstructure",
+]
+
+[[package]]
+name = "syn"
+version = "0.15.44"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "syn"
+version = "1.0.60"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+dependencies = [
+ "proc-macro2",
+]
+
+[[package]]
+name = "synstructure"
+version = "0.12.4"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+dependencies = [
+ "syn 0.15.44",
+]
+
+[[package]]
+name = "tool"
+version = "0.1.0"
+dependencies = [
+ "syn 0.15.44",
+]
+"#;
+
+#[test]
+fn duplicates_with_their_chains() {
+    let lockfile: Lockfile = toml::from_str(LOCKFILE).unwrap();
+    let duplicates = find_duplicates(&lockfile, vec!["rustc-main"].into_iter());
+    assert_eq!(
+        render(&duplicates),
+        "duplicate crates in the check graph:\n\
+         syn\n    \
+         0.15.44: rustc-main -> rustc_macros -> synstructure -> syn\n    \
+         1.0.60: rustc-main -> rustc_macros -> syn\n"
+    );
+}
+
+#[test]
+fn only_the_checked_crates_count() {
+    let lockfile: Lockfile = toml::from_str(LOCKFILE).unwrap();
+    assert_eq!(find_duplicates(&lockfile, vec!["tool"].into_iter()), vec![]);
+    assert_eq!(find_duplicates(&lockfile, vec!["proc-macro2"].into_iter()), vec![]);
+}
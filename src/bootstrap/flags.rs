@@ -4,15 +4,16 @@
 //! has various flags to configure how it's run.
 
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::str::FromStr;
 
 use build_helper::t;
 use getopts::Options;
 
 use crate::builder::Builder;
-use crate::config::{Config, TargetSelection};
-use crate::setup::Profile;
+use crate::config::{Config, StdProfile, TargetSelection};
+use crate::setup::{Editor, Profile};
 use crate::{Build, DocTests};
 
 /// Deserialized version of all flags for this compile.
@@ -33,6 +34,7 @@ pub struct Flags {
     pub include_default_paths: bool,
     pub rustc_error_format: Option<String>,
     pub json_output: bool,
+    pub emit_sysroot_manifest: bool,
     pub dry_run: bool,
 
     // This overrides the deny-warnings configuration option,
@@ -52,13 +54,135 @@ pub enum Subcommand {
         // Whether to run checking over all targets (e.g., unit / integration
         // tests).
         all_targets: bool,
+        // Whether `--no-all-targets` overrides `check.all-targets`.
+        no_all_targets: bool,
+        // Whether to reuse the already checked standard library when its
+        // stamp is still valid, rather than re-checking it before rustc.
+        skip_std_check: bool,
+        // File to write the sorted names of all checked crates to.
+        checked_crates_output: Option<PathBuf>,
+        // Directory to copy the sysroot with the checked std to.
+        export_sysroot: Option<PathBuf>,
+        // Sysroot exported with `--export-sysroot` to check the tools against,
+        // instead of the one checking std and rustc fills.
+        with_sysroot: Option<PathBuf>,
+        // Whether to only print the paths of the std and rustc stamps.
+        print_stamp_path: bool,
+        // Whether to only print the resolved configuration of the check.
+        print_config: bool,
+        // Whether to leave out bootstrap's progress messages, keeping Cargo's
+        // output.
+        quiet: bool,
+        // Whether to list the crates pulled into the check graph in more than
+        // one version.
+        duplicate_crates: bool,
+        // Whether such crates fail the check unless `check.allowed-duplicates`
+        // lists them.
+        deny_duplicates: bool,
+        // Whether to write the dependency graph of the checked crates to
+        // build/check-depgraph.{dot,json}.
+        emit_dep_graph: bool,
+        // Number of threads a parallel compiler should type-check with.
+        threads: Option<u32>,
+        // Seconds Cargo may go without output, overriding `build.step-timeout`.
+        timeout: Option<u64>,
+        // Number of jobs Cargo runs in parallel, overriding `--jobs`.
+        check_jobs: Option<u32>,
+        rustc_args: Vec<String>,
+        // Revision whose merge-base the check is limited to the changes since.
+        only_changed: Option<String>,
+        // Whether to also check the facade crates in their extra configurations.
+        facade_configs: bool,
+        // Cargo target selectors (`--lib`, `--bins`, `--bin NAME`) for the
+        // tools, passed on as-is.
+        tool_target_args: Vec<String>,
+        // Crates to check instead of all crates of the selected steps.
+        packages: Vec<String>,
+        // File to write an HTML report of all diagnostics to.
+        report: Option<PathBuf>,
+        // Whether to open the report in a browser once it's written.
+        open_report: bool,
+        // Whether cargo may not access the network.
+        offline: bool,
+        // Whether cargo must fail instead of updating `Cargo.lock`.
+        locked: bool,
+        // Whether to also check the documentation examples of std.
+        doctests: bool,
+        // Whether to also build the docs of std and the compiler crates,
+        // private items included.
+        document_private_items: bool,
+        // Whether std may be skipped without running Cargo when none of the
+        // inputs recorded by its last check changed.
+        fast_fresh: bool,
+        // Extra features of the compiler crates to check with.
+        features: Vec<String>,
+        // Whether to check the compiler without its default features.
+        no_default_features: bool,
+        // The sanitizer to check std with, overriding `check.sanitizer`.
+        sanitizer: Option<String>,
+        // The panic runtime to check std against, instead of `panic_unwind`.
+        panic_strategy: Option<StdProfile>,
+        // Directory to write the compiler's internal statistics for each
+        // crate to.
+        compiler_stats: Option<PathBuf>,
+        // Whether cargo carries on with the other crates when one fails to
+        // compile.
+        keep_going: bool,
+        // Whether the `--all-targets` pass of std stops at the first test,
+        // bench or example target that fails to compile.
+        fail_fast: bool,
+        // Whether the steps asked for are checked again, whatever their stamps
+        // and Cargo's fingerprints say.
+        force: bool,
+        // Whether that also goes for the steps they depend on.
+        force_deps: bool,
+        // Whether std is the only default step checked.
+        std_only: bool,
+        // Whether the compiler is the only default step checked, along with
+        // the std it needs.
+        rustc_only: bool,
+        // The `[check.profiles]` entry whose settings apply where the command
+        // line doesn't say otherwise.
+        profile: Option<String>,
+        // A crate outside of the check steps to check instead of them, by the
+        // path to its manifest or its directory.
+        manifest_path: Option<PathBuf>,
+        // Whether Cargo only gets the environment bootstrap sets up, as with
+        // `build.hermetic-env`.
+        deterministic_env: bool,
+        // Whether crates going over their budgets in check-budgets.toml fail
+        // the check.
+        deny_budget_overruns: bool,
+        // Whether each check step says why it runs Cargo again rather than
+        // being up to date.
+        explain_rebuild: bool,
         paths: Vec<PathBuf>,
     },
     Clippy {
+        rustc_args: Vec<String>,
+        // Revision whose merge-base the lints are limited to the changes since.
+        only_changed: Option<String>,
+        // Whether to apply the suggestions of the lints that fire.
+        fix: bool,
+        // The `-A`/`-W`/`-D` options after `--`, selecting the lints `--fix`
+        // applies.
+        lint_args: Vec<String>,
+        // Whether cargo carries on with the other crates when one fails to
+        // compile.
+        keep_going: bool,
+        // File to write the findings to as SARIF.
+        sarif_output: Option<PathBuf>,
+        // Whether the findings only go to that file, instead of also being
+        // printed.
+        sarif_only: bool,
+        // Whether to only print the lint options clippy would get.
+        print_lint_flags: bool,
         paths: Vec<PathBuf>,
     },
     Fix {
         paths: Vec<PathBuf>,
+        // Whether to run rustfmt over the files `cargo fix` edited.
+        format: bool,
     },
     Format {
         check: bool,
@@ -85,6 +209,8 @@ pub enum Subcommand {
     },
     Clean {
         all: bool,
+        // Only remove stale stamps rather than all build artifacts.
+        stamps: bool,
     },
     Dist {
         paths: Vec<PathBuf>,
@@ -98,6 +224,14 @@ pub enum Subcommand {
     Setup {
         profile: Profile,
     },
+    SetupEditor {
+        // Where the settings for rust-analyzer go.
+        editor: Editor,
+    },
+    Presubmit {
+        // Whether to stop at the first command that fails.
+        fail_fast: bool,
+    },
 }
 
 impl Default for Subcommand {
@@ -126,6 +260,7 @@ Subcommands:
     install     Install distribution artifacts
     run, r      Run tools contained in this repository
     setup       Create a config.toml (making it easier to use `x.py` itself)
+    presubmit   Run check, clippy and fmt --check, as the fast tier of CI would
 
 To learn more about a subcommand, run `./x.py <subcommand> -h`",
         );
@@ -137,7 +272,13 @@ To learn more about a subcommand, run `./x.py <subcommand> -h`",
         opts.optopt("", "config", "TOML configuration file for build", "FILE");
         opts.optopt("", "build", "build target of the stage0 compiler", "BUILD");
         opts.optmulti("", "host", "host targets to build", "HOST");
-        opts.optmulti("", "target", "target targets to build", "TARGET");
+        opts.optmulti(
+            "",
+            "target",
+            "target targets to build, `all` for every target config.toml lists, or a group of \
+             them such as `tier1`",
+            "TARGET",
+        );
         opts.optmulti("", "exclude", "build paths to exclude", "PATH");
         opts.optflag(
             "",
@@ -181,8 +322,13 @@ To learn more about a subcommand, run `./x.py <subcommand> -h`",
             "if value is deny, will deny warnings, otherwise use default",
             "VALUE",
         );
-        opts.optopt("", "error-format", "rustc error format", "FORMAT");
+        opts.optopt("", "error-format", "rustc error format (human, short or json)", "FORMAT");
         opts.optflag("", "json-output", "use message-format=json");
+        opts.optflag(
+            "",
+            "emit-sysroot-manifest",
+            "write a JSON manifest of the artifacts copied into the sysroot next to each stamp",
+        );
         opts.optopt(
             "",
             "llvm-skip-rebuild",
@@ -215,6 +361,7 @@ To learn more about a subcommand, run `./x.py <subcommand> -h`",
                 || (s == "run")
                 || (s == "r")
                 || (s == "setup")
+                || (s == "presubmit")
         });
         let subcommand = match subcommand {
             Some(s) => s,
@@ -263,6 +410,301 @@ To learn more about a subcommand, run `./x.py <subcommand> -h`",
             }
             "check" | "c" => {
                 opts.optflag("", "all-targets", "Check all targets");
+                opts.optflag(
+                    "",
+                    "no-all-targets",
+                    "only check the default targets, even if `check.all-targets` is set",
+                );
+                opts.optflag(
+                    "",
+                    "no-std-check",
+                    "don't re-check the standard library before the compiler if it is up to date",
+                );
+                opts.optopt(
+                    "",
+                    "checked-crates-output",
+                    "write the names of all checked crates to FILE, one per line",
+                    "FILE",
+                );
+                opts.optopt(
+                    "",
+                    "export-sysroot",
+                    "copy the sysroot with the checked std for each target to DIR",
+                    "DIR",
+                );
+                opts.optopt(
+                    "",
+                    "with-sysroot",
+                    "check the tools against the sysroot exported to DIR, e.g. a known-good one, \
+                     instead of checking std and rustc for them",
+                    "DIR",
+                );
+                opts.optflag(
+                    "",
+                    "print-stamp-path",
+                    "print the paths of the std and rustc stamps for each target and exit",
+                );
+                opts.optflag(
+                    "",
+                    "print-config",
+                    "print the resolved configuration and the steps that would run as JSON, \
+                     without checking anything, e.g. to diff two machines",
+                );
+                opts.optflag(
+                    "",
+                    "quiet",
+                    "don't print which steps are checking what, only cargo's output and errors",
+                );
+                opts.optflag(
+                    "",
+                    "duplicate-crates",
+                    "list the crates the checked crates depend on in more than one version",
+                );
+                opts.optflag(
+                    "",
+                    "deny-duplicates",
+                    "like `--duplicate-crates`, but fail unless `check.allowed-duplicates` lists them",
+                );
+                opts.optflag(
+                    "",
+                    "emit-dep-graph",
+                    "write the graph of the checked crates to build/check-depgraph.{dot,json}",
+                );
+                opts.optopt(
+                    "",
+                    "threads",
+                    "type-check with N threads if the compiler supports the parallel compiler",
+                    "N",
+                );
+                opts.optopt(
+                    "",
+                    "timeout",
+                    "kill Cargo if it prints nothing for SECS seconds, overriding `build.step-timeout`",
+                    "SECS",
+                );
+                opts.optopt(
+                    "",
+                    "check-jobs",
+                    "run at most N jobs in parallel while checking, instead of `--jobs`",
+                    "N",
+                );
+                opts.optmulti(
+                    "",
+                    "rustc-args",
+                    "extra options to pass the compiler when checking std and rustc",
+                    "ARGS",
+                );
+                opts.optflagopt(
+                    "",
+                    "only-changed",
+                    "only check std and rustc crates affected by changes since the merge-base \
+                     with BASE (default: HEAD)",
+                    "BASE",
+                );
+                opts.optopt(
+                    "",
+                    "since",
+                    "same as --only-changed REF, e.g. `--since origin/master`",
+                    "REF",
+                );
+                opts.optmulti(
+                    "p",
+                    "package",
+                    "only check the crate NAME of std, rustc or the tools that know it; std and \
+                     rustc are still checked in full when none of their crates are named",
+                    "NAME",
+                );
+                opts.optopt(
+                    "",
+                    "report",
+                    "write an HTML report of all diagnostics to FILE",
+                    "FILE",
+                );
+                opts.optflag(
+                    "",
+                    "open-report",
+                    "open the HTML report, build/check-report.html without --report, in a \
+                     browser when done",
+                );
+                opts.optflag("", "lib", "only check the library target of the tools");
+                opts.optflag("", "bins", "only check the binary targets of the tools");
+                opts.optmulti("", "bin", "only check the tool binary NAME", "NAME");
+                opts.optflag(
+                    "",
+                    "facade-configs",
+                    "also check alloc and core in the configurations listed for \
+                     `rust.check-facade-configs`",
+                );
+                opts.optflag(
+                    "",
+                    "offline",
+                    "pass `--offline` to cargo, failing instead of accessing the network",
+                );
+                opts.optflag(
+                    "",
+                    "locked",
+                    "pass `--locked` to cargo, failing instead of updating Cargo.lock",
+                );
+                opts.optflag(
+                    "",
+                    "doctests",
+                    "also check that the documentation examples of std compile, without \
+                     running them",
+                );
+                opts.optflag(
+                    "",
+                    "document-private-items",
+                    "also check that the docs of std and the compiler crates build, private \
+                     items included, e.g. for broken intra-doc links",
+                );
+                opts.optflag(
+                    "",
+                    "no-fast-fresh",
+                    "always run cargo for std, even when none of the inputs recorded by the \
+                     last check changed",
+                );
+                opts.optmulti(
+                    "",
+                    "features",
+                    "extra features to check the compiler crates with, as cargo takes them",
+                    "FEATURES",
+                );
+                opts.optflag(
+                    "",
+                    "no-default-features",
+                    "check the compiler without its default features",
+                );
+                opts.optopt(
+                    "",
+                    "sanitizer",
+                    "check std with a sanitizer enabled, one of address, hwaddress, leak, \
+                     memory or thread",
+                    "NAME",
+                );
+                opts.optopt(
+                    "",
+                    "panic-strategy",
+                    "check std against the panic runtime for STRATEGY, abort or unwind",
+                    "STRATEGY",
+                );
+                opts.optopt(
+                    "",
+                    "compiler-stats",
+                    "write the query, timing and HIR statistics of the compiler for each crate \
+                     of std and rustc to DIR",
+                    "DIR",
+                );
+                opts.optflag(
+                    "",
+                    "keep-going",
+                    "check the crates that don't depend on one that failed to compile; the exit \
+                     status is still 1",
+                );
+                opts.optflag(
+                    "",
+                    "no-fail-fast",
+                    "with --all-targets, check all test targets of std even if some fail",
+                );
+                opts.optflag(
+                    "",
+                    "force",
+                    "check the steps asked for again, whatever their stamps and cargo say, e.g. \
+                     after changing RUSTFLAGS outside of config.toml",
+                );
+                opts.optflag(
+                    "",
+                    "force-deps",
+                    "like --force, and also check the steps they depend on again",
+                );
+                opts.optflag(
+                    "",
+                    "std-only",
+                    "only check std, not the compiler or the tools; can't be combined with paths",
+                );
+                opts.optflag(
+                    "",
+                    "rustc-only",
+                    "only check the compiler, and the std it needs, not the tools; can't be \
+                     combined with paths",
+                );
+                opts.optopt(
+                    "",
+                    "profile",
+                    "use the settings of `check.profiles.NAME` in config.toml, where the command \
+                     line doesn't give them",
+                    "NAME",
+                );
+                opts.optopt(
+                    "",
+                    "manifest-path",
+                    "check the workspace member whose Cargo.toml is at PATH, like a tool, e.g. an \
+                     experiment under src/tools",
+                    "PATH",
+                );
+                opts.optflag(
+                    "",
+                    "deterministic-env",
+                    "don't pass the environment on to cargo, beyond what it can't run without, as \
+                     `build.hermetic-env` does; -v shows what's left out",
+                );
+                opts.optflag(
+                    "",
+                    "deny-budget-overruns",
+                    "fail if a crate goes over its budget in src/bootstrap/check-budgets.toml \
+                     instead of warning; the last 50 runs are timed in build/check-timings/",
+                );
+                opts.optflag(
+                    "",
+                    "explain-rebuild",
+                    "print why each step is checked again, e.g. which file changed; cargo may \
+                     still rebuild crates for reasons of its own",
+                );
+            }
+            "clippy" => {
+                opts.optmulti(
+                    "",
+                    "rustc-args",
+                    "extra options to pass the compiler when linting std and rustc",
+                    "ARGS",
+                );
+                opts.optflagopt(
+                    "",
+                    "only-changed",
+                    "only lint std and rustc crates affected by changes since the merge-base \
+                     with BASE (default: HEAD)",
+                    "BASE",
+                );
+                opts.optopt(
+                    "",
+                    "since",
+                    "same as --only-changed REF, e.g. `--since origin/master`",
+                    "REF",
+                );
+                opts.optflag("", "fix", "apply the suggestions of the lints that fire");
+                opts.optflag(
+                    "",
+                    "keep-going",
+                    "lint the crates that don't depend on one that failed to compile",
+                );
+                opts.optopt("", "sarif-output", "write the findings to FILE as SARIF", "FILE");
+                opts.optflag(
+                    "",
+                    "sarif-only",
+                    "don't print the findings, only write them to the --sarif-output file",
+                );
+                opts.optflag(
+                    "",
+                    "print-lint-flags",
+                    "print the lint options clippy gets, including the lint set file's",
+                );
+            }
+            "fix" => {
+                opts.optflag(
+                    "",
+                    "format",
+                    "run rustfmt over the files `cargo fix` edited; can also be enabled with \
+                     `fix.auto-format`",
+                );
             }
             "bench" => {
                 opts.optmulti("", "test-args", "extra arguments", "ARGS");
@@ -272,13 +714,33 @@ To learn more about a subcommand, run `./x.py <subcommand> -h`",
             }
             "clean" => {
                 opts.optflag("", "all", "clean all build artifacts");
+                opts.optflag(
+                    "",
+                    "stamps",
+                    "only remove stamps no longer written by any step, and their artifacts",
+                );
             }
             "fmt" => {
                 opts.optflag("", "check", "check formatting instead of applying.");
             }
+            "presubmit" => {
+                opts.optflag("", "fail-fast", "stop at the first command that fails");
+            }
+            "setup" => {
+                opts.optopt(
+                    "",
+                    "editor",
+                    "with check-editor, where the settings go: vscode (default) or stdout",
+                    "EDITOR",
+                );
+            }
             _ => {}
         };
 
+        // `x.py check` documents 2 as the exit status of a bad invocation, which
+        // scripts tell apart from 1 for errors in the checked crates.
+        let misuse = if ["check", "c", "clippy"].contains(&subcommand.as_str()) { 2 } else { 1 };
+
         // fn usage()
         let usage = |exit_code: i32, opts: &Options, verbose: bool, subcommand_help: &str| -> ! {
             let mut extra_help = String::new();
@@ -290,7 +752,7 @@ To learn more about a subcommand, run `./x.py <subcommand> -h`",
 
                 let maybe_rules_help = Builder::get_help(&build, subcommand.as_str());
                 extra_help.push_str(maybe_rules_help.unwrap_or_default().as_str());
-            } else if !(subcommand.as_str() == "clean" || subcommand.as_str() == "fmt") {
+            } else if !["clean", "fmt", "presubmit"].contains(&subcommand.as_str()) {
                 extra_help.push_str(
                     format!("Run `./x.py {} -h -v` to see a list of available paths.", subcommand)
                         .as_str(),
@@ -304,11 +766,17 @@ To learn more about a subcommand, run `./x.py <subcommand> -h`",
             process::exit(exit_code);
         };
 
+        // Everything after `--` for clippy are lint options, not paths.
+        let (args, lint_args) = match args.iter().position(|arg| arg == "--") {
+            Some(i) if subcommand == "clippy" => (&args[..i], args[i + 1..].to_vec()),
+            _ => (args, Vec::new()),
+        };
+
         // Done specifying what options are possible, so do the getopts parsing
         let matches = opts.parse(&args[..]).unwrap_or_else(|e| {
             // Invalid argument/option format
-            println!("\n{}\n", e);
-            usage(1, &opts, false, &subcommand_help);
+            eprintln!("\n{}\n", e);
+            usage(misuse, &opts, false, &subcommand_help);
         });
 
         // Extra sanity check to make sure we didn't hit this crazy corner case:
@@ -333,7 +801,7 @@ To learn more about a subcommand, run `./x.py <subcommand> -h`",
                 "Sorry, I couldn't figure out which subcommand you were trying to specify.\n\
                  You may need to move some options to after the subcommand.\n"
             );
-            process::exit(1);
+            process::exit(misuse);
         }
         // Extra help text for some commands
         match subcommand.as_str() {
@@ -373,13 +841,30 @@ Arguments:
 
         ./x.py check library/core
         ./x.py check library/core library/proc_macro
-
-    If no arguments are passed then the complete artifacts are compiled: std, test, and rustc. Note
-    also that since we use `cargo check`, by default this will automatically enable incremental
-    compilation, so there's no need to pass it separately, though it won't hurt. We also completely
-    ignore the stage passed, as there's no way to compile in non-stage 0 without actually building
-    the compiler.",
+        ./x.py check src/tools/rustdoc --lib
+        ./x.py check src/tools/miri -p cargo-miri
+        ./x.py check --std-only --target all
+        ./x.py check --force-deps src/tools/clippy
+        ./x.py check --profile full --target x86_64-unknown-linux-gnu
+        ./x.py check --manifest-path src/tools/experimental/Cargo.toml
+
+    If no arguments are passed then the complete artifacts are compiled: std, test, and rustc.
+    The stage is ignored, as std and the compiler are always checked with the stage0 compiler,
+    and so with `cfg(bootstrap)` set unless `build.local-rebuild` is.
+
+Exit status:
+    0   everything type-checked
+    1   cargo or rustc reported errors in the checked crates
+    2   bootstrap couldn't set up the check, e.g. because of a bad argument, a missing stage0
+        component, a missing submodule, a bad config.toml or a path no step handles, or cargo ran
+        into `--timeout`, `build.step-timeout` or `build.step-budget` and was killed
+    3   internal bootstrap error, e.g. a panic or cargo output bootstrap doesn't understand
+    4   a command of the `[hooks]` section of config.toml failed while `hooks.strict` is set",
                 );
+                subcommand_help.push_str("\n\nTools:");
+                for path in crate::check::TOOL_PATHS {
+                    subcommand_help.push_str(&format!("\n    ./x.py check {}", path));
+                }
             }
             "clippy" => {
                 subcommand_help.push_str(
@@ -389,7 +874,28 @@ Arguments:
     and/or artifacts to run clippy against. For example:
 
         ./x.py clippy library/core
-        ./x.py clippy library/core library/proc_macro",
+        ./x.py clippy library/core library/proc_macro
+
+    Pass `--error-format short` to get one line per finding instead of the fully rendered
+    diagnostics.
+
+    By default, the clippy of the stage0 toolchain is used, which may report different lints
+    than the in-tree one. Pass `--stage 1` to build the in-tree clippy and lint with it instead.
+
+    With `--fix`, the suggestions of the lints that fire are applied. Lint options after `--`
+    select which ones, for example:
+
+        ./x.py clippy --fix library/core -- -A clippy::all -W clippy::needless_return
+
+    Pass `--keep-going` to still lint the crates that don't depend on one that fails to
+    compile, which is useful in the middle of a refactor. It works with `x.py check` too.
+
+    Pass `--sarif-output FILE` to also write the findings to FILE as SARIF, for code scanning
+    tools. Add `--sarif-only` to not print them.
+
+    The lints allowed, warned about and denied across the tree are listed in
+    `src/tools/clippy-lints.toml`, or the file `check.clippy-lints` in config.toml names. Pass
+    `--print-lint-flags` to print the lint options clippy gets from it.",
                 );
             }
             "fix" => {
@@ -400,7 +906,10 @@ Arguments:
     and/or artifacts to run `cargo fix` against. For example:
 
         ./x.py fix library/core
-        ./x.py fix library/core library/proc_macro",
+        ./x.py fix library/core library/proc_macro
+
+    Pass `--format` to run rustfmt over the files that were edited, with the same
+    configuration as `./x.py fmt`.",
                 );
             }
             "fmt" => {
@@ -414,6 +923,17 @@ Arguments:
         ./x.py fmt --check",
                 );
             }
+            "presubmit" => {
+                subcommand_help.push_str(
+                    "\n
+x.py presubmit runs `./x.py check`, `./x.py clippy` and `./x.py fmt --check` with the options it's
+given, and prints which of them passed. It fails if any of them does, after running all of them
+unless `--fail-fast` is passed. For example:
+
+        ./x.py presubmit
+        ./x.py presubmit --fail-fast -j 8",
+                );
+            }
             "test" | "t" => {
                 subcommand_help.push_str(
                     "\n
@@ -485,7 +1005,15 @@ Arguments:
     The profile is optional and you will be prompted interactively if it is not given.
     The following profiles are available:
 
-{}",
+{}
+
+    Pass `check-editor` instead of a profile to make rust-analyzer check the tree with
+    `x.py check --json-output --quiet` on save, instead of `cargo check`, which doesn't work
+    here. The setting is added to `.vscode/settings.json`, leaving the others there alone, or
+    with `--editor stdout` printed for other editors' rust-analyzer settings:
+
+        ./x.py setup check-editor
+        ./x.py setup check-editor --editor stdout",
                     Profile::all_for_help("        ").trim_end()
                 ));
             }
@@ -504,11 +1032,85 @@ Arguments:
 
         let cmd = match subcommand.as_str() {
             "build" | "b" => Subcommand::Build { paths },
-            "check" | "c" => {
-                Subcommand::Check { paths, all_targets: matches.opt_present("all-targets") }
+            "check" | "c" => Subcommand::Check {
+                paths,
+                all_targets: matches.opt_present("all-targets"),
+                no_all_targets: matches.opt_present("no-all-targets"),
+                skip_std_check: matches.opt_present("no-std-check"),
+                checked_crates_output: matches.opt_str("checked-crates-output").map(PathBuf::from),
+                export_sysroot: matches.opt_str("export-sysroot").map(PathBuf::from),
+                with_sysroot: matches.opt_str("with-sysroot").map(PathBuf::from),
+                print_stamp_path: matches.opt_present("print-stamp-path"),
+                print_config: matches.opt_present("print-config"),
+                quiet: matches.opt_present("quiet"),
+                duplicate_crates: matches.opt_present("duplicate-crates"),
+                deny_duplicates: matches.opt_present("deny-duplicates"),
+                emit_dep_graph: matches.opt_present("emit-dep-graph"),
+                threads: parse_opt(&matches, "threads", "a number", misuse),
+                timeout: parse_opt(&matches, "timeout", "a number of seconds", misuse),
+                check_jobs: parse_opt(&matches, "check-jobs", "a number", misuse),
+                rustc_args: matches.opt_strs("rustc-args"),
+                only_changed: only_changed(&matches, misuse),
+                facade_configs: matches.opt_present("facade-configs"),
+                tool_target_args: tool_target_args(&matches),
+                packages: matches.opt_strs("package"),
+                report: matches.opt_str("report").map(PathBuf::from),
+                open_report: matches.opt_present("open-report"),
+                offline: matches.opt_present("offline"),
+                locked: matches.opt_present("locked"),
+                doctests: matches.opt_present("doctests"),
+                document_private_items: matches.opt_present("document-private-items"),
+                fast_fresh: !matches.opt_present("no-fast-fresh"),
+                features: matches.opt_strs("features"),
+                no_default_features: matches.opt_present("no-default-features"),
+                sanitizer: matches.opt_str("sanitizer"),
+                panic_strategy: matches
+                    .opt_str("panic-strategy")
+                    .map(|s| panic_strategy(&s, misuse)),
+                compiler_stats: matches.opt_str("compiler-stats").map(PathBuf::from),
+                keep_going: matches.opt_present("keep-going"),
+                fail_fast: !matches.opt_present("no-fail-fast"),
+                force: matches.opt_present("force") || matches.opt_present("force-deps"),
+                force_deps: matches.opt_present("force-deps"),
+                std_only: matches.opt_present("std-only"),
+                rustc_only: matches.opt_present("rustc-only"),
+                profile: matches.opt_str("profile"),
+                manifest_path: matches.opt_str("manifest-path").map(PathBuf::from),
+                deterministic_env: matches.opt_present("deterministic-env"),
+                deny_budget_overruns: matches.opt_present("deny-budget-overruns"),
+                explain_rebuild: matches.opt_present("explain-rebuild"),
+            },
+            "clippy" => {
+                let fix = matches.opt_present("fix");
+                if !lint_args.is_empty() && !fix {
+                    eprintln!("\nlint options after `--` are only supported with --fix\n");
+                    usage(misuse, &opts, verbose, &subcommand_help);
+                }
+                if fix && matches.opt_str("stage").map_or(false, |stage| stage != "0") {
+                    eprintln!("\n--fix is only supported with the stage0 clippy\n");
+                    usage(misuse, &opts, verbose, &subcommand_help);
+                }
+                if let Some(arg) = invalid_lint_arg(&lint_args) {
+                    eprintln!("\nonly -A, -W, -D and -F can follow `--`, not `{}`\n", arg);
+                    usage(misuse, &opts, verbose, &subcommand_help);
+                }
+                if matches.opt_present("sarif-only") && !matches.opt_present("sarif-output") {
+                    eprintln!("\n--sarif-only needs --sarif-output\n");
+                    usage(misuse, &opts, verbose, &subcommand_help);
+                }
+                Subcommand::Clippy {
+                    paths,
+                    rustc_args: matches.opt_strs("rustc-args"),
+                    only_changed: only_changed(&matches, misuse),
+                    fix,
+                    lint_args,
+                    keep_going: matches.opt_present("keep-going"),
+                    sarif_output: matches.opt_str("sarif-output").map(PathBuf::from),
+                    sarif_only: matches.opt_present("sarif-only"),
+                    print_lint_flags: matches.opt_present("print-lint-flags"),
+                }
             }
-            "clippy" => Subcommand::Clippy { paths },
-            "fix" => Subcommand::Fix { paths },
+            "fix" => Subcommand::Fix { paths, format: matches.opt_present("format") },
             "test" | "t" => Subcommand::Test {
                 paths,
                 bless: matches.opt_present("bless"),
@@ -534,7 +1136,10 @@ Arguments:
                     usage(1, &opts, verbose, &subcommand_help);
                 }
 
-                Subcommand::Clean { all: matches.opt_present("all") }
+                Subcommand::Clean {
+                    all: matches.opt_present("all"),
+                    stamps: matches.opt_present("stamps"),
+                }
             }
             "fmt" => Subcommand::Format { check: matches.opt_present("check") },
             "dist" => Subcommand::Dist { paths },
@@ -546,6 +1151,21 @@ Arguments:
                 }
                 Subcommand::Run { paths }
             }
+            "presubmit" => {
+                if !paths.is_empty() {
+                    println!("\npresubmit does not take a path argument\n");
+                    usage(1, &opts, verbose, &subcommand_help);
+                }
+                Subcommand::Presubmit { fail_fast: matches.opt_present("fail-fast") }
+            }
+            "setup" if paths.len() == 1 && paths[0] == Path::new("check-editor") => {
+                let editor = matches.opt_str("editor").map_or(Ok(Editor::VsCode), |s| s.parse());
+                let editor = editor.unwrap_or_else(|err| {
+                    println!("\n{}\n", err);
+                    usage(1, &opts, verbose, &subcommand_help);
+                });
+                Subcommand::SetupEditor { editor }
+            }
             "setup" => {
                 let profile = if paths.len() > 1 {
                     println!("\nat most one profile can be passed to setup\n");
@@ -567,39 +1187,79 @@ Arguments:
                 Subcommand::Setup { profile }
             }
             _ => {
-                usage(1, &opts, verbose, &subcommand_help);
+                usage(misuse, &opts, verbose, &subcommand_help);
             }
         };
 
+        if let Some(format) = matches.opt_str("error-format") {
+            if !["human", "short", "json"].contains(&&*format) {
+                eprintln!("--error-format must be one of human, short or json, not `{}`", format);
+                process::exit(misuse);
+            }
+        }
+
         if let Subcommand::Check { .. } = &cmd {
             if matches.opt_str("stage").is_some() {
-                println!("--stage not supported for x.py check, always treated as stage 0");
-                process::exit(1);
+                eprintln!("--stage not supported for x.py check, always treated as stage 0");
+                process::exit(misuse);
             }
             if matches.opt_str("keep-stage").is_some()
                 || matches.opt_str("keep-stage-std").is_some()
             {
-                println!("--keep-stage not supported for x.py check, only one stage available");
-                process::exit(1);
+                eprintln!("--keep-stage not supported for x.py check, only one stage available");
+                process::exit(misuse);
+            }
+            if matches.opt_present("all-targets")
+                && (matches.opt_present("lib")
+                    || matches.opt_present("bins")
+                    || matches.opt_present("bin"))
+            {
+                eprintln!("--all-targets can't be combined with --lib, --bins or --bin");
+                process::exit(misuse);
+            }
+            if matches.opt_present("all-targets") && matches.opt_present("no-all-targets") {
+                eprintln!("--all-targets can't be combined with --no-all-targets");
+                process::exit(misuse);
+            }
+            if matches.opt_present("std-only") && matches.opt_present("rustc-only") {
+                eprintln!("--std-only can't be combined with --rustc-only");
+                process::exit(misuse);
+            }
+            if (matches.opt_present("std-only") || matches.opt_present("rustc-only"))
+                && !matches.free[1..].is_empty()
+            {
+                eprintln!("--std-only and --rustc-only can't be combined with paths");
+                process::exit(misuse);
+            }
+            if matches.opt_present("manifest-path")
+                && (matches.opt_present("std-only")
+                    || matches.opt_present("rustc-only")
+                    || !matches.free[1..].is_empty())
+            {
+                eprintln!(
+                    "--manifest-path can't be combined with paths, --std-only or --rustc-only"
+                );
+                process::exit(misuse);
             }
         }
 
         Flags {
             verbose: matches.opt_count("verbose"),
-            stage: matches.opt_str("stage").map(|j| j.parse().expect("`stage` should be a number")),
+            stage: parse_opt(&matches, "stage", "a number", misuse),
             dry_run: matches.opt_present("dry-run"),
             on_fail: matches.opt_str("on-fail"),
             rustc_error_format: matches.opt_str("error-format"),
             json_output: matches.opt_present("json-output"),
+            emit_sysroot_manifest: matches.opt_present("emit-sysroot-manifest"),
             keep_stage: matches
                 .opt_strs("keep-stage")
                 .into_iter()
-                .map(|j| j.parse().expect("`keep-stage` should be a number"))
+                .map(|j| parse_value("keep-stage", &j, "a number", misuse))
                 .collect(),
             keep_stage_std: matches
                 .opt_strs("keep-stage-std")
                 .into_iter()
-                .map(|j| j.parse().expect("`keep-stage-std` should be a number"))
+                .map(|j| parse_value("keep-stage-std", &j, "a number", misuse))
                 .collect(),
             host: if matches.opt_present("host") {
                 Some(
@@ -622,7 +1282,7 @@ Arguments:
                 None
             },
             config: cfg_file,
-            jobs: matches.opt_str("jobs").map(|j| j.parse().expect("`jobs` should be a number")),
+            jobs: parse_opt(&matches, "jobs", "a number", misuse),
             cmd,
             incremental: matches.opt_present("incremental"),
             exclude: split(&matches.opt_strs("exclude"))
@@ -630,10 +1290,10 @@ Arguments:
                 .map(|p| p.into())
                 .collect::<Vec<_>>(),
             include_default_paths: matches.opt_present("include-default-paths"),
-            deny_warnings: parse_deny_warnings(&matches),
-            llvm_skip_rebuild: matches.opt_str("llvm-skip-rebuild").map(|s| s.to_lowercase()).map(
-                |s| s.parse::<bool>().expect("`llvm-skip-rebuild` should be either true or false"),
-            ),
+            deny_warnings: parse_deny_warnings(&matches, misuse),
+            llvm_skip_rebuild: matches.opt_str("llvm-skip-rebuild").map(|s| {
+                parse_value("llvm-skip-rebuild", &s.to_lowercase(), "either true or false", misuse)
+            }),
         }
     }
 }
@@ -650,13 +1310,94 @@ impl Subcommand {
 
     pub fn rustc_args(&self) -> Vec<&str> {
         match *self {
-            Subcommand::Test { ref rustc_args, .. } => {
+            Subcommand::Test { ref rustc_args, .. }
+            | Subcommand::Check { ref rustc_args, .. }
+            | Subcommand::Clippy { ref rustc_args, .. } => {
                 rustc_args.iter().flat_map(|s| s.split_whitespace()).collect()
             }
             _ => Vec::new(),
         }
     }
 
+    pub fn clippy_fix(&self) -> bool {
+        match *self {
+            Subcommand::Clippy { fix, .. } => fix,
+            _ => false,
+        }
+    }
+
+    pub fn lint_args(&self) -> &[String] {
+        match *self {
+            Subcommand::Clippy { ref lint_args, .. } => lint_args,
+            _ => &[],
+        }
+    }
+
+    pub fn only_changed(&self) -> Option<&str> {
+        match *self {
+            Subcommand::Check { ref only_changed, .. }
+            | Subcommand::Clippy { ref only_changed, .. } => only_changed.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn keep_going(&self) -> bool {
+        match *self {
+            Subcommand::Check { keep_going, .. } | Subcommand::Clippy { keep_going, .. } => {
+                keep_going
+            }
+            _ => false,
+        }
+    }
+
+    pub fn check_jobs(&self) -> Option<u32> {
+        match *self {
+            Subcommand::Check { check_jobs, .. } => check_jobs,
+            _ => None,
+        }
+    }
+
+    /// The panic runtime std is checked against, which is also what the
+    /// sysroot gets.
+    pub fn panic_strategy(&self) -> StdProfile {
+        match *self {
+            Subcommand::Check { panic_strategy: Some(strategy), .. } => strategy,
+            _ => StdProfile::PanicUnwind,
+        }
+    }
+
+    pub fn explain_rebuild(&self) -> bool {
+        match *self {
+            Subcommand::Check { explain_rebuild, .. } => explain_rebuild,
+            _ => false,
+        }
+    }
+
+    pub fn fast_fresh(&self) -> bool {
+        match *self {
+            Subcommand::Check { fast_fresh, .. } => fast_fresh,
+            _ => false,
+        }
+    }
+
+    pub fn packages(&self) -> Vec<&str> {
+        match *self {
+            Subcommand::Check { ref packages, .. } => packages.iter().map(|s| &s[..]).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The only step `x.py check --std-only`, `--rustc-only` or
+    /// `--manifest-path` runs, if one of them was passed.
+    pub fn only_default_step(&self) -> Option<&'static str> {
+        match *self {
+            Subcommand::Check { std_only: true, .. } => Some("check::Std"),
+            Subcommand::Check { rustc_only: true, .. } => Some("check::Rustc"),
+            Subcommand::Check { manifest_path: Some(_), .. } => Some("check::Manifest"),
+            _ => None,
+        }
+    }
+
     pub fn fail_fast(&self) -> bool {
         match *self {
             Subcommand::Test { fail_fast, .. } => fail_fast,
@@ -711,14 +1452,93 @@ fn split(s: &[String]) -> Vec<String> {
     s.iter().flat_map(|s| s.split(',')).filter(|s| !s.is_empty()).map(|s| s.to_string()).collect()
 }
 
-fn parse_deny_warnings(matches: &getopts::Matches) -> Option<bool> {
+/// The first of `args` that isn't a lint level option or its lint. `--cap-lints`
+/// is rejected too, as it would keep the lints from being fixed.
+fn invalid_lint_arg(args: &[String]) -> Option<&String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-A" | "-W" | "-D" | "-F" | "--allow" | "--warn" | "--deny" | "--forbid" => {
+                if args.next().is_none() {
+                    return Some(arg);
+                }
+            }
+            _ if ["-A", "-W", "-D", "-F"].iter().any(|flag| arg.starts_with(flag)) => {}
+            _ if ["--allow=", "--warn=", "--deny=", "--forbid="]
+                .iter()
+                .any(|flag| arg.starts_with(flag)) => {}
+            _ => return Some(arg),
+        }
+    }
+    None
+}
+
+/// Parses the `--panic-strategy` of `x.py check`.
+fn panic_strategy(name: &str, exit_code: i32) -> StdProfile {
+    match name {
+        "abort" => StdProfile::PanicAbort,
+        "unwind" => StdProfile::PanicUnwind,
+        _ => {
+            eprintln!("\n--panic-strategy must be `abort` or `unwind`, not `{}`\n", name);
+            process::exit(exit_code);
+        }
+    }
+}
+
+/// Parses the value of `--{name}`, if given, exiting with `exit_code` if it
+/// isn't `what`.
+fn parse_opt<T: FromStr>(
+    matches: &getopts::Matches,
+    name: &str,
+    what: &str,
+    exit_code: i32,
+) -> Option<T> {
+    matches.opt_str(name).map(|value| parse_value(name, &value, what, exit_code))
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str, what: &str, exit_code: i32) -> T {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("error: `--{}` should be {}, not `{}`", name, what, value);
+        process::exit(exit_code);
+    })
+}
+
+fn parse_deny_warnings(matches: &getopts::Matches, exit_code: i32) -> Option<bool> {
     match matches.opt_str("warnings").as_deref() {
         Some("deny") => Some(true),
         Some("warn") => Some(false),
         Some(value) => {
             eprintln!(r#"invalid value for --warnings: {:?}, expected "warn" or "deny""#, value,);
-            process::exit(1);
+            process::exit(exit_code);
         }
         None => None,
     }
 }
+
+fn tool_target_args(matches: &getopts::Matches) -> Vec<String> {
+    let mut args = Vec::new();
+    if matches.opt_present("lib") {
+        args.push("--lib".to_string());
+    }
+    if matches.opt_present("bins") {
+        args.push("--bins".to_string());
+    }
+    for bin in matches.opt_strs("bin") {
+        args.push("--bin".to_string());
+        args.push(bin);
+    }
+    args
+}
+
+fn only_changed(matches: &getopts::Matches, exit_code: i32) -> Option<String> {
+    let since = matches.opt_str("since");
+    match (matches.opt_str("only-changed"), since) {
+        (Some(base), Some(since)) if base != since => {
+            eprintln!("error: `--since {}` and `--only-changed {}` disagree", since, base);
+            process::exit(exit_code);
+        }
+        (Some(base), _) | (None, Some(base)) => Some(base),
+        (None, None) if matches.opt_present("only-changed") => Some("HEAD".to_string()),
+        (None, None) => None,
+    }
+}
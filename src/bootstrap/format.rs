@@ -3,7 +3,11 @@
 use crate::Build;
 use build_helper::{output, t};
 use ignore::WalkBuilder;
-use std::path::Path;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
 fn rustfmt(src: &Path, rustfmt: &Path, path: &Path, check: bool) {
@@ -117,3 +121,81 @@ pub fn format(build: &Build, check: bool) {
         })
     });
 }
+
+/// Hashes the contents of the Rust files that differ from `HEAD`, so that two
+/// snapshots taken around `x.py fix` tell which files it edited. Returns `None`
+/// if git can't answer.
+pub fn modified_files(build: &Build) -> Option<HashMap<PathBuf, u64>> {
+    let output = Command::new("git")
+        .current_dir(&build.src)
+        .args(&["diff", "--name-only", "HEAD"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let files = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|path| path.ends_with(".rs"))
+        .map(|path| {
+            let path = build.src.join(path);
+            let mut hasher = DefaultHasher::new();
+            // Deleted files hash as empty, which is fine as rustfmt skips them.
+            fs::read(&path).unwrap_or_default().hash(&mut hasher);
+            (path, hasher.finish())
+        })
+        .collect();
+    Some(files)
+}
+
+/// Runs rustfmt over the files that changed since the `before` snapshot taken
+/// by `modified_files`, skipping those `rustfmt.toml` ignores.
+///
+/// Not having rustfmt isn't an error: the files are listed instead so they can
+/// be formatted by hand.
+pub fn format_fixed_files(build: &Build, before: Option<HashMap<PathBuf, u64>>) {
+    let (before, after) = match (before, modified_files(build)) {
+        (Some(before), Some(after)) => (before, after),
+        _ => {
+            eprintln!("Could not find usable git; not formatting the files `x.py fix` edited");
+            return;
+        }
+    };
+    let mut fixed = after
+        .into_iter()
+        .filter(|(path, hash)| before.get(path) != Some(hash) && path.exists())
+        .map(|(path, _)| path)
+        .collect::<Vec<_>>();
+    fixed.sort();
+
+    let rustfmt_config = build.src.join("rustfmt.toml");
+    if rustfmt_config.exists() {
+        let rustfmt_config = t!(fs::read_to_string(&rustfmt_config));
+        let rustfmt_config: RustfmtConfig = t!(toml::from_str(&rustfmt_config));
+        let mut ignore_fmt = ignore::overrides::OverrideBuilder::new(&build.src);
+        for ignore in rustfmt_config.ignore {
+            ignore_fmt.add(&format!("!{}", ignore)).expect(&ignore);
+        }
+        let ignore_fmt = ignore_fmt.build().unwrap();
+        fixed.retain(|path| !ignore_fmt.matched(path, false).is_ignore());
+    }
+    if fixed.is_empty() {
+        return;
+    }
+
+    let rustfmt_path = match build.config.initial_rustfmt {
+        Some(ref path) if path.exists() => path,
+        _ => {
+            eprintln!("rustfmt is not available; these files were edited by `x.py fix`:");
+            for path in &fixed {
+                eprintln!("    {}", path.strip_prefix(&build.src).unwrap_or(path).display());
+            }
+            return;
+        }
+    };
+    build.info(&format!("Formatting {} file(s) edited by `x.py fix`", fixed.len()));
+    for path in &fixed {
+        rustfmt(&build.src, rustfmt_path, path, false);
+    }
+}
@@ -0,0 +1,93 @@
+//! Dependency graphs as Graphviz DOT and JSON.
+//!
+//! `x.py check --emit-dep-graph` exports the graph of the crates it checked,
+//! with the dependencies between them as resolved by Cargo, to
+//! `build/check-depgraph.{dot,json}`. Other graphs bootstrap knows about,
+//! such as the steps a run ensured, can be exported through `Graph` the same
+//! way.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+use build_helper::t;
+use serde::Serialize;
+
+use crate::cache::Interned;
+use crate::{Build, Crate, Subcommand};
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<String>,
+    pub edges: Vec<Edge>,
+}
+
+/// An edge from `from` to `to`, which `from` depends on.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+}
+
+impl Graph {
+    /// Renders the graph as the DOT digraph `name`.
+    pub fn to_dot(&self, name: &str) -> String {
+        let mut out = format!("digraph {} {{\n", quote(name));
+        for node in &self.nodes {
+            out.push_str(&format!("    {};\n", quote(node)));
+        }
+        for edge in &self.edges {
+            out.push_str(&format!("    {} -> {};\n", quote(&edge.from), quote(&edge.to)));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Writes the graph to `base` with the `dot` and the `json` extension.
+    pub fn write(&self, name: &str, base: &Path) {
+        t!(fs::write(base.with_extension("dot"), self.to_dot(name)));
+        t!(fs::write(base.with_extension("json"), t!(serde_json::to_string_pretty(self))));
+    }
+}
+
+fn quote(id: &str) -> String {
+    format!("\"{}\"", id.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Writes the graph of the crates `x.py check` checked, for `--emit-dep-graph`.
+pub fn emit_check_dep_graph(build: &Build) {
+    match build.config.cmd {
+        Subcommand::Check { emit_dep_graph: true, .. } if !build.config.dry_run => {}
+        _ => return,
+    }
+    let graph = checked_graph(build.crate_graph(), &build.checked_crates.borrow());
+    let base = build.out.join("check-depgraph");
+    graph.write("check", &base);
+    build.info(&format!(
+        "Wrote the dependency graph of {} checked crates to {}",
+        graph.nodes.len(),
+        base.with_extension("{dot,json}").display()
+    ));
+}
+
+/// The subgraph of `crates` induced by the `checked` ones, sorted by name.
+fn checked_graph(
+    crates: &HashMap<Interned<String>, Crate>,
+    checked: &BTreeSet<Interned<String>>,
+) -> Graph {
+    let mut graph = Graph::default();
+    for name in checked {
+        graph.nodes.push(name.to_string());
+        let deps = match crates.get(name) {
+            Some(krate) => krate.deps.iter().filter(|dep| checked.contains(dep)),
+            None => continue,
+        };
+        for dep in deps.collect::<BTreeSet<_>>() {
+            graph.edges.push(Edge { from: name.to_string(), to: dep.to_string() });
+        }
+    }
+    graph
+}
+
+#[cfg(test)]
+mod tests;
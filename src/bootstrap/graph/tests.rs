@@ -0,0 +1,56 @@
+use super::*;
+use crate::cache::INTERNER;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+fn krate(name: &str, deps: &[&str]) -> (Interned<String>, Crate) {
+    let name = INTERNER.intern_str(name);
+    let krate = Crate {
+        name,
+        deps: deps.iter().map(|dep| INTERNER.intern_str(dep)).collect::<HashSet<_>>(),
+        id: String::new(),
+        path: PathBuf::new(),
+        proc_macro: false,
+    };
+    (name, krate)
+}
+
+#[test]
+fn checked_graph_leaves_out_unchecked_crates() {
+    let crates = vec![
+        krate("rustc_middle", &["rustc_ast", "rustc_span", "smallvec"]),
+        krate("rustc_ast", &["rustc_span"]),
+        krate("rustc_span", &[]),
+        krate("smallvec", &[]),
+    ]
+    .into_iter()
+    .collect::<HashMap<_, _>>();
+    let checked = ["rustc_middle", "rustc_ast", "rustc_span"]
+        .iter()
+        .map(|name| INTERNER.intern_str(name))
+        .collect::<BTreeSet<_>>();
+
+    let graph = checked_graph(&crates, &checked);
+    assert_eq!(graph.nodes, ["rustc_ast", "rustc_middle", "rustc_span"]);
+    let edges = graph.edges.iter().map(|edge| (&edge.from[..], &edge.to[..])).collect::<Vec<_>>();
+    assert_eq!(
+        edges,
+        [
+            ("rustc_ast", "rustc_span"),
+            ("rustc_middle", "rustc_ast"),
+            ("rustc_middle", "rustc_span")
+        ]
+    );
+}
+
+#[test]
+fn dot_output() {
+    let graph = Graph {
+        nodes: vec!["a".to_string(), "b\"c".to_string()],
+        edges: vec![Edge { from: "a".to_string(), to: "b\"c".to_string() }],
+    };
+    assert_eq!(
+        graph.to_dot("check"),
+        "digraph \"check\" {\n    \"a\";\n    \"b\\\"c\";\n    \"a\" -> \"b\\\"c\";\n}\n"
+    );
+}
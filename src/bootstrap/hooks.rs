@@ -0,0 +1,87 @@
+//! Implementation of the `[hooks]` section of config.toml.
+//!
+//! Hooks are shell commands run after a check step, or the whole `x.py check`,
+//! succeeded, e.g. to regenerate a tags file or to run a custom lint driver
+//! over the checked crates. They run one after the other from the source
+//! directory. A failing hook is only reported, unless `hooks.strict` is set.
+
+use std::path::Path;
+use std::process::{self, Command};
+
+use build_helper::t;
+
+use crate::config::TargetSelection;
+use crate::{Build, Subcommand};
+
+/// Runs the `hooks.post-step` commands of the step `step`, e.g.
+/// `check::Rustc`, which checked `target` and wrote `stamp`, with the metadata
+/// of the checked crates in `sysroot`.
+pub fn post_step(
+    build: &Build,
+    step: &'static str,
+    target: TargetSelection,
+    stamp: &Path,
+    sysroot: &Path,
+) {
+    let hooks = match build.config.hooks_post_step.get(step) {
+        Some(hooks) if !build.config.dry_run => hooks,
+        _ => return,
+    };
+    // Only the std and compiler steps say which crates they checked.
+    let crates = build
+        .check_results
+        .borrow()
+        .get(&(step, target))
+        .map(|crates| crates.iter().map(|krate| &krate[..]).collect::<Vec<_>>().join(" "));
+    for hook in hooks {
+        let mut cmd = shell(hook);
+        cmd.env("BOOTSTRAP_HOOK_STEP", step)
+            .env("BOOTSTRAP_HOOK_TARGET", &*target.triple)
+            .env("BOOTSTRAP_HOOK_STAMP", stamp)
+            .env("BOOTSTRAP_HOOK_SYSROOT", sysroot);
+        if let Some(crates) = &crates {
+            cmd.env("BOOTSTRAP_HOOK_CRATES", crates);
+        }
+        run(build, hook, cmd);
+    }
+}
+
+/// Runs the `hooks.post-check` commands once `x.py check` succeeded.
+pub fn post_check(build: &Build) {
+    if build.config.dry_run || !matches!(build.config.cmd, Subcommand::Check { .. }) {
+        return;
+    }
+    for hook in &build.config.hooks_post_check {
+        let mut cmd = shell(hook);
+        cmd.env("BOOTSTRAP_HOOK_STEP", "check");
+        run(build, hook, cmd);
+    }
+}
+
+fn shell(hook: &str) -> Command {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    cmd.arg(hook);
+    cmd
+}
+
+fn run(build: &Build, hook: &str, mut cmd: Command) {
+    build.verbose(&format!("running hook: {}", hook));
+    if t!(cmd.current_dir(&build.src).status()).success() {
+        return;
+    }
+    // A distinct exit status, as documented in `x.py check --help`, so that
+    // scripts don't take a failing hook for errors in the checked crates.
+    if build.config.hooks_strict {
+        eprintln!("error: the hook `{}` failed", hook);
+        process::exit(4);
+    }
+    println!("warning: the hook `{}` failed", hook);
+}
@@ -104,7 +104,7 @@
 //! also check out the `src/bootstrap/README.md` file for more information.
 
 use std::cell::{Cell, RefCell};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::env;
 use std::fs::{self, File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
@@ -121,9 +121,10 @@ use std::os::windows::fs::symlink_file;
 use build_helper::{mtime, output, run, run_suppressed, t, try_run, try_run_suppressed};
 use filetime::FileTime;
 
-use crate::config::TargetSelection;
+use crate::config::{StdProfile, TargetSelection};
 use crate::util::{exe, libdir, CiEnv};
 
+mod budgets;
 mod builder;
 mod cache;
 mod cc_detect;
@@ -134,13 +135,20 @@ mod compile;
 mod config;
 mod dist;
 mod doc;
+mod duplicates;
 mod flags;
 mod format;
+mod graph;
+mod hooks;
 mod install;
+mod lints;
 mod metadata;
 mod native;
+mod presubmit;
+mod report;
 mod run;
 mod sanity;
+mod sarif;
 mod setup;
 mod test;
 mod tool;
@@ -165,6 +173,8 @@ mod job {
 }
 
 use crate::cache::{Interned, INTERNER};
+pub use crate::check::CheckOutcome;
+pub use crate::compile::CargoFailure;
 pub use crate::config::Config;
 pub use crate::flags::Subcommand;
 
@@ -262,9 +272,24 @@ pub struct Build {
     ranlib: HashMap<TargetSelection, PathBuf>,
     // Miscellaneous
     crates: HashMap<Interned<String>, Crate>,
+    // The crates `in_tree_crates` found for each root it was asked about
+    in_tree_crates: RefCell<HashMap<Interned<String>, Vec<Interned<String>>>>,
     is_sudo: bool,
     ci_env: CiEnv,
     delayed_failures: RefCell<Vec<String>>,
+    // Crates checked by `x.py check`, for `--checked-crates-output`
+    checked_crates: RefCell<BTreeSet<Interned<String>>>,
+    // The crates each check step checked per target, for `hooks.post-step`
+    check_results: RefCell<HashMap<(&'static str, TargetSelection), BTreeSet<Interned<String>>>>,
+    // How long checking each library crate took, for the check budgets
+    check_timings: RefCell<Vec<budgets::CrateTiming>>,
+    // The Cargo invocations walking the steps planned, for `check --print-config`
+    planned_steps: RefCell<Vec<compile::PlannedStep>>,
+    diagnostics: RefCell<Vec<report::Entry>>,
+    // The diagnostics Cargo emitted so far, for `check_std`
+    diagnostic_count: Cell<usize>,
+    // Whether `-p` names matching no crate were already warned about
+    unknown_packages_reported: Cell<bool>,
     prerelease_version: Cell<Option<u32>>,
     tool_artifacts:
         RefCell<HashMap<TargetSelection, HashMap<String, (&'static str, PathBuf, Vec<String>)>>>,
@@ -276,6 +301,8 @@ struct Crate {
     deps: HashSet<Interned<String>>,
     id: String,
     path: PathBuf,
+    // Whether the crate is a proc-macro, which always runs on the host
+    proc_macro: bool,
 }
 
 impl Crate {
@@ -425,9 +452,17 @@ impl Build {
             ar: HashMap::new(),
             ranlib: HashMap::new(),
             crates: HashMap::new(),
+            in_tree_crates: RefCell::new(HashMap::new()),
             is_sudo,
             ci_env: CiEnv::current(),
             delayed_failures: RefCell::new(Vec::new()),
+            checked_crates: RefCell::new(BTreeSet::new()),
+            check_timings: RefCell::new(Vec::new()),
+            check_results: RefCell::new(HashMap::new()),
+            planned_steps: RefCell::new(Vec::new()),
+            diagnostics: RefCell::new(Vec::new()),
+            diagnostic_count: Cell::new(0),
+            unknown_packages_reported: Cell::new(false),
             prerelease_version: Cell::new(None),
             tool_artifacts: Default::default(),
         };
@@ -451,6 +486,15 @@ impl Build {
             build.verbose(&format!("auto-detected local-rebuild {}", local_release));
             build.local_rebuild = true;
         }
+        // Checking with an outdated stage0 fails with errors that look like
+        // they're about the code, so that's where it's caught first.
+        let checks_stage0 = matches!(
+            build.config.cmd,
+            Subcommand::Check { .. } | Subcommand::Build { .. } | Subcommand::Test { .. }
+        );
+        if checks_stage0 && !build.local_rebuild {
+            sanity::check_stage0_version(&build, local_release);
+        }
 
         build.verbose("learning about cargo");
         metadata::build(&mut build);
@@ -462,6 +506,14 @@ impl Build {
         slice::from_ref(&self.build.triple)
     }
 
+    /// Checks std for `target`, like `x.py check library/std` would, and
+    /// returns how it went rather than exiting if it fails. The configuration
+    /// must have been parsed for `check`, `clippy` or `fix`, e.g. with
+    /// `Config::parse_with_toml(&["check".to_string()], toml)`.
+    pub fn check_std(&self, target: &str) -> Result<CheckOutcome, CargoFailure> {
+        check::check_std(self, TargetSelection::from_user(target))
+    }
+
     /// Executes the entire build, as configured by the flags and configuration.
     pub fn build(&mut self) {
         unsafe {
@@ -472,14 +524,37 @@ impl Build {
             return format::format(self, check);
         }
 
-        if let Subcommand::Clean { all } = self.config.cmd {
-            return clean::clean(self, all);
+        if let Subcommand::Clean { all, stamps } = self.config.cmd {
+            return clean::clean(self, all, stamps);
         }
 
         if let Subcommand::Setup { profile } = &self.config.cmd {
             return setup::setup(&self.config.src, *profile);
         }
 
+        if let Subcommand::SetupEditor { editor } = self.config.cmd {
+            return setup::setup_editor(&self.config.src, editor);
+        }
+
+        if let Subcommand::Presubmit { fail_fast } = self.config.cmd {
+            return presubmit::presubmit(self, fail_fast);
+        }
+
+        if let Subcommand::Check { print_stamp_path: true, .. } = self.config.cmd {
+            return check::print_stamp_paths(self);
+        }
+
+        if let Subcommand::Check { print_config: true, .. } = self.config.cmd {
+            // Only walk the steps, but with the stamps of a real run.
+            self.config.dry_run = true;
+            return check::print_config(self);
+        }
+
+        if let Subcommand::Clippy { print_lint_flags: true, .. } = self.config.cmd {
+            println!("{}", builder::Kind::clippy_args(&self.config).join(" "));
+            return;
+        }
+
         {
             let builder = builder::Builder::new(&self);
             if let Some(path) = builder.paths.get(0) {
@@ -489,6 +564,21 @@ impl Build {
             }
         }
 
+        if let Subcommand::Clippy { .. } = self.config.cmd {
+            if self.config.stage == 0 && !self.config.dry_run {
+                self.report_stage0_clippy();
+            }
+        }
+
+        // Which files `cargo fix` edits is only known by comparing the working
+        // tree before and after it runs.
+        let fix_snapshot = match self.config.cmd {
+            Subcommand::Fix { .. } if self.config.fix_auto_format && !self.config.dry_run => {
+                Some(format::modified_files(self))
+            }
+            _ => None,
+        };
+
         if !self.config.dry_run {
             {
                 self.config.dry_run = true;
@@ -503,6 +593,26 @@ impl Build {
             builder.execute_cli();
         }
 
+        if let Some(before) = fix_snapshot {
+            format::format_fixed_files(self, before);
+        }
+
+        if let Subcommand::Check { checked_crates_output: Some(ref path), .. } = self.config.cmd {
+            if !self.config.dry_run {
+                let mut contents = String::new();
+                for krate in self.checked_crates.borrow().iter() {
+                    contents.push_str(krate);
+                    contents.push('\n');
+                }
+                t!(fs::write(path, contents));
+            }
+        }
+        graph::emit_check_dep_graph(self);
+        report::finish(self);
+        sarif::finish(self);
+        duplicates::report(self);
+        budgets::finish(self);
+
         // Check for postponed failures from `test --no-fail-fast`.
         let failures = self.delayed_failures.borrow();
         if failures.len() > 0 {
@@ -512,6 +622,8 @@ impl Build {
             }
             process::exit(1);
         }
+
+        hooks::post_check(self);
     }
 
     /// Clear out `dir` if `input` is newer.
@@ -533,9 +645,12 @@ impl Build {
     }
 
     /// Gets the space-separated set of activated features for the standard
-    /// library.
-    fn std_features(&self) -> String {
-        let mut features = "panic-unwind".to_string();
+    /// library when built with `profile`.
+    fn std_features(&self, profile: StdProfile) -> String {
+        let mut features = match profile {
+            StdProfile::PanicUnwind => "panic-unwind".to_string(),
+            StdProfile::PanicAbort => String::new(),
+        };
 
         if self.config.llvm_libunwind {
             features.push_str(" llvm-libunwind");
@@ -597,7 +712,36 @@ impl Build {
             Mode::ToolBootstrap => "-bootstrap-tools",
             Mode::ToolStd | Mode::ToolRustc => "-tools",
         };
-        self.out.join(&*compiler.host.triple).join(format!("stage{}{}", compiler.stage, suffix))
+        self.cargo_out_root()
+            .join(&*compiler.host.triple)
+            .join(format!("stage{}{}", compiler.stage, suffix))
+    }
+
+    /// Like `stage_out`, but for the Cargo invocations of the check-like
+    /// commands. Each of them gets its own directory, so that alternating
+    /// between e.g. `x.py check` and `x.py clippy` doesn't make both of them
+    /// start over.
+    fn check_stage_out(&self, compiler: Compiler, mode: Mode) -> PathBuf {
+        let out = self.stage_out(compiler, mode);
+        let kind = match self.config.cmd {
+            Subcommand::Clippy { .. } => "-clippy",
+            Subcommand::Fix { .. } => "-fix",
+            _ => return out,
+        };
+        let mut name = out.file_name().unwrap().to_os_string();
+        name.push(kind);
+        out.with_file_name(name)
+    }
+
+    /// The directory all Cargo output goes under, which is `build.check-out-dir`
+    /// for the check-like commands if that's set.
+    fn cargo_out_root(&self) -> &Path {
+        match (&self.config.check_out_dir, &self.config.cmd) {
+            (Some(dir), Subcommand::Check { .. })
+            | (Some(dir), Subcommand::Clippy { .. })
+            | (Some(dir), Subcommand::Fix { .. }) => dir,
+            _ => &self.out,
+        }
     }
 
     /// Returns the root output directory for all Cargo output in a given stage,
@@ -607,6 +751,12 @@ impl Build {
         self.stage_out(compiler, mode).join(&*target.triple).join(self.cargo_dir())
     }
 
+    /// `cargo_out` for the Cargo invocations of the check-like commands, see
+    /// `check_stage_out`.
+    fn check_cargo_out(&self, compiler: Compiler, mode: Mode, target: TargetSelection) -> PathBuf {
+        self.check_stage_out(compiler, mode).join(&*target.triple).join(self.cargo_dir())
+    }
+
     /// Root output directory for LLVM compiled for `target`
     ///
     /// Note that if LLVM is configured externally then the directory returned
@@ -783,12 +933,29 @@ impl Build {
     }
 
     fn info(&self, msg: &str) {
-        if self.config.dry_run {
+        if self.config.dry_run || matches!(self.config.cmd, Subcommand::Check { quiet: true, .. }) {
             return;
         }
         println!("{}", msg);
     }
 
+    /// Says which clippy `x.py clippy` lints with, as the stage0 one can
+    /// disagree with the in-tree clippy about which lints fire.
+    fn report_stage0_clippy(&self) {
+        let version = Command::new(&self.initial_cargo)
+            .args(&["clippy", "--version"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+        let version = version.as_deref().unwrap_or("the clippy");
+        self.info(&format!(
+            "Linting with {} of the stage0 toolchain, which may report different lints \
+             than the in-tree clippy; pass `--stage 1` to use that instead",
+            version
+        ));
+    }
+
     /// Returns the number of parallel jobs that have been configured for this
     /// build.
     fn jobs(&self) -> u32 {
@@ -934,6 +1101,11 @@ impl Build {
         self.config.target_config.get(&target).and_then(|t| t.wasi_root.as_ref()).map(|p| &**p)
     }
 
+    /// Returns the `rustflags` of `target` in config.toml.
+    fn target_rustflags(&self, target: TargetSelection) -> &[String] {
+        self.config.target_config.get(&target).map_or(&[], |t| &t.rustflags)
+    }
+
     /// Returns `true` if this is a no-std `target`, if defined
     fn no_std(&self, target: TargetSelection) -> Option<bool> {
         self.config.target_config.get(&target).map(|t| t.no_std)
@@ -1102,15 +1274,34 @@ impl Build {
         }
     }
 
+    /// The local crates of the workspace, which `cargo metadata` is only run
+    /// once for, when the build is set up. Everything looking at the crate
+    /// graph goes through this instead of running Cargo again.
+    fn crate_graph(&self) -> &HashMap<Interned<String>, Crate> {
+        &self.crates
+    }
+
     /// Returns a Vec of all the dependencies of the given root crate,
     /// including transitive dependencies and the root itself. Only includes
     /// "local" crates (those in the local source tree, not from a registry).
+    ///
+    /// The crate graph doesn't change once it's loaded, so the list is only
+    /// computed once for each root.
     fn in_tree_crates(&self, root: &str) -> Vec<&Crate> {
+        let root = INTERNER.intern_str(root);
+        let mut cache = self.in_tree_crates.borrow_mut();
+        let names = cache.entry(root).or_insert_with(|| {
+            self.find_in_tree_crates(root).into_iter().map(|krate| krate.name).collect()
+        });
+        names.iter().map(|name| &self.crate_graph()[name]).collect()
+    }
+
+    fn find_in_tree_crates(&self, root: Interned<String>) -> Vec<&Crate> {
         let mut ret = Vec::new();
-        let mut list = vec![INTERNER.intern_str(root)];
+        let mut list = vec![root];
         let mut visited = HashSet::new();
         while let Some(krate) = list.pop() {
-            let krate = &self.crates[&krate];
+            let krate = &self.crate_graph()[&krate];
             ret.push(krate);
             for dep in &krate.deps {
                 // Don't include optional deps if their features are not
@@ -1132,13 +1323,35 @@ impl Build {
         ret
     }
 
+    /// Returns whether `relative_path` is registered as a submodule in the
+    /// `.gitmodules` file at the root of the source tree.
+    fn is_submodule(&self, relative_path: &Path) -> bool {
+        let gitmodules = match fs::read_to_string(self.src.join(".gitmodules")) {
+            Ok(contents) => contents,
+            Err(_) => return false,
+        };
+        gitmodules
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("path"))
+            .filter_map(|line| line.trim_start().strip_prefix('='))
+            .any(|path| Path::new(path.trim()) == relative_path)
+    }
+
+    /// Checks out the submodule at `relative_path`, along with any submodules
+    /// nested inside of it.
+    fn update_submodule(&self, relative_path: &Path) {
+        let mut cmd = Command::new("git");
+        cmd.current_dir(&self.src).args(&["submodule", "update", "--init", "--recursive"]);
+        self.run(cmd.arg(relative_path));
+    }
+
     fn read_stamp_file(&self, stamp: &Path) -> Vec<(PathBuf, DependencyType)> {
         if self.config.dry_run {
             return Vec::new();
         }
 
         let mut paths = Vec::new();
-        let contents = t!(fs::read(stamp), &stamp);
+        let contents = t!(fs::read(util::long_path(stamp)), &stamp);
         // This is the method we use for extracting paths from the stamp file passed to us. See
         // run_cargo for more information (in compile.rs).
         for part in contents.split(|b| *b == 0) {
@@ -1146,17 +1359,39 @@ impl Build {
                 continue;
             }
             let dependency_type = match part[0] as char {
+                // The format version header and the trailer, see
+                // `compile::encode_stamp`. This doesn't verify them so that
+                // stamps in older formats can still be cleaned up.
+                'v' | 'c' => continue,
                 'h' => DependencyType::Host,
                 's' => DependencyType::TargetSelfContained,
                 't' => DependencyType::Target,
                 _ => unreachable!(),
             };
-            let path = PathBuf::from(t!(str::from_utf8(&part[1..])));
+            // Paths within the build directory are relative to it.
+            let path = self.out.join(t!(str::from_utf8(&part[1..])));
             paths.push((path, dependency_type));
         }
         paths
     }
 
+    /// Returns whether `stamp` exists and is a complete stamp in the current
+    /// format. Outdated and truncated stamps are removed with a notice.
+    fn validate_stamp(&self, stamp: &Path) -> bool {
+        let contents = match fs::read(stamp) {
+            Ok(contents) => contents,
+            Err(_) => return false,
+        };
+        match compile::decode_stamp(&contents, &self.out) {
+            Ok(_) => true,
+            Err(err) => {
+                self.info(&format!("Discarding {} stamp {}", err, stamp.display()));
+                t!(fs::remove_file(stamp));
+                false
+            }
+        }
+    }
+
     /// Copies a file from `src` to `dst`
     pub fn copy(&self, src: &Path, dst: &Path) {
         if self.config.dry_run {
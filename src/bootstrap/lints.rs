@@ -0,0 +1,153 @@
+//! Implementation of the lint set file of `x.py clippy`, which is
+//! `src/tools/clippy-lints.toml` unless `check.clippy-lints` names another.
+//!
+//! The file lists the lints allowed, warned about and denied across the tree:
+//!
+//! ```toml
+//! allow = ["clippy::too_many_arguments"]
+//! warn = ["clippy::needless_return"]
+//! deny = ["clippy::correctness"]
+//! ```
+//!
+//! Clippy gets them as `-A`, `-W` and `-D` options, the groups before the
+//! single lints so that a lint can be singled out of its group, and all of
+//! them before the lint options given after `--` on the command line.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct LintFile {
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    warn: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+/// The lint options derived from the lint set file.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LintSet {
+    pub flags: Vec<String>,
+    /// Hash of the file, which goes into the metadata of the linted crates so
+    /// that editing the file lints them again.
+    pub hash: Option<String>,
+}
+
+/// Reads the lint set file at `path`, checking its lint names against the
+/// clippy lints of the tree at `src`.
+pub fn load(path: &Path, src: &Path) -> Result<LintSet, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    let known = known_lints(src);
+    parse(&contents, known.as_ref())
+        .map_err(|(line, msg)| format!("{}:{}: {}", path.display(), line, msg))
+}
+
+/// Parses the contents of a lint set file, reporting errors with the number of
+/// the line they're on. Without `known` clippy lints, only the shape of the
+/// names is checked.
+fn parse(contents: &str, known: Option<&HashSet<String>>) -> Result<LintSet, (usize, String)> {
+    let file: LintFile = toml::from_str(contents).map_err(|err| {
+        // Unknown keys are reported at the start of their table, so look for
+        // them instead.
+        let msg = err.to_string();
+        let key = msg.strip_prefix("unknown field `").and_then(|rest| rest.split('`').next());
+        let line = key.map(|key| line_of(contents, key));
+        let line = line.or_else(|| err.line_col().map(|(line, _)| line + 1)).unwrap_or(1);
+        (line, format!("invalid lint set: {}", err))
+    })?;
+
+    let levels = [("-A", &file.allow), ("-W", &file.warn), ("-D", &file.deny)];
+    for (_, lints) in &levels {
+        for lint in lints.iter() {
+            if let Err(msg) = validate(lint, known) {
+                return Err((line_of(contents, lint), msg));
+            }
+        }
+    }
+
+    let mut flags = Vec::new();
+    for groups in &[true, false] {
+        for (flag, lints) in &levels {
+            for lint in lints.iter().filter(|lint| is_group(lint) == *groups) {
+                flags.push(flag.to_string());
+                flags.push(lint.clone());
+            }
+        }
+    }
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    Ok(LintSet { flags, hash: Some(format!("{:016x}", hasher.finish())) })
+}
+
+fn validate(lint: &str, known: Option<&HashSet<String>>) -> Result<(), String> {
+    let (tool, name) = match lint.find("::") {
+        Some(i) => (Some(&lint[..i]), &lint[i + 2..]),
+        None => (None, lint),
+    };
+    let well_formed = !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !well_formed {
+        let help =
+            if name.contains('-') { ", lints are named with `_` instead of `-`" } else { "" };
+        return Err(format!("invalid lint name `{}`{}", lint, help));
+    }
+    match (tool, known) {
+        (None, _) | (Some("clippy"), None) => Ok(()),
+        (Some("clippy"), Some(known)) if known.contains(name) => Ok(()),
+        (Some("clippy"), Some(_)) => Err(format!("unknown clippy lint `{}`", lint)),
+        (Some(tool), _) => Err(format!("unknown lint tool `{}` in `{}`", tool, lint)),
+    }
+}
+
+/// Whether `lint` names a group of lints rather than a single one.
+fn is_group(lint: &str) -> bool {
+    match lint.strip_prefix("clippy::") {
+        Some(name) => GROUPS.contains(&name),
+        None => lint == "warnings" || lint == "unused" || lint.starts_with("rust_"),
+    }
+}
+
+/// The groups of clippy lints.
+const GROUPS: &[&str] = &[
+    "all",
+    "cargo",
+    "complexity",
+    "correctness",
+    "internal",
+    "nursery",
+    "pedantic",
+    "perf",
+    "restriction",
+    "style",
+];
+
+/// The clippy lints and groups, if the tree at `src` has clippy's lint list.
+fn known_lints(src: &Path) -> Option<HashSet<String>> {
+    let list = fs::read_to_string(src.join("src/tools/clippy/src/lintlist/mod.rs")).ok()?;
+    let mut known = GROUPS.iter().map(|name| name.to_string()).collect::<HashSet<_>>();
+    for line in list.lines() {
+        if let Some(name) = line.trim().strip_prefix("name: \"") {
+            known.insert(name.trim_end_matches("\",").to_string());
+        }
+    }
+    Some(known)
+}
+
+/// The number of the first line mentioning `lint`.
+fn line_of(contents: &str, lint: &str) -> usize {
+    let quoted = format!("\"{}\"", lint);
+    let position = |needle: &str| contents.lines().position(|line| line.contains(needle));
+    position(&quoted).or_else(|| position(lint)).map_or(1, |i| i + 1)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,75 @@
+use super::*;
+
+fn known() -> HashSet<String> {
+    ["needless_return", "too_many_arguments", "style", "all"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+#[test]
+fn groups_come_before_lints() {
+    let contents = r#"
+allow = ["clippy::style", "dead_code"]
+warn = ["clippy::needless_return", "warnings"]
+deny = ["clippy::all"]
+"#;
+    let set = parse(contents, Some(&known())).unwrap();
+    assert_eq!(
+        set.flags,
+        [
+            "-A",
+            "clippy::style",
+            "-W",
+            "warnings",
+            "-D",
+            "clippy::all",
+            "-A",
+            "dead_code",
+            "-W",
+            "clippy::needless_return",
+        ]
+    );
+    assert!(set.hash.is_some());
+    assert_ne!(set.hash, parse("allow = []", None).unwrap().hash);
+}
+
+#[test]
+fn invalid_names_are_reported_with_their_line() {
+    let contents = "allow = [\n    \"clippy::style\",\n    \"clippy::needless-return\",\n]\n";
+    assert_eq!(
+        parse(contents, None).unwrap_err(),
+        (
+            3,
+            "invalid lint name `clippy::needless-return`, lints are named with `_` instead of `-`"
+                .to_string()
+        )
+    );
+
+    let contents = "warn = []\ndeny = [\"clippy::needles_return\"]\n";
+    assert_eq!(
+        parse(contents, Some(&known())).unwrap_err(),
+        (2, "unknown clippy lint `clippy::needles_return`".to_string())
+    );
+    // Without clippy's lint list, only the shape of the name is checked.
+    assert!(parse(contents, None).is_ok());
+
+    let contents = "deny = [\"rustdoc::broken_intra_doc_links\"]\n";
+    assert_eq!(
+        parse(contents, None).unwrap_err(),
+        (1, "unknown lint tool `rustdoc` in `rustdoc::broken_intra_doc_links`".to_string())
+    );
+}
+
+#[test]
+fn unknown_keys_are_rejected() {
+    let (line, msg) = parse("allow = []\nforbid = [\"clippy::all\"]\n", None).unwrap_err();
+    assert_eq!(line, 2);
+    assert!(msg.starts_with("invalid lint set: unknown field `forbid`"), "{}", msg);
+}
+
+#[test]
+fn the_tree_lint_set_is_valid() {
+    let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("../..");
+    load(&src.join("src/tools/clippy-lints.toml"), &src).unwrap();
+}
@@ -19,6 +19,12 @@ struct Package {
     source: Option<String>,
     manifest_path: String,
     dependencies: Vec<Dependency>,
+    targets: Vec<Target>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    kind: Vec<String>,
 }
 
 #[derive(Deserialize)]
@@ -50,7 +56,11 @@ pub fn build(build: &mut Build) {
                 .filter(|dep| dep.source.is_none())
                 .map(|dep| INTERNER.intern_string(dep.name))
                 .collect();
-            build.crates.insert(name, Crate { name, id: package.id, deps, path });
+            let proc_macro = package
+                .targets
+                .iter()
+                .any(|target| target.kind.iter().any(|kind| kind == "proc-macro"));
+            build.crates.insert(name, Crate { name, id: package.id, deps, path, proc_macro });
         }
     }
 }
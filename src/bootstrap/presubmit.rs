@@ -0,0 +1,84 @@
+//! Implementation of `x.py presubmit`.
+//!
+//! Runs what the fast tier of CI would catch, `x.py check`, `x.py clippy` and
+//! `x.py fmt --check`, one after the other with the options `presubmit` was
+//! given, and prints whether each of them passed. All of them run even if one
+//! fails, unless `--fail-fast` is passed.
+
+use std::env;
+use std::process::{self, Command};
+use std::time::{Duration, Instant};
+
+use build_helper::t;
+
+use crate::Build;
+
+/// The commands `x.py presubmit` runs, as the arguments replacing `presubmit`.
+const PHASES: &[&[&str]] = &[&["check"], &["clippy"], &["fmt", "--check"]];
+
+/// How one of the commands went.
+struct Outcome {
+    name: String,
+    passed: bool,
+    duration: Duration,
+}
+
+pub fn presubmit(build: &Build, fail_fast: bool) {
+    let exe = t!(env::current_exe());
+    let args = env::args().skip(1).collect::<Vec<_>>();
+
+    let mut outcomes = Vec::new();
+    for phase in PHASES {
+        let name = phase.join(" ");
+        build.info(&format!("Running `x.py {}`", name));
+
+        let start = Instant::now();
+        let mut cmd = Command::new(&exe);
+        cmd.args(phase_args(&args, phase));
+        build.verbose(&format!("running: {:?}", cmd));
+        let passed = t!(cmd.status()).success();
+        outcomes.push(Outcome { name, passed, duration: start.elapsed() });
+
+        if !passed && fail_fast {
+            break;
+        }
+    }
+
+    print_summary(&outcomes);
+    if outcomes.iter().any(|outcome| !outcome.passed) {
+        process::exit(1);
+    }
+}
+
+/// The arguments `x.py presubmit` was run with, for running `phase` instead.
+fn phase_args(args: &[String], phase: &[&str]) -> Vec<String> {
+    let mut phase_args = Vec::new();
+    let mut replaced = false;
+    for arg in args {
+        if arg == "presubmit" && !replaced {
+            phase_args.extend(phase.iter().map(|arg| arg.to_string()));
+            replaced = true;
+        } else if arg != "--fail-fast" {
+            phase_args.push(arg.clone());
+        }
+    }
+    phase_args
+}
+
+fn print_summary(outcomes: &[Outcome]) {
+    let width = PHASES.iter().map(|phase| phase.join(" ").len()).max().unwrap_or(0);
+    println!("\npresubmit summary:");
+    for outcome in outcomes {
+        println!(
+            "    {:width$}  {:6}  {}.{:03}s",
+            outcome.name,
+            if outcome.passed { "ok" } else { "FAILED" },
+            outcome.duration.as_secs(),
+            outcome.duration.subsec_millis(),
+            width = width
+        );
+    }
+    for phase in &PHASES[outcomes.len()..] {
+        println!("    {:width$}  skipped", phase.join(" "), width = width);
+    }
+}
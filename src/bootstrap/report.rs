@@ -0,0 +1,211 @@
+//! Static HTML report of the diagnostics emitted while checking.
+//!
+//! With `x.py check --report FILE`, every diagnostic Cargo forwards from rustc
+//! is collected in `Build::diagnostics` and rendered into a single HTML page,
+//! grouped by crate and then by file, with a per-crate summary of the lints
+//! that fired.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+
+use build_helper::t;
+use serde::Deserialize;
+
+use crate::{Build, Subcommand};
+
+/// A diagnostic as emitted by rustc in JSON, with only the fields the report
+/// shows.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Diagnostic {
+    pub message: String,
+    pub code: Option<DiagnosticCode>,
+    pub level: String,
+    pub spans: Vec<DiagnosticSpan>,
+    pub rendered: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiagnosticCode {
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiagnosticSpan {
+    pub file_name: String,
+    pub line_start: usize,
+    pub column_start: usize,
+    pub is_primary: bool,
+    pub text: Vec<DiagnosticSpanLine>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiagnosticSpanLine {
+    pub text: String,
+}
+
+/// A diagnostic along with the crate it was reported for.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub krate: String,
+    pub diagnostic: Diagnostic,
+}
+
+impl Entry {
+    pub fn new(package_id: &str, diagnostic: Diagnostic) -> Entry {
+        Entry { krate: crate_name(package_id).to_string(), diagnostic }
+    }
+
+    pub fn primary_span(&self) -> Option<&DiagnosticSpan> {
+        self.diagnostic.spans.iter().find(|span| span.is_primary)
+    }
+
+    /// The lint or error code, falling back to the level for diagnostics
+    /// without one.
+    pub fn kind(&self) -> &str {
+        match self.diagnostic.code {
+            Some(ref code) => &code.code,
+            None => &self.diagnostic.level,
+        }
+    }
+}
+
+/// Extracts the crate name from a Cargo package ID, which is either of the
+/// form `name version (source)` or `source#name@version`.
+pub fn crate_name(package_id: &str) -> &str {
+    let mut parts = package_id.rsplitn(2, '#');
+    match (parts.next(), parts.next()) {
+        (Some(fragment), Some(source)) => {
+            let name = fragment.split(|c| c == '@' || c == ':').next().unwrap_or(fragment);
+            if name.starts_with(|c: char| c.is_ascii_digit()) {
+                // `path+file:///.../name#version` leaves the name in the source.
+                source.rsplit('/').next().unwrap_or(source)
+            } else {
+                name
+            }
+        }
+        _ => package_id.split_whitespace().next().unwrap_or(package_id),
+    }
+}
+
+/// Where `--report` or `--open-report` asked for the report to be written.
+pub fn path(build: &Build) -> Option<PathBuf> {
+    match build.config.cmd {
+        Subcommand::Check { report: Some(ref path), .. } => Some(path.clone()),
+        Subcommand::Check { open_report: true, .. } => Some(build.out.join("check-report.html")),
+        _ => None,
+    }
+}
+
+/// Writes the report of all diagnostics collected so far, and opens it if
+/// `--open-report` was passed.
+pub fn finish(build: &Build) {
+    let path = match path(build) {
+        Some(path) if !build.config.dry_run => path,
+        _ => return,
+    };
+
+    t!(fs::write(&path, render(&build.diagnostics.borrow())));
+    build.info(&format!("Wrote the check report to {}", path.display()));
+    if let Subcommand::Check { open_report: true, .. } = build.config.cmd {
+        if let Err(err) = opener::open(&path) {
+            build.info(&format!("{}\n", err));
+        }
+    }
+}
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+td, th { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }
+.diagnostic { border-left: 4px solid #ccc; margin: 1em 0; padding-left: 1em; }
+.error { border-color: #d33; }
+.warning { border-color: #e90; }
+.location { color: #666; font-family: monospace; }
+pre { background: #f6f6f6; padding: 0.5em; overflow-x: auto; }";
+
+/// Renders `entries` as a standalone HTML page: crate, then file, then the
+/// diagnostics in the order they were emitted.
+pub fn render(entries: &[Entry]) -> String {
+    let mut crates: BTreeMap<&str, BTreeMap<&str, Vec<&Entry>>> = BTreeMap::new();
+    for entry in entries {
+        let file = entry.primary_span().map_or("(no location)", |span| &span.file_name[..]);
+        crates.entry(&entry.krate).or_default().entry(file).or_default().push(entry);
+    }
+
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<title>x.py check report</title>\n");
+    writeln!(html, "<style>\n{}\n</style>\n</head>\n<body>", STYLE).unwrap();
+    html.push_str("<h1>x.py check report</h1>\n");
+    writeln!(html, "<p>{} diagnostic(s) in {} crate(s)</p>", entries.len(), crates.len()).unwrap();
+
+    for (krate, files) in &crates {
+        writeln!(html, "<h2 id=\"crate-{0}\">{0}</h2>", escape(krate)).unwrap();
+
+        let mut kinds: BTreeMap<&str, usize> = BTreeMap::new();
+        for entry in files.values().flatten() {
+            *kinds.entry(entry.kind()).or_default() += 1;
+        }
+        html.push_str("<table>\n<tr><th>Lint</th><th>Count</th></tr>\n");
+        for (kind, count) in &kinds {
+            writeln!(html, "<tr><td>{}</td><td>{}</td></tr>", escape(kind), count).unwrap();
+        }
+        html.push_str("</table>\n");
+
+        for (file, entries) in files {
+            writeln!(html, "<h3>{}</h3>", escape(file)).unwrap();
+            for entry in entries {
+                render_diagnostic(&mut html, entry);
+            }
+        }
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn render_diagnostic(html: &mut String, entry: &Entry) {
+    let diagnostic = &entry.diagnostic;
+    writeln!(html, "<div class=\"diagnostic {}\">", escape(&diagnostic.level)).unwrap();
+    write!(html, "<p><strong>{}</strong>", escape(&diagnostic.level)).unwrap();
+    if let Some(ref code) = diagnostic.code {
+        write!(html, "[<code>{}</code>]", escape(&code.code)).unwrap();
+    }
+    writeln!(html, ": {}</p>", escape(&diagnostic.message)).unwrap();
+
+    if let Some(span) = entry.primary_span() {
+        writeln!(
+            html,
+            "<p class=\"location\">{}:{}:{}</p>",
+            escape(&span.file_name),
+            span.line_start,
+            span.column_start
+        )
+        .unwrap();
+        html.push_str("<pre>");
+        for (i, line) in span.text.iter().enumerate() {
+            writeln!(html, "{:>5} | {}", span.line_start + i, escape(&line.text)).unwrap();
+        }
+        html.push_str("</pre>\n");
+    }
+    html.push_str("</div>\n");
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,54 @@
+use super::*;
+use crate::compile::CargoMessage;
+use std::env;
+use std::path::Path;
+
+/// Parses the Cargo messages in `report/diagnostics.json` like `stream_cargo`
+/// does.
+fn fixture() -> Vec<Entry> {
+    include_str!("diagnostics.json")
+        .lines()
+        .filter_map(|line| match serde_json::from_str(line).unwrap() {
+            CargoMessage::CompilerMessage { package_id, message } => {
+                Some(Entry::new(&package_id, message))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Compares `actual` with the snapshot in `report/<name>`, or updates the
+/// snapshot if `BOOTSTRAP_BLESS` is set.
+fn assert_snapshot(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("report").join(name);
+    if env::var_os("BOOTSTRAP_BLESS").is_some() {
+        t!(fs::write(&path, actual));
+        return;
+    }
+    let expected = t!(fs::read_to_string(&path));
+    assert!(
+        expected == actual,
+        "{} is out of date, rerun the tests with BOOTSTRAP_BLESS=1 to update it",
+        path.display()
+    );
+}
+
+#[test]
+fn render_fixture() {
+    let entries = fixture();
+    assert_eq!(entries.len(), 5);
+    assert_snapshot("report.html", &render(&entries));
+}
+
+#[test]
+fn render_empty() {
+    assert_snapshot("empty.html", &render(&[]));
+}
+
+#[test]
+fn crate_names() {
+    assert_eq!(crate_name("core 0.0.0 (path+file:///checkout/library/core)"), "core");
+    assert_eq!(crate_name("path+file:///checkout/library/core#0.0.0"), "core");
+    assert_eq!(crate_name("path+file:///checkout/library/std#rustc-std@0.0.0"), "rustc-std");
+    assert_eq!(crate_name("registry+https://github.com/rust-lang/crates.io-index#cc@1.0.60"), "cc");
+}
@@ -13,7 +13,7 @@ use std::env;
 use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::{self, Command};
 
 use build_helper::{output, t};
 
@@ -231,3 +231,56 @@ $ pacman -R cmake && pacman -S mingw-w64-x86_64-cmake
         }
     }
 }
+
+/// Makes sure the stage0 compiler, whose release is `release`, is the one
+/// `src/stage0.txt` pins, warning if it isn't, or failing with
+/// `build.require-matching-stage0`.
+pub fn check_stage0_version(build: &Build, release: &str) {
+    let stage0 = t!(fs::read_to_string(build.src.join("src/stage0.txt")));
+    let expected = match expected_stage0(&stage0, &build.version, release) {
+        Some(expected) => expected,
+        None => return,
+    };
+
+    let level = if build.config.require_matching_stage0 { "error" } else { "warning" };
+    eprintln!(
+        "{}: the stage0 compiler is {}, but src/stage0.txt pins {}\n\
+         note: the code in the tree is written for the pinned one, so a different one can \
+         report errors that aren't there",
+        level, release, expected
+    );
+    if build.initial_rustc.starts_with(&build.out) {
+        let dir = build.initial_rustc.parent().and_then(|bin| bin.parent()).unwrap();
+        eprintln!("help: delete {} so that x.py downloads the pinned one again", dir.display());
+    } else {
+        eprintln!("help: point `build.rustc` in config.toml at a compiler matching src/stage0.txt");
+    }
+    if build.config.require_matching_stage0 {
+        process::exit(2);
+    }
+}
+
+/// What `stage0` (the contents of `src/stage0.txt`) pins the stage0 compiler
+/// to when building `version`, if the stage0 compiler's `release` isn't that.
+fn expected_stage0(stage0: &str, version: &str, release: &str) -> Option<String> {
+    let rustc = stage0.lines().find_map(|line| line.strip_prefix("rustc:"))?.trim();
+    let date = stage0.lines().find_map(|line| line.strip_prefix("date:")).unwrap_or("").trim();
+    if rustc == "beta" {
+        // The beta is the release before the one being built.
+        let mut parts = version.split('.');
+        let major = parts.next()?;
+        let minor = parts.next()?.parse::<u32>().ok()?.checked_sub(1)?;
+        let prefix = format!("{}.{}.", major, minor);
+        if release.starts_with(&prefix) && release.contains("-beta") {
+            return None;
+        }
+        Some(format!("the {}0 beta from {}", prefix, date))
+    } else if rustc.starts_with(|c: char| c.is_ascii_digit()) {
+        if release == rustc { None } else { Some(rustc.to_string()) }
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,28 @@
+use super::*;
+
+const STAGE0: &str = "\
+# comment
+date: 2020-10-16
+rustc: beta
+cargo: beta
+";
+
+#[test]
+fn pinned_beta_matches() {
+    assert_eq!(expected_stage0(STAGE0, "1.49.0", "1.48.0-beta.8"), None);
+    assert_eq!(expected_stage0(STAGE0, "1.49.0", "1.48.0-beta.2"), None);
+}
+
+#[test]
+fn outdated_beta_is_reported() {
+    let expected = Some("the 1.48.0 beta from 2020-10-16".to_string());
+    assert_eq!(expected_stage0(STAGE0, "1.49.0", "1.47.0-beta.6"), expected);
+    assert_eq!(expected_stage0(STAGE0, "1.49.0", "1.48.0"), expected);
+}
+
+#[test]
+fn pinned_release_must_match_exactly() {
+    let stage0 = "date: 2020-11-19\nrustc: 1.48.0\ncargo: 0.49.0\n";
+    assert_eq!(expected_stage0(stage0, "1.49.0", "1.48.0"), None);
+    assert_eq!(expected_stage0(stage0, "1.49.0", "1.47.0"), Some("1.48.0".to_string()));
+}
@@ -0,0 +1,108 @@
+//! SARIF output of `x.py clippy --sarif-output FILE`.
+//!
+//! The diagnostics Cargo forwards from clippy are collected in
+//! `Build::diagnostics`, as for the `x.py check` report, and written as a
+//! SARIF 2.1.0 log for code scanning tools once linting is done.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use build_helper::t;
+use serde_json::{json, Value};
+
+use crate::report::Entry;
+use crate::{Build, Subcommand};
+
+/// Where `--sarif-output` asked for the SARIF log to be written.
+pub fn path(build: &Build) -> Option<PathBuf> {
+    match build.config.cmd {
+        Subcommand::Clippy { sarif_output: Some(ref path), .. } => Some(path.clone()),
+        _ => None,
+    }
+}
+
+/// Whether the diagnostics only go to the SARIF log, for `--sarif-only`.
+pub fn only(build: &Build) -> bool {
+    matches!(build.config.cmd, Subcommand::Clippy { sarif_only: true, .. })
+}
+
+/// Writes the SARIF log of all diagnostics collected so far.
+pub fn finish(build: &Build) {
+    let path = match path(build) {
+        Some(path) if !build.config.dry_run => path,
+        _ => return,
+    };
+
+    let log = render(&build.diagnostics.borrow(), &build.src);
+    t!(fs::write(&path, t!(serde_json::to_string_pretty(&log))));
+    build.info(&format!("Wrote the SARIF log to {}", path.display()));
+}
+
+/// Renders `entries` as a SARIF log with a single run of clippy. Locations
+/// are made relative to `src` where they aren't already. Diagnostics without
+/// a location, such as the count of warnings of a crate, are left out.
+pub fn render(entries: &[Entry], src: &Path) -> Value {
+    let entries = entries.iter().filter(|entry| entry.primary_span().is_some());
+    let mut rules = BTreeSet::new();
+    let mut results = Vec::new();
+    for entry in entries {
+        let span = entry.primary_span().unwrap();
+        let uri =
+            Path::new(&span.file_name).strip_prefix(src).unwrap_or(Path::new(&span.file_name));
+        rules.insert(entry.kind());
+        results.push(json!({
+            "ruleId": entry.kind(),
+            "level": level(&entry.diagnostic.level),
+            "message": { "text": entry.diagnostic.message },
+            "locations": [{
+                "physicalLocation": {
+                    "artifactLocation": { "uri": uri.to_string_lossy().replace('\\', "/") },
+                    "region": { "startLine": span.line_start, "startColumn": span.column_start },
+                },
+            }],
+        }));
+    }
+
+    let rules = rules.into_iter().map(rule).collect::<Vec<_>>();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "clippy",
+                    "informationUri": "https://github.com/rust-lang/rust-clippy",
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+/// The SARIF level of a diagnostic of rustc's `level`.
+fn level(level: &str) -> &'static str {
+    if level.starts_with("error") {
+        "error"
+    } else if level == "warning" {
+        "warning"
+    } else {
+        "note"
+    }
+}
+
+/// Describes the rule `id`, a lint or an error code, linking to the
+/// documentation of clippy's lints.
+fn rule(id: &str) -> Value {
+    match id.strip_prefix("clippy::") {
+        Some(lint) => json!({
+            "id": id,
+            "helpUri": format!("https://rust-lang.github.io/rust-clippy/master/index.html#{}", lint),
+        }),
+        None => json!({ "id": id }),
+    }
+}
+
+#[cfg(test)]
+mod tests;
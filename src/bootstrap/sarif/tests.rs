@@ -0,0 +1,59 @@
+use super::*;
+use crate::compile::CargoMessage;
+
+/// The diagnostics of the `x.py check` report's fixture, with the paths of
+/// some of them made absolute.
+fn fixture() -> Vec<Entry> {
+    include_str!("../report/diagnostics.json")
+        .lines()
+        .filter_map(|line| match serde_json::from_str(line).unwrap() {
+            CargoMessage::CompilerMessage { package_id, mut message } => {
+                for span in &mut message.spans {
+                    if span.file_name.starts_with("library/core") {
+                        span.file_name = format!("/checkout/{}", span.file_name);
+                    }
+                }
+                Some(Entry::new(&package_id, message))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn render_fixture() {
+    let log = render(&fixture(), Path::new("/checkout"));
+    assert_eq!(log["version"], "2.1.0");
+    let run = &log["runs"][0];
+    assert_eq!(run["tool"]["driver"]["name"], "clippy");
+
+    // The count of errors has no location.
+    let results = run["results"].as_array().unwrap();
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[1]["ruleId"], "E0308");
+    assert_eq!(results[1]["level"], "error");
+    assert_eq!(results[1]["message"]["text"], "mismatched types");
+    let region = &results[1]["locations"][0]["physicalLocation"]["region"];
+    assert_eq!(*region, json!({ "startLine": 42, "startColumn": 9 }));
+    for result in results {
+        let uri =
+            result["locations"][0]["physicalLocation"]["artifactLocation"]["uri"].as_str().unwrap();
+        assert!(uri.starts_with("library/"), "{} isn't relative to the source", uri);
+    }
+    let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+    let ids = rules.iter().map(|rule| rule["id"].as_str().unwrap()).collect::<BTreeSet<_>>();
+    for result in results {
+        assert!(ids.contains(result["ruleId"].as_str().unwrap()));
+    }
+}
+
+#[test]
+fn clippy_rules_link_to_the_lint() {
+    assert_eq!(
+        rule("clippy::needless_return")["helpUri"],
+        "https://rust-lang.github.io/rust-clippy/master/index.html#needless_return"
+    );
+    assert_eq!(rule("E0308"), json!({ "id": "E0308" }));
+    assert_eq!(level("error: internal compiler error"), "error");
+    assert_eq!(level("failure-note"), "note");
+}
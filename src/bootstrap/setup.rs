@@ -1,4 +1,5 @@
 use crate::{t, VERSION};
+use serde_json::{json, Value};
 use std::fmt::Write as _;
 use std::path::{Path, PathBuf};
 use std::process::Command;
@@ -125,6 +126,91 @@ pub fn setup(src_path: &Path, profile: Profile) {
     }
 }
 
+/// Where `x.py setup check-editor` puts the rust-analyzer settings.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Editor {
+    /// Merged into `.vscode/settings.json`.
+    VsCode,
+    /// Printed, for the rust-analyzer settings of any other editor.
+    Stdout,
+}
+
+impl FromStr for Editor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vscode" | "code" => Ok(Editor::VsCode),
+            "stdout" => Ok(Editor::Stdout),
+            _ => Err(format!("unknown editor: '{}', expected vscode or stdout", s)),
+        }
+    }
+}
+
+/// The command rust-analyzer runs on save instead of `cargo check`, from the
+/// root of the source directory.
+fn check_command() -> Value {
+    json!(["python3", "x.py", "check", "--json-output", "--quiet"])
+}
+
+/// Implementation of `x.py setup check-editor`.
+pub fn setup_editor(src_path: &Path, editor: Editor) {
+    if editor == Editor::Stdout {
+        let settings = json!({ "rust-analyzer": { "checkOnSave": {
+            "overrideCommand": check_command(),
+        } } });
+        println!("{}", t!(serde_json::to_string_pretty(&settings)));
+        return;
+    }
+
+    let path = src_path.join(".vscode").join("settings.json");
+    let existing = if path.exists() { Some(t!(fs::read_to_string(&path))) } else { None };
+    match merge_vscode_settings(existing.as_deref()) {
+        Ok(None) => {
+            println!("{} already makes rust-analyzer check with `x.py check`", path.display())
+        }
+        Ok(Some(settings)) => {
+            t!(fs::create_dir_all(path.parent().unwrap()));
+            t!(fs::write(&path, settings));
+            println!("rust-analyzer in VS Code will now check with `x.py check`");
+            println!("note: the setting is in {}", path.display());
+        }
+        Err(err) => {
+            eprintln!("error: can't update {}: {}", path.display(), err);
+            eprintln!("help: add this setting to it by hand:");
+            eprintln!("    \"{}\": {}", VSCODE_SETTING, check_command());
+            std::process::exit(1);
+        }
+    }
+}
+
+const VSCODE_SETTING: &str = "rust-analyzer.checkOnSave.overrideCommand";
+
+/// Adds the setting for `x.py check` to the `existing` contents of
+/// `.vscode/settings.json`, keeping the other settings. Returns `None` if it's
+/// already there.
+fn merge_vscode_settings(existing: Option<&str>) -> Result<Option<String>, String> {
+    let mut settings = match existing {
+        Some(existing) if !existing.trim().is_empty() => {
+            // VS Code allows comments in its settings, which we'd lose.
+            let settings: Value = serde_json::from_str(existing)
+                .map_err(|err| format!("it isn't plain JSON ({})", err))?;
+            match settings {
+                Value::Object(settings) => settings,
+                _ => return Err("it isn't a JSON object".to_string()),
+            }
+        }
+        _ => Default::default(),
+    };
+    if settings.get(VSCODE_SETTING) == Some(&check_command()) {
+        return Ok(None);
+    }
+    settings.insert(VSCODE_SETTING.to_string(), check_command());
+    let mut out = t!(serde_json::to_string_pretty(&settings));
+    out.push('\n');
+    Ok(Some(out))
+}
+
 // Used to get the path for `Subcommand::Setup`
 pub fn interactive_path() -> io::Result<Profile> {
     fn abbrev_all() -> impl Iterator<Item = (String, Profile)> {
@@ -216,3 +302,6 @@ simply delete the `pre-commit` file from .git/hooks."
         println!("Ok, skipping installation!");
     })
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,42 @@
+use super::*;
+
+#[test]
+fn check_editor_merges_vscode_settings() {
+    let merged = merge_vscode_settings(None).unwrap().unwrap();
+    assert_eq!(
+        merged,
+        "{\n  \"rust-analyzer.checkOnSave.overrideCommand\": [\n    \"python3\",\n    \"x.py\",\n    \
+         \"check\",\n    \"--json-output\",\n    \"--quiet\"\n  ]\n}\n"
+    );
+    assert_eq!(merge_vscode_settings(Some(&merged)).unwrap(), None);
+
+    let existing =
+        r#"{"editor.formatOnSave": true, "rust-analyzer.checkOnSave.overrideCommand": []}"#;
+    let merged: Value =
+        serde_json::from_str(&merge_vscode_settings(Some(existing)).unwrap().unwrap()).unwrap();
+    assert_eq!(merged["editor.formatOnSave"], true);
+    assert_eq!(merged[VSCODE_SETTING], check_command());
+}
+
+#[test]
+fn check_editor_leaves_unreadable_settings_alone() {
+    let err = merge_vscode_settings(Some("{\n  // comment\n}")).unwrap_err();
+    assert!(err.starts_with("it isn't plain JSON"), "{}", err);
+    assert_eq!(merge_vscode_settings(Some("[]")).unwrap_err(), "it isn't a JSON object");
+}
+
+#[test]
+fn check_editor_flag() {
+    let parse = |args: &[&str]| {
+        let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        crate::Config::parse(&args).cmd
+    };
+    assert!(matches!(
+        parse(&["setup", "check-editor"]),
+        crate::Subcommand::SetupEditor { editor: Editor::VsCode }
+    ));
+    assert!(matches!(
+        parse(&["setup", "check-editor", "--editor", "stdout"]),
+        crate::Subcommand::SetupEditor { editor: Editor::Stdout }
+    ));
+}
@@ -3,13 +3,16 @@
 //! Simple things like testing the various filesystem operations here and there,
 //! not a lot of interesting happenings here unfortunately.
 
+use std::cmp;
 use std::env;
+use std::ffi::OsString;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Child, Command};
 use std::str;
-use std::time::Instant;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use build_helper::t;
 
@@ -274,6 +277,125 @@ impl CiEnv {
     }
 }
 
+/// Returns whether stderr is a terminal, so that diagnostics printed by
+/// bootstrap itself can be colored like the ones Cargo prints.
+pub fn stderr_is_terminal() -> bool {
+    #[cfg(unix)]
+    unsafe {
+        libc::isatty(libc::STDERR_FILENO) == 1
+    }
+    #[cfg(windows)]
+    unsafe {
+        use winapi::um::consoleapi::{GetConsoleMode, SetConsoleMode};
+        use winapi::um::processenv::GetStdHandle;
+        use winapi::um::winbase::STD_ERROR_HANDLE;
+        use winapi::um::wincon::ENABLE_VIRTUAL_TERMINAL_PROCESSING;
+
+        // The diagnostics are rendered with ANSI escapes, which the console
+        // only understands with virtual terminal processing turned on.
+        let handle = GetStdHandle(STD_ERROR_HANDLE);
+        let mut mode = 0;
+        GetConsoleMode(handle, &mut mode) != 0
+            && (mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+                || SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0)
+    }
+    #[cfg(not(any(unix, windows)))]
+    false
+}
+
+/// A child process along with everything it spawns, which can be killed as a
+/// whole: a process group on Unix and a job object on Windows.
+pub struct ProcessTree {
+    #[cfg(windows)]
+    job: winapi::um::winnt::HANDLE,
+}
+
+impl ProcessTree {
+    /// Makes the process `cmd` spawns the leader of a new process group on
+    /// Unix. This also takes it out of the terminal's foreground group, so
+    /// Ctrl-C no longer reaches it directly.
+    pub fn prepare(cmd: &mut Command) {
+        #[cfg(unix)]
+        unsafe {
+            use std::os::unix::process::CommandExt;
+            cmd.pre_exec(|| {
+                if libc::setpgid(0, 0) == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+            });
+        }
+        #[cfg(not(unix))]
+        let _ = cmd;
+    }
+
+    /// Tracks the tree of `child`, which was spawned from a command passed to
+    /// `prepare`.
+    pub fn new(child: &Child) -> ProcessTree {
+        #[cfg(windows)]
+        unsafe {
+            use std::os::windows::io::AsRawHandle;
+            use std::ptr;
+
+            use winapi::um::jobapi2::{AssignProcessToJobObject, CreateJobObjectW};
+
+            let job = CreateJobObjectW(ptr::null_mut(), ptr::null());
+            assert!(!job.is_null(), "{}", io::Error::last_os_error());
+            // Processes the child starts from now on are in the job as well.
+            // Before Windows 8 this fails if bootstrap itself is in a job, and
+            // `kill` can then only kill the child.
+            AssignProcessToJobObject(job, child.as_raw_handle() as _);
+            ProcessTree { job }
+        }
+        #[cfg(not(windows))]
+        {
+            let _ = child;
+            ProcessTree {}
+        }
+    }
+
+    /// Kills `child` and all the processes it started.
+    pub fn kill(&self, child: &mut Child) {
+        #[cfg(unix)]
+        unsafe {
+            libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+        }
+        #[cfg(windows)]
+        unsafe {
+            winapi::um::jobapi2::TerminateJobObject(self.job, 1);
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+#[cfg(windows)]
+impl Drop for ProcessTree {
+    fn drop(&mut self) {
+        unsafe {
+            winapi::um::handleapi::CloseHandle(self.job);
+        }
+    }
+}
+
+/// The arguments of a rustc invocation compiling the crate in `manifest_dir`,
+/// with its lints capped at `allow` if that crate isn't part of the source
+/// tree at `src`, like the vendored ones.
+pub fn cap_external_lints(args: &[OsString], manifest_dir: &Path, src: &Path) -> Vec<OsString> {
+    if manifest_dir.starts_with(src) && !manifest_dir.starts_with(src.join("vendor")) {
+        return args.to_vec();
+    }
+    let mut capped = Vec::with_capacity(args.len() + 2);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--cap-lints" {
+            args.next();
+        } else if !arg.to_str().map_or(false, |arg| arg.starts_with("--cap-lints=")) {
+            capped.push(arg.clone());
+        }
+    }
+    capped.push("--cap-lints".into());
+    capped.push("allow".into());
+    capped
+}
+
 pub fn forcing_clang_based_tests() -> bool {
     if let Some(var) = env::var_os("RUSTBUILD_FORCE_CLANG_BASED_TESTS") {
         match &var.to_string_lossy().to_lowercase()[..] {
@@ -302,3 +424,108 @@ pub fn use_host_linker(target: TargetSelection) -> bool {
         || target.contains("fortanix")
         || target.contains("fuchsia"))
 }
+
+/// Returns the Levenshtein distance between `a` and `b`, used to suggest
+/// alternatives for misspelled paths.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == cb { 0 } else { 1 };
+            cur[j + 1] = cmp::min(substitution, cmp::min(prev[j + 1], cur[j]) + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// Paths this long can't be used on Windows without the `\\?\` prefix, as
+/// the directories they name couldn't be created: `MAX_PATH` leaves room for
+/// an 8.3 file name in them.
+const WINDOWS_MAX_DIR_PATH: usize = 248;
+
+/// Returns `path` in a form the filesystem accepts however long it is: on
+/// Windows, the `\\?\` extended-length form once it's too long for the
+/// Win32 API, e.g. for the deeply nested outputs of a tool in a long build
+/// directory. Other paths are returned as is.
+pub fn long_path(path: &Path) -> PathBuf {
+    if !cfg!(windows) || path.as_os_str().len() < WINDOWS_MAX_DIR_PATH {
+        return path.to_path_buf();
+    }
+    match path.to_str().and_then(extended_length_path) {
+        Some(path) => PathBuf::from(path),
+        None => path.to_path_buf(),
+    }
+}
+
+/// The extended-length form of the absolute Windows path `path`, or `None` if
+/// it's relative or already in that form. The Win32 API doesn't normalize
+/// such paths, so `/` becomes `\` and `.` and `..` are resolved here.
+fn extended_length_path(path: &str) -> Option<String> {
+    if path.starts_with(r"\\?\") || path.starts_with(r"\\.\") {
+        return None;
+    }
+    let path = path.replace('/', "\\");
+    let (prefix, rest) = if let Some(unc) = path.strip_prefix(r"\\") {
+        let mut parts = unc.splitn(3, '\\');
+        let (server, share) = (parts.next()?, parts.next()?);
+        if server.is_empty() || share.is_empty() {
+            return None;
+        }
+        (format!(r"\\?\UNC\{}\{}", server, share), parts.next().unwrap_or(""))
+    } else {
+        let bytes = path.as_bytes();
+        if bytes.len() < 3 || !bytes[0].is_ascii_alphabetic() || &bytes[1..3] != b":\\" {
+            return None;
+        }
+        (format!(r"\\?\{}", &path[..2]), &path[3..])
+    };
+    let mut components: Vec<&str> = Vec::new();
+    for component in rest.split('\\') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    Some(format!("{}\\{}", prefix, components.join("\\")))
+}
+
+/// How long `retry_io` waits before each retry.
+const IO_RETRY_DELAYS_MS: &[u64] = &[50, 100, 200, 400, 800];
+
+/// Runs the filesystem operation `op`, trying it again a few times, with
+/// increasing delays, while it fails because another process has the file
+/// open, e.g. a virus scanner looking at an rmeta file that was just written.
+/// That only happens on Windows, where files in use can't be replaced.
+pub fn retry_io<T>(op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    retry_io_with(op, |err| cfg!(windows) && is_sharing_violation(err), thread::sleep)
+}
+
+fn retry_io_with<T>(
+    mut op: impl FnMut() -> io::Result<T>,
+    is_transient: impl Fn(&io::Error) -> bool,
+    mut sleep: impl FnMut(Duration),
+) -> io::Result<T> {
+    for &delay in IO_RETRY_DELAYS_MS {
+        match op() {
+            Err(ref err) if is_transient(err) => sleep(Duration::from_millis(delay)),
+            result => return result,
+        }
+    }
+    op()
+}
+
+/// Whether `err` is one of the errors Windows reports when a file is in use by
+/// another process: `ERROR_ACCESS_DENIED`, `ERROR_SHARING_VIOLATION` or
+/// `ERROR_LOCK_VIOLATION`.
+fn is_sharing_violation(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(5) | Some(32) | Some(33))
+}
+
+#[cfg(test)]
+mod tests;
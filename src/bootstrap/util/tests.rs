@@ -0,0 +1,119 @@
+use super::*;
+
+use std::process::Stdio;
+
+/// Checks `lib.rs` in `dir` the way the rustc shim would when checking the
+/// tree at `src`, and returns what rustc printed.
+fn check_with_capped_lints(src: &Path, dir: &Path) -> String {
+    let source = dir.join("lib.rs");
+    t!(fs::create_dir_all(dir));
+    t!(fs::write(&source, "pub fn f() {\n    let unused = 1;\n}\n"));
+    let args = vec![
+        OsString::from("--crate-type"),
+        "lib".into(),
+        "--cap-lints".into(),
+        "warn".into(),
+        "--emit=metadata".into(),
+        "--out-dir".into(),
+        dir.into(),
+        source.into(),
+    ];
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let output = t!(Command::new(rustc)
+        .args(cap_external_lints(&args, dir, src))
+        .stderr(Stdio::piped())
+        .output());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn lints_of_vendored_crates_are_capped() {
+    let src = env::temp_dir().join(format!("bootstrap-cap-lints-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&src);
+
+    let in_tree = check_with_capped_lints(&src, &src.join("compiler/rustc_span"));
+    assert!(in_tree.contains("unused variable"), "{}", in_tree);
+    let vendored = check_with_capped_lints(&src, &src.join("vendor/some-crate"));
+    assert_eq!(vendored, "");
+    let registry = check_with_capped_lints(&src, &src.with_extension("registry"));
+    assert_eq!(registry, "");
+
+    t!(fs::remove_dir_all(&src));
+    let _ = fs::remove_dir_all(src.with_extension("registry"));
+}
+
+#[test]
+fn cap_lints_is_replaced() {
+    let args = ["--cap-lints=warn", "--crate-name", "foo", "--cap-lints", "deny"];
+    let args = args.iter().map(OsString::from).collect::<Vec<_>>();
+    let capped = cap_external_lints(&args, Path::new("/cargo/foo"), Path::new("/rust"));
+    assert_eq!(capped, ["--crate-name", "foo", "--cap-lints", "allow"]);
+    let in_tree = cap_external_lints(&args, Path::new("/rust/library/std"), Path::new("/rust"));
+    assert_eq!(in_tree, args);
+}
+
+#[test]
+fn extended_length_paths() {
+    let extended = |path| extended_length_path(path);
+    assert_eq!(extended(r"C:\build\x86_64\stage0").as_deref(), Some(r"\\?\C:\build\x86_64\stage0"));
+    assert_eq!(extended(r"c:/build/./tools/../stage0").as_deref(), Some(r"\\?\c:\build\stage0"));
+    assert_eq!(extended(r"C:\").as_deref(), Some(r"\\?\C:\"));
+    assert_eq!(extended(r"\\server\share\build").as_deref(), Some(r"\\?\UNC\server\share\build"));
+    assert_eq!(extended(r"\\?\C:\build"), None);
+    assert_eq!(extended(r"\\server"), None);
+    assert_eq!(extended(r"build\stage0"), None);
+    assert_eq!(extended(r"C:build"), None);
+    assert_eq!(extended("/build"), None);
+}
+
+#[test]
+fn io_retried_while_file_in_use() {
+    let in_use = || io::Error::from_raw_os_error(32);
+    let transient = |err: &io::Error| err.raw_os_error() == Some(32);
+
+    let mut failures = 2;
+    let mut delays = Vec::new();
+    let result = retry_io_with(
+        || {
+            if failures > 0 {
+                failures -= 1;
+                Err(in_use())
+            } else {
+                Ok("copied")
+            }
+        },
+        transient,
+        |delay| delays.push(delay.as_millis()),
+    );
+    assert_eq!(result.unwrap(), "copied");
+    assert_eq!(delays, [50, 100]);
+
+    // Other errors aren't retried, and the last attempt's error is returned.
+    let mut attempts = 0;
+    let result: io::Result<()> = retry_io_with(
+        || {
+            attempts += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        },
+        transient,
+        |_| panic!("a missing file was retried"),
+    );
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(attempts, 1);
+
+    let mut attempts = 0;
+    let result: io::Result<()> = retry_io_with(
+        || {
+            attempts += 1;
+            Err(in_use())
+        },
+        transient,
+        |_| {},
+    );
+    assert_eq!(result.unwrap_err().raw_os_error(), Some(32));
+    assert_eq!(attempts, IO_RETRY_DELAYS_MS.len() + 1);
+
+    assert!(is_sharing_violation(&io::Error::from_raw_os_error(33)));
+    assert!(!is_sharing_violation(&io::Error::from(io::ErrorKind::NotFound)));
+}
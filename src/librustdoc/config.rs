@@ -113,6 +113,8 @@ pub struct Options {
     pub runtool: Option<String>,
     /// Arguments to pass to the runtool
     pub runtool_args: Vec<String>,
+    /// Whether doctests are only compiled, as if they were all marked `no_run`
+    pub no_run: bool,
     /// Whether to allow ignoring doctests on a per-target basis
     /// For example, using ignore-foo to ignore running the doctest on any target that
     /// contains "foo" as a substring
@@ -184,6 +186,7 @@ impl fmt::Debug for Options {
             .field("render_options", &self.render_options)
             .field("runtool", &self.runtool)
             .field("runtool_args", &self.runtool_args)
+            .field("no_run", &self.no_run)
             .field("enable-per-target-ignores", &self.enable_per_target_ignores)
             .finish()
     }
@@ -379,6 +382,12 @@ impl Options {
             test_args.iter().flat_map(|s| s.split_whitespace()).map(|s| s.to_string()).collect();
 
         let should_test = matches.opt_present("test");
+        let no_run = matches.opt_present("no-run");
+
+        if !should_test && no_run {
+            diag.struct_err("the `--test` flag must be passed to enable `--no-run`").emit();
+            return Err(1);
+        }
 
         let output =
             matches.opt_str("o").map(|s| PathBuf::from(&s)).unwrap_or_else(|| PathBuf::from("doc"));
@@ -585,6 +594,7 @@ impl Options {
             persist_doctests,
             runtool,
             runtool_args,
+            no_run,
             enable_per_target_ignores,
             test_builder,
             render_options: RenderOptions {
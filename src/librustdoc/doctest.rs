@@ -716,6 +716,7 @@ impl Tester for Collector {
         let options = self.options.clone();
         let runtool = self.options.runtool.clone();
         let runtool_args = self.options.runtool_args.clone();
+        let no_run = config.no_run || options.no_run;
         let target = self.options.target.clone();
         let target_str = target.to_string();
 
@@ -782,7 +783,7 @@ impl Tester for Collector {
                     line,
                     options,
                     config.should_panic,
-                    config.no_run,
+                    no_run,
                     config.test_harness,
                     runtool,
                     runtool_args,
@@ -396,6 +396,9 @@ fn opts() -> Vec<RustcOptGroup> {
                 "One (of possibly many) arguments to pass to the runtool",
             )
         }),
+        unstable("no-run", |o| {
+            o.optflag("", "no-run", "Compile doctests without running them")
+        }),
         unstable("test-builder", |o| {
             o.optflag(
                 "",